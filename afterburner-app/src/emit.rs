@@ -1,21 +1,428 @@
-pub struct MockTransaction {
-    pub signature: [u8; 64],
-    pub message: [u8; 170],
+use std::fmt;
+
+/// Largest serialized transaction a validator accepts (IPv6 minimum MTU minus headers).
+pub const PACKET_DATA_SIZE: usize = 1232;
+/// Marks a versioned message; the low bits carry the version.
+const MESSAGE_VERSION_PREFIX: u8 = 0x80;
+
+pub type Pubkey = [u8; 32];
+pub type Hash = [u8; 32];
+pub type Signature = [u8; 64];
+
+pub const SYSTEM_PROGRAM_ID: Pubkey = [0; 32];
+// SystemInstruction::Transfer, as a little-endian u32 enum tag
+const SYSTEM_TRANSFER: u32 = 2;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum WireError {
+    /// Input ended early, or the output buffer is too small
+    Truncated,
+    /// Serialized size above `PACKET_DATA_SIZE`
+    TooLarge(usize),
+    /// Overlong, non-canonical or out-of-range compact-u16
+    BadCompactU16,
+    UnknownVersion(u8),
+    /// Signature count doesn't match the header's required signatures
+    SignatureCount { signatures: usize, required: u8 },
+    /// Legacy messages can't carry address table lookups
+    LookupsInLegacy,
+    /// A list longer than a u8 index or compact-u16 length can address
+    TooMany(&'static str),
+    TrailingBytes(usize),
+}
+
+impl fmt::Display for WireError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            WireError::Truncated => write!(f, "truncated"),
+            WireError::TooLarge(n) => write!(f, "{} bytes, over the {}-byte packet limit", n, PACKET_DATA_SIZE),
+            WireError::BadCompactU16 => write!(f, "invalid compact-u16"),
+            WireError::UnknownVersion(v) => write!(f, "unknown message version {}", v),
+            WireError::SignatureCount { signatures, required } => {
+                write!(f, "{} signatures for {} required signers", signatures, required)
+            }
+            WireError::LookupsInLegacy => write!(f, "address table lookups in a legacy message"),
+            WireError::TooMany(what) => write!(f, "too many {}", what),
+            WireError::TrailingBytes(n) => write!(f, "{} trailing bytes", n),
+        }
+    }
+}
+
+/// Bounds-checked writer over a caller's buffer, so the TX path doesn't allocate.
+struct Writer<'a> {
+    buf: &'a mut [u8],
+    pos: usize,
+}
+
+impl Writer<'_> {
+    fn bytes(&mut self, b: &[u8]) -> Result<(), WireError> {
+        let end = self.pos + b.len();
+        self.buf.get_mut(self.pos..end).ok_or(WireError::Truncated)?.copy_from_slice(b);
+        self.pos = end;
+        Ok(())
+    }
+
+    fn u8(&mut self, v: u8) -> Result<(), WireError> {
+        self.bytes(&[v])
+    }
+
+    fn compact_len(&mut self, len: usize, what: &'static str) -> Result<(), WireError> {
+        let len = u16::try_from(len).map_err(|_| WireError::TooMany(what))?;
+        let mut tmp = [0; 3];
+        let n = encode_compact_u16(len, &mut tmp);
+        self.bytes(&tmp[..n])
+    }
+
+    /// compact-u16 length followed by the bytes.
+    fn short_vec(&mut self, b: &[u8], what: &'static str) -> Result<(), WireError> {
+        self.compact_len(b.len(), what)?;
+        self.bytes(b)
+    }
+}
+
+struct Reader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn bytes(&mut self, n: usize) -> Result<&'a [u8], WireError> {
+        let b = self.data.get(self.pos..self.pos + n).ok_or(WireError::Truncated)?;
+        self.pos += n;
+        Ok(b)
+    }
+
+    fn u8(&mut self) -> Result<u8, WireError> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    fn array<const N: usize>(&mut self) -> Result<[u8; N], WireError> {
+        Ok(self.bytes(N)?.try_into().unwrap())
+    }
+
+    fn compact_len(&mut self) -> Result<usize, WireError> {
+        let (v, n) = decode_compact_u16(&self.data[self.pos..])?;
+        self.pos += n;
+        Ok(v as usize)
+    }
+
+    fn short_vec(&mut self) -> Result<Vec<u8>, WireError> {
+        let n = self.compact_len()?;
+        Ok(self.bytes(n)?.to_vec())
+    }
+}
+
+/// Solana's "shortvec" length: 7 bits per byte, little-endian, at most 3 bytes.
+/// Returns the number of bytes written.
+pub fn encode_compact_u16(mut v: u16, out: &mut [u8; 3]) -> usize {
+    let mut n = 0;
+    loop {
+        let byte = (v & 0x7f) as u8;
+        v >>= 7;
+        if v == 0 {
+            out[n] = byte;
+            return n + 1;
+        }
+        out[n] = byte | 0x80;
+        n += 1;
+    }
+}
+
+/// Inverse of `encode_compact_u16`, rejecting aliases (trailing zero groups) like agave does.
+pub fn decode_compact_u16(data: &[u8]) -> Result<(u16, usize), WireError> {
+    let mut v: u32 = 0;
+    for i in 0..3 {
+        let byte = *data.get(i).ok_or(WireError::Truncated)?;
+        if i > 0 && byte == 0 {
+            return Err(WireError::BadCompactU16);
+        }
+        v |= ((byte & 0x7f) as u32) << (7 * i);
+        if byte & 0x80 == 0 {
+            return u16::try_from(v).map(|v| (v, i + 1)).map_err(|_| WireError::BadCompactU16);
+        }
+    }
+    Err(WireError::BadCompactU16)
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct MessageHeader {
+    pub num_required_signatures: u8,
+    pub num_readonly_signed_accounts: u8,
+    pub num_readonly_unsigned_accounts: u8,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CompiledInstruction {
+    /// Index into the message's account keys
+    pub program_id_index: u8,
+    pub accounts: Vec<u8>,
+    pub data: Vec<u8>,
+}
+
+/// Accounts a v0 message loads from an on-chain address lookup table.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct AddressTableLookup {
+    pub account_key: Pubkey,
+    pub writable_indexes: Vec<u8>,
+    pub readonly_indexes: Vec<u8>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MessageVersion {
+    Legacy,
+    V0,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Message {
+    pub version: MessageVersion,
+    pub header: MessageHeader,
+    pub account_keys: Vec<Pubkey>,
+    pub recent_blockhash: Hash,
+    pub instructions: Vec<CompiledInstruction>,
+    /// v0 only
+    pub address_table_lookups: Vec<AddressTableLookup>,
+}
+
+impl Message {
+    /// Legacy message moving `lamports` from `from` (the fee payer) to `to`.
+    pub fn transfer(from: Pubkey, to: Pubkey, lamports: u64, recent_blockhash: Hash) -> Self {
+        let mut data = SYSTEM_TRANSFER.to_le_bytes().to_vec();
+        data.extend_from_slice(&lamports.to_le_bytes());
+        Message {
+            version: MessageVersion::Legacy,
+            header: MessageHeader { num_required_signatures: 1, num_readonly_signed_accounts: 0, num_readonly_unsigned_accounts: 1 },
+            account_keys: vec![from, to, SYSTEM_PROGRAM_ID],
+            recent_blockhash,
+            instructions: vec![CompiledInstruction { program_id_index: 2, accounts: vec![0, 1], data }],
+            address_table_lookups: Vec::new(),
+        }
+    }
+
+    /// The signed bytes. Returns the length written to `buf`.
+    pub fn serialize(&self, buf: &mut [u8]) -> Result<usize, WireError> {
+        let mut w = Writer { buf, pos: 0 };
+        self.write(&mut w)?;
+        Ok(w.pos)
+    }
+
+    fn write(&self, w: &mut Writer) -> Result<(), WireError> {
+        match self.version {
+            MessageVersion::Legacy if !self.address_table_lookups.is_empty() => return Err(WireError::LookupsInLegacy),
+            MessageVersion::Legacy => {}
+            MessageVersion::V0 => w.u8(MESSAGE_VERSION_PREFIX)?,
+        }
+        let h = &self.header;
+        w.bytes(&[h.num_required_signatures, h.num_readonly_signed_accounts, h.num_readonly_unsigned_accounts])?;
+
+        // Instructions address accounts with a u8
+        if self.account_keys.len() > 256 {
+            return Err(WireError::TooMany("account keys"));
+        }
+        w.compact_len(self.account_keys.len(), "account keys")?;
+        for key in &self.account_keys {
+            w.bytes(key)?;
+        }
+        w.bytes(&self.recent_blockhash)?;
+
+        w.compact_len(self.instructions.len(), "instructions")?;
+        for ix in &self.instructions {
+            w.u8(ix.program_id_index)?;
+            w.short_vec(&ix.accounts, "instruction accounts")?;
+            w.short_vec(&ix.data, "instruction data")?;
+        }
+
+        if self.version == MessageVersion::V0 {
+            w.compact_len(self.address_table_lookups.len(), "address table lookups")?;
+            for lookup in &self.address_table_lookups {
+                w.bytes(&lookup.account_key)?;
+                w.short_vec(&lookup.writable_indexes, "writable indexes")?;
+                w.short_vec(&lookup.readonly_indexes, "readonly indexes")?;
+            }
+        }
+        Ok(())
+    }
+
+    fn read(r: &mut Reader) -> Result<Self, WireError> {
+        let first = r.u8()?;
+        let (version, num_required_signatures) = if first & MESSAGE_VERSION_PREFIX == 0 {
+            (MessageVersion::Legacy, first)
+        } else {
+            match first & !MESSAGE_VERSION_PREFIX {
+                0 => (MessageVersion::V0, r.u8()?),
+                v => return Err(WireError::UnknownVersion(v)),
+            }
+        };
+        let header = MessageHeader {
+            num_required_signatures,
+            num_readonly_signed_accounts: r.u8()?,
+            num_readonly_unsigned_accounts: r.u8()?,
+        };
+
+        let account_keys = (0..r.compact_len()?).map(|_| r.array()).collect::<Result<_, _>>()?;
+        let recent_blockhash = r.array()?;
+        let instructions = (0..r.compact_len()?)
+            .map(|_| Ok(CompiledInstruction { program_id_index: r.u8()?, accounts: r.short_vec()?, data: r.short_vec()? }))
+            .collect::<Result<_, _>>()?;
+        let address_table_lookups = match version {
+            MessageVersion::Legacy => Vec::new(),
+            MessageVersion::V0 => (0..r.compact_len()?)
+                .map(|_| {
+                    Ok(AddressTableLookup { account_key: r.array()?, writable_indexes: r.short_vec()?, readonly_indexes: r.short_vec()? })
+                })
+                .collect::<Result<_, _>>()?,
+        };
+
+        Ok(Message { version, header, account_keys, recent_blockhash, instructions, address_table_lookups })
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Transaction {
+    /// One per required signer, in account key order
+    pub signatures: Vec<Signature>,
+    pub message: Message,
+}
+
+impl Transaction {
+    /// Unsigned: every signature zeroed.
+    pub fn new(message: Message) -> Self {
+        let signatures = vec![[0; 64]; message.header.num_required_signatures as usize];
+        Transaction { signatures, message }
+    }
+
+    /// Wire format: compact-u16 signature count, signatures, message. Returns the length
+    /// written to `buf`; fails past `PACKET_DATA_SIZE` even if `buf` is larger.
+    pub fn serialize(&self, buf: &mut [u8]) -> Result<usize, WireError> {
+        let required = self.message.header.num_required_signatures;
+        if self.signatures.len() != required as usize {
+            return Err(WireError::SignatureCount { signatures: self.signatures.len(), required });
+        }
+
+        let mut w = Writer { buf, pos: 0 };
+        w.compact_len(self.signatures.len(), "signatures")?;
+        for sig in &self.signatures {
+            w.bytes(sig)?;
+        }
+        self.message.write(&mut w)?;
+        if w.pos > PACKET_DATA_SIZE {
+            return Err(WireError::TooLarge(w.pos));
+        }
+        Ok(w.pos)
+    }
+
+    /// Offset of the message in a serialized transaction with `signatures` signatures;
+    /// the signed bytes run from there to the end.
+    #[cfg(test)]
+    pub fn message_offset(signatures: usize) -> usize {
+        let mut tmp = [0; 3];
+        encode_compact_u16(signatures as u16, &mut tmp) + signatures * 64
+    }
+
+    pub fn deserialize(data: &[u8]) -> Result<Self, WireError> {
+        if data.len() > PACKET_DATA_SIZE {
+            return Err(WireError::TooLarge(data.len()));
+        }
+        let mut r = Reader { data, pos: 0 };
+        let signatures: Vec<Signature> = (0..r.compact_len()?).map(|_| r.array()).collect::<Result<_, _>>()?;
+        let message = Message::read(&mut r)?;
+        if r.pos != data.len() {
+            return Err(WireError::TrailingBytes(data.len() - r.pos));
+        }
+        let required = message.header.num_required_signatures;
+        if signatures.len() != required as usize {
+            return Err(WireError::SignatureCount { signatures: signatures.len(), required });
+        }
+        Ok(Transaction { signatures, message })
+    }
 }
 
-impl MockTransaction {
-    pub fn new() -> Self {
-        MockTransaction {
-            signature: [0xAA; 64],
-            message: [0xBB; 170],
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn round_trip(tx: &Transaction) -> Vec<u8> {
+        let mut buf = [0; PACKET_DATA_SIZE];
+        let len = tx.serialize(&mut buf).unwrap();
+        assert_eq!(&Transaction::deserialize(&buf[..len]).unwrap(), tx);
+        buf[..len].to_vec()
+    }
+
+    #[test]
+    fn compact_u16_encoding() {
+        let cases: [(u16, &[u8]); 7] = [
+            (0, &[0x00]),
+            (0x7f, &[0x7f]),
+            (0x80, &[0x80, 0x01]),
+            (0xff, &[0xff, 0x01]),
+            (0x3fff, &[0xff, 0x7f]),
+            (0x4000, &[0x80, 0x80, 0x01]),
+            (0xffff, &[0xff, 0xff, 0x03]),
+        ];
+        for (v, bytes) in cases {
+            let mut out = [0; 3];
+            let n = encode_compact_u16(v, &mut out);
+            assert_eq!(&out[..n], bytes, "encode {:#x}", v);
+            assert_eq!(decode_compact_u16(bytes), Ok((v, bytes.len())), "decode {:#x}", v);
+        }
+
+        // Aliases, overflow past u16, a fourth byte, and running out of input
+        for bad in [&[0x80, 0x00][..], &[0x80, 0x80, 0x00], &[0xff, 0xff, 0x04], &[0x80, 0x80, 0x80, 0x01]] {
+            assert_eq!(decode_compact_u16(bad), Err(WireError::BadCompactU16), "{:02x?}", bad);
         }
+        assert_eq!(decode_compact_u16(&[0x80]), Err(WireError::Truncated));
     }
 
-    /// Serialize to wire format: [Flag(1)] [Signature(64)] [Message(170)] = 235 bytes
-    pub fn serialize(&self, buf: &mut [u8]) -> usize {
-        buf[0] = 0x00;
-        buf[1..65].copy_from_slice(&self.signature);
-        buf[65..235].copy_from_slice(&self.message);
-        235
+    #[test]
+    fn legacy_transfer_layout() {
+        let tx = Transaction::new(Message::transfer([1; 32], [2; 32], 5000, [9; 32]));
+        let bytes = round_trip(&tx);
+
+        // 1 signature, header, 3 keys, blockhash, 1 instruction (program 2, accounts [0, 1], 12 bytes of data)
+        assert_eq!(bytes.len(), 1 + 64 + 3 + 1 + 3 * 32 + 32 + 1 + 1 + 3 + 13);
+        assert_eq!(&bytes[..1], &[1]);
+        assert_eq!(&bytes[65..69], &[1, 0, 1, 3]);
+        assert_eq!(Transaction::message_offset(1), 65);
+        assert_eq!(&bytes[bytes.len() - 12..], &[2, 0, 0, 0, 0x88, 0x13, 0, 0, 0, 0, 0, 0]);
+    }
+
+    #[test]
+    fn v0_with_lookups_round_trips() {
+        let mut message = Message::transfer([1; 32], [2; 32], 1, [9; 32]);
+        message.version = MessageVersion::V0;
+        message.instructions[0].accounts.push(3);
+        message.address_table_lookups = vec![
+            AddressTableLookup { account_key: [7; 32], writable_indexes: vec![0, 5], readonly_indexes: vec![] },
+            AddressTableLookup { account_key: [8; 32], writable_indexes: vec![], readonly_indexes: vec![1; 200] },
+        ];
+        let tx = Transaction { signatures: vec![[0x5a; 64]], message };
+        let bytes = round_trip(&tx);
+        assert_eq!(bytes[65], MESSAGE_VERSION_PREFIX);
+
+        let mut legacy = tx.message.clone();
+        legacy.version = MessageVersion::Legacy;
+        assert_eq!(legacy.serialize(&mut [0; PACKET_DATA_SIZE]), Err(WireError::LookupsInLegacy));
+    }
+
+    #[test]
+    fn rejects_malformed() {
+        let tx = Transaction::new(Message::transfer([1; 32], [2; 32], 1, [0; 32]));
+        let bytes = round_trip(&tx);
+
+        assert_eq!(Transaction::deserialize(&bytes[..bytes.len() - 1]), Err(WireError::Truncated));
+        assert_eq!(Transaction::deserialize(&[&bytes[..], &[0]].concat()), Err(WireError::TrailingBytes(1)));
+
+        let mut versioned = bytes.clone();
+        versioned[65] = MESSAGE_VERSION_PREFIX | 1;
+        assert_eq!(Transaction::deserialize(&versioned), Err(WireError::UnknownVersion(1)));
+
+        let mut unsigned = tx.clone();
+        unsigned.signatures.clear();
+        assert!(matches!(unsigned.serialize(&mut [0; PACKET_DATA_SIZE]), Err(WireError::SignatureCount { .. })));
+        assert_eq!(tx.serialize(&mut [0; 64]), Err(WireError::Truncated));
+
+        let mut big = tx;
+        big.message.instructions[0].data = vec![0; PACKET_DATA_SIZE];
+        assert_eq!(big.serialize(&mut [0; 4096]), Err(WireError::TooLarge(bytes.len() + PACKET_DATA_SIZE - 12 + 1)));
     }
 }
@@ -0,0 +1,203 @@
+use std::collections::HashMap;
+use std::fs;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::pin::Pin;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Duration;
+use crate::emit::{Message, Transaction, PACKET_DATA_SIZE};
+use crate::flood;
+use crate::loopback::{self, Clock, LinkConfig, Loopback};
+use crate::packet_io::{PacketIo, TxPacket};
+use crate::quic_driver::ClientTls;
+use crate::worker::Worker;
+
+pub const TICK: Duration = Duration::from_micros(100);
+// The flooder's legacy transfer
+pub const TX_LEN: usize = 215;
+/// What most tests run on: 1ms each way, no impairments.
+pub const LINK_1MS: LinkConfig = LinkConfig { latency: Duration::from_millis(1), loss: 0.0, reorder: 0.0, mtu: 1500, seed: 1 };
+
+/// The client's address.
+pub fn local() -> SocketAddr {
+    "10.0.0.10:8000".parse().unwrap()
+}
+
+/// Where the `i`th server listens.
+pub fn peer(i: usize) -> SocketAddr {
+    SocketAddr::new([10, 0, 0, 11 + i as u8].into(), 8004)
+}
+
+/// Fresh directory under the temp dir, removed with its contents on drop, so a failed assert
+/// doesn't leave it behind.
+pub struct TempDir(PathBuf);
+
+impl TempDir {
+    pub fn new(name: &str) -> Self {
+        static NEXT: AtomicUsize = AtomicUsize::new(0);
+        let n = NEXT.fetch_add(1, Ordering::Relaxed);
+        let path = std::env::temp_dir().join(format!("afterburner-{}-{}-{}", name, std::process::id(), n));
+        fs::create_dir_all(&path).unwrap();
+        TempDir(path)
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.0
+    }
+
+    pub fn join(&self, name: &str) -> PathBuf {
+        self.0.join(name)
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.0);
+    }
+}
+
+/// Minimal quiche server on the far end of the loopback, counting the bytes it reads. A
+/// worker floods one or more of these in the end-to-end tests (see `setup`).
+pub struct TestServer {
+    pub io: Loopback,
+    config: quiche::Config,
+    pub conn: Option<Pin<Box<quiche::Connection>>>,
+    pub local: SocketAddr,
+    peer: SocketAddr,
+    buf: Vec<u8>,
+    pub rx_bytes: usize,
+    // Stream IDs that ended with FIN, and how many bytes each carried
+    pub finished: Vec<(u64, usize)>,
+    stream_bytes: HashMap<u64, usize>,
+}
+
+impl TestServer {
+    pub fn new(io: Loopback) -> Self {
+        let cert = rcgen::generate_simple_self_signed(vec!["localhost".to_string()]).unwrap();
+        Self::with_cert(io, &cert.cert.pem(), &cert.key_pair.serialize_pem())
+    }
+
+    /// Presents a self-signed ed25519 certificate for `key`, the way validators do.
+    pub fn with_identity(io: Loopback, key: &rcgen::KeyPair) -> Self {
+        let cert = rcgen::CertificateParams::new(vec!["localhost".to_string()]).unwrap().self_signed(key).unwrap();
+        Self::with_cert(io, &cert.pem(), &key.serialize_pem())
+    }
+
+    fn with_cert(io: Loopback, cert_pem: &str, key_pem: &str) -> Self {
+        let dir = TempDir::new("server");
+        let (cert_path, key_path) = (dir.join("cert.crt"), dir.join("cert.key"));
+        fs::write(&cert_path, cert_pem).unwrap();
+        fs::write(&key_path, key_pem).unwrap();
+
+        let mut config = quiche::Config::new(quiche::PROTOCOL_VERSION).unwrap();
+        config.set_application_protos(&[b"solana-tpu"]).unwrap();
+        config.load_cert_chain_from_pem_file(cert_path.to_str().unwrap()).unwrap();
+        config.load_priv_key_from_pem_file(key_path.to_str().unwrap()).unwrap();
+        config.set_initial_max_data(100_000_000);
+        config.set_initial_max_stream_data_uni(10_000_000);
+        config.set_initial_max_streams_uni(1000);
+        config.set_max_ack_delay(0);
+        config.set_ack_delay_exponent(0);
+        config.enable_early_data();
+
+        TestServer {
+            io, config, conn: None, local: peer(0), peer: local(), buf: vec![0; 65535], rx_bytes: 0,
+            finished: Vec::new(), stream_bytes: Default::default(),
+        }
+    }
+
+    /// Grant at most `n` concurrent uni streams (applies to the next connection).
+    pub fn limit_streams(mut self, n: u64) -> Self {
+        self.config.set_initial_max_streams_uni(n);
+        self
+    }
+
+    /// Serve a new client over `io`. The TLS context, and so the session ticket key, stays.
+    pub fn restart(&mut self, io: Loopback) {
+        self.io = io;
+        self.conn = None;
+        self.rx_bytes = 0;
+        self.finished.clear();
+        self.stream_bytes.clear();
+    }
+
+    pub fn conn(&mut self) -> &mut quiche::Connection {
+        self.conn.as_mut().expect("no connection yet")
+    }
+
+    pub fn step(&mut self) {
+        while let Some((from, payload)) = self.io.poll_rx() {
+            if self.conn.is_none() {
+                let Ok(hdr) = quiche::Header::from_slice(payload, quiche::MAX_CONN_ID_LEN) else { continue };
+                if hdr.ty != quiche::Type::Initial { continue; }
+                let scid = quiche::ConnectionId::from_ref(&[0xAA; 16]);
+                let conn = quiche::accept(&scid, None, self.local, self.peer, &mut self.config).unwrap();
+                self.conn = Some(Box::pin(conn));
+            }
+            let info = quiche::RecvInfo { from, to: self.local };
+            let _ = self.conn.as_mut().unwrap().recv(payload, info);
+        }
+
+        let Some(conn) = self.conn.as_mut() else { return };
+        conn.on_timeout();
+        for stream_id in conn.readable() {
+            while let Ok((len, fin)) = conn.stream_recv(stream_id, &mut self.buf) {
+                self.rx_bytes += len;
+                *self.stream_bytes.entry(stream_id).or_default() += len;
+                if fin {
+                    self.finished.push((stream_id, self.stream_bytes.remove(&stream_id).unwrap()));
+                }
+            }
+        }
+        while let Some(mut frame) = self.io.get_tx_frame() {
+            match conn.send(&mut frame) {
+                Ok((len, _)) => frame.submit(len, self.local, self.peer),
+                Err(_) => break,
+            }
+        }
+        self.io.tx_submit();
+    }
+}
+
+/// A worker on one client socket, joined over `link` to one server per entry in `servers`,
+/// the `i`th serving at `peer(i)`.
+pub fn setup(link: LinkConfig, tls: &ClientTls, servers: &[&dyn Fn(Loopback) -> TestServer]) -> (Worker<Loopback>, Vec<TestServer>, Clock) {
+    let clock = Clock::default();
+    let peers: Vec<_> = (0..servers.len()).map(peer).collect();
+    let (hub, spokes) = loopback::star(link, &clock, &peers);
+    let servers = spokes.into_iter().zip(servers).zip(&peers).map(|((io, server), at)| TestServer { local: *at, ..server(io) }).collect();
+    (Worker::new(0, vec![hub], local(), &peers, tls), servers, clock)
+}
+
+/// The worker's first connection.
+pub fn client_conn(client: &mut Worker<Loopback>) -> &mut quiche::Connection {
+    &mut client.driver().connections_mut()[0].conn
+}
+
+pub fn pump(client: &mut Worker<Loopback>, servers: &mut [TestServer], clock: &Clock, ticks: usize) {
+    for _ in 0..ticks {
+        client.poll_once();
+        for server in servers.iter_mut() {
+            server.step();
+        }
+        clock.advance(TICK);
+    }
+}
+
+/// Pump until every handshake has completed on both ends.
+pub fn establish(client: &mut Worker<Loopback>, servers: &mut [TestServer], clock: &Clock) {
+    for _ in 0..1000 {
+        pump(client, servers, clock, 1);
+        let clients = client.driver().connections_mut().iter().all(|c| c.conn.is_established());
+        if clients && servers.iter().all(|s| s.conn.as_ref().is_some_and(|c| c.is_established())) { return; }
+    }
+    panic!("handshakes did not complete");
+}
+
+/// Wire format of a distinct transfer, the size the flooder's own placeholder is.
+pub fn transfer(lamports: u64) -> Vec<u8> {
+    let mut buf = [0u8; PACKET_DATA_SIZE];
+    let tx = Transaction::new(Message::transfer([0xAA; 32], flood::RECIPIENT, lamports, [0; 32]));
+    let len = tx.serialize(&mut buf).unwrap();
+    buf[..len].to_vec()
+}
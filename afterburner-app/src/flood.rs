@@ -1,42 +1,155 @@
-use crate::quic_driver::QuicDriver;
-use crate::emit::MockTransaction;
+use crate::quic_driver::{Connection, QuicDriver, TxOutcome};
+use std::ops::Range;
+use std::sync::Arc;
+use std::time::Instant;
+use crate::emit::{Message, Pubkey, Transaction, PACKET_DATA_SIZE};
+use crate::pacer::{PaceConfig, Pacer};
+use crate::spsc::Consumer;
 
+/// Where flood transfers send their lamports.
+pub const RECIPIENT: Pubkey = [0xBB; 32];
+
+enum Feed {
+    /// The unsigned placeholder, resent forever
+    Repeat(Vec<u8>),
+    /// Serialized, already signed; `txs[next..end]` are still to go, each once. Sending one
+    /// again would only be dropped as a duplicate.
+    Batch { txs: Arc<[Vec<u8>]>, next: usize, end: usize },
+    /// Built elsewhere (see `ingest.rs`); each sent once, to every connection ready for it.
+    /// Best effort per peer: a connection that isn't is passed over, and counts it as skipped
+    Ingest(Consumer<Vec<u8>>),
+    /// The batch is used up
+    Spent,
+}
+
+/// Hands transactions to connections; `QuicDriver::tx_count` says how many made it onto a stream.
 pub struct Flooder {
-    pub tx_count: u64,
-    batch_size: usize,
-    mock_tx: MockTransaction,
-    buf: [u8; 1024],
+    pacer: Pacer,
+    feed: Feed,
+}
+
+fn can_send(c: &Connection) -> bool {
+    c.is_ready() && !c.is_backlogged()
+}
+
+/// Hand out up to `budget` transactions, `tx(i)` being the i-th, one per connection per round
+/// so the connections share the budget evenly. Returns how many were taken, including ones
+/// waiting for stream credit.
+fn spread<'a>(driver: &mut QuicDriver, budget: usize, tx: impl Fn(usize) -> &'a [u8]) -> usize {
+    let mut sent = 0;
+    while sent < budget {
+        let mut progress = false;
+        for c in driver.connections_mut().iter_mut() {
+            if sent == budget {
+                break;
+            }
+            if can_send(c) && c.send_tx(tx(sent)) != TxOutcome::Dropped {
+                sent += 1;
+                progress = true;
+            }
+        }
+        if !progress {
+            break;
+        }
+    }
+    sent
 }
 
 impl Flooder {
+    /// Floods a single unsigned 1-lamport transfer, unpaced.
     pub fn new() -> Self {
+        let mut buf = [0u8; PACKET_DATA_SIZE];
+        let tx = Transaction::new(Message::transfer([0xAA; 32], RECIPIENT, 1, [0; 32]));
+        let len = tx.serialize(&mut buf).expect("Transaction::serialize");
         Flooder {
-            tx_count: 0,
-            batch_size: 4,
-            mock_tx: MockTransaction::new(),
-            buf: [0u8; 1024],
+            pacer: Pacer::new(PaceConfig::default(), Instant::now()),
+            feed: Feed::Repeat(buf[..len].to_vec()),
         }
     }
 
-    pub fn shoot(&mut self, driver: &mut QuicDriver) {
-        if !driver.conn.is_established() {
-            return;
-        }
+    /// Floods `txs[range]` once each, so workers sharing a batch take disjoint slices of it.
+    pub fn with_transactions(mut self, txs: Arc<[Vec<u8>]>, range: Range<usize>) -> Self {
+        assert!(range.end <= txs.len(), "slice {:?} out of {} transactions", range, txs.len());
+        self.feed = Feed::Batch { next: range.start, end: range.end, txs };
+        self
+    }
 
-        let len = self.mock_tx.serialize(&mut self.buf);
-        let payload = &self.buf[..len];
+    /// Sends what arrives on `queue`, and nothing while it is empty.
+    pub fn with_ingest(mut self, queue: Consumer<Vec<u8>>) -> Self {
+        self.feed = Feed::Ingest(queue);
+        self
+    }
 
-        for i in 0..self.batch_size {
-            let stream_id = (i * 4) as u64;
+    /// Send at `config`'s rate; the ramp starts now.
+    pub fn with_pace(mut self, config: PaceConfig) -> Self {
+        self.pacer = Pacer::new(config, Instant::now());
+        self
+    }
 
-            match driver.conn.stream_send(stream_id, payload, false) {
-                Ok(written) if written == len => {
-                    self.tx_count += 1;
+    /// Send what the pacer allows over the ready connections, including ones still in
+    /// 0-RTT, one stream per transaction. A connection that has to wait for stream credit
+    /// is left out until its queue drains.
+    pub fn shoot(&mut self, driver: &mut QuicDriver) {
+        let mut budget = self.pacer.available(Instant::now());
+        if !self.pacer.is_paced() {
+            // A batch per connection every iteration, as fast as the loop spins
+            budget *= driver.connections_mut().len();
+        }
+        let mut sent = 0;
+        match &mut self.feed {
+            Feed::Repeat(tx) => {
+                sent = spread(driver, budget, |_| &tx[..]);
+            }
+            Feed::Batch { txs, next, end } => {
+                let start = *next;
+                sent = spread(driver, budget.min(*end - start), |i| &txs[start + i][..]);
+                *next += sent;
+                if *next == *end {
+                    println!("[EMIT] Pre-signed transactions all handed out, flooding stops; raise --presign for longer runs");
+                    self.feed = Feed::Spent;
+                }
+            }
+            // Left in the queue until some connection can take it; one token per
+            // transaction however many peers it goes to
+            Feed::Ingest(queue) => {
+                while sent < budget && driver.connections_mut().iter().any(can_send) {
+                    let Some(tx) = queue.pop() else { break };
+                    for c in driver.connections_mut().iter_mut() {
+                        if can_send(c) {
+                            c.send_tx(&tx);
+                        } else {
+                            c.skip_tx();
+                        }
+                    }
+                    sent += 1;
                 }
-                Ok(_) => {}
-                Err(quiche::Error::Done) => break,
-                Err(_) => {}
             }
+            Feed::Spent => {}
         }
+        self.pacer.spend(sent);
+    }
+
+    /// `[PACE]` line: achieved rate since the last one against the target.
+    pub fn log_pace(&mut self, label: &str) {
+        self.pacer.log(label, Instant::now());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::fixture::{establish, pump, setup, transfer, TestServer, LINK_1MS};
+    use crate::quic_driver::ClientTls;
+
+    #[test]
+    fn presigned_slice_is_sent_once() {
+        let (client, mut servers, clock) = setup(LINK_1MS, &ClientTls::default(), &[&TestServer::new]);
+        let txs: Vec<Vec<u8>> = (1..=20).map(transfer).collect();
+        let mut client = client.with_transactions(txs.into(), 5..15);
+        establish(&mut client, &mut servers, &clock);
+
+        // Nothing is resent once the slice runs out
+        pump(&mut client, &mut servers, &clock, 2000);
+        assert_eq!(client.driver().tx_count(), 10);
+        assert_eq!(servers[0].finished.len(), 10);
     }
 }
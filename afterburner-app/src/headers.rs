@@ -1,67 +1,712 @@
-pub fn write_headers(frame: &mut [u8], payload_len: usize, src_port: u16, dst_port: u16) {
-    // ---------------------------------------------------------
-    // 1. ETHERNET HEADER (14 Bytes)
-    // ---------------------------------------------------------
-    // Dest MAC: FF:FF:FF:FF:FF:FF (Broadcast - guarantees acceptance on veth)
-    frame[0] = 0xFF; frame[1] = 0xFF; frame[2] = 0xFF;
-    frame[3] = 0xFF; frame[4] = 0xFF; frame[5] = 0xFF;
-
-    // Src MAC: 02:00:00:00:00:01 (Arbitrary Local)
-    frame[6] = 0x02; frame[7] = 0x00; frame[8] = 0x00;
-    frame[9] = 0x00; frame[10] = 0x00; frame[11] = 0x01;
-
-    // EtherType: IPv4 (0x0800)
-    frame[12] = 0x08; frame[13] = 0x00;
-
-    // ---------------------------------------------------------
-    // 2. IP HEADER (20 Bytes)
-    // ---------------------------------------------------------
-    let total_len = (20 + 8 + payload_len) as u16;
-
-    frame[14] = 0x45; // Version 4, Header Len 5
-    frame[15] = 0x00; // DSCP/ECN
-    frame[16] = (total_len >> 8) as u8;
-    frame[17] = (total_len & 0xFF) as u8;
-    
-    frame[18] = 0x00; frame[19] = 0x00; // ID
-    frame[20] = 0x40; frame[21] = 0x00; // Flags (Don't Fragment)
-    
-    frame[22] = 64;   // TTL
-    frame[23] = 17;   // Protocol (UDP)
-    frame[24] = 0x00; frame[25] = 0x00; // Checksum (Placeholder)
-
-    // Src IP: 10.0.0.10
-    frame[26] = 10; frame[27] = 0; frame[28] = 0; frame[29] = 10;
-    // Dst IP: 10.0.0.11
-    frame[30] = 10; frame[31] = 0; frame[32] = 0; frame[33] = 11;
-
-    // Calculate IP Checksum
-    let checksum = ipv4_checksum(&frame[14..34]);
-    frame[24] = (checksum >> 8) as u8;
-    frame[25] = (checksum & 0xFF) as u8;
-
-    // ---------------------------------------------------------
-    // 3. UDP HEADER (8 Bytes)
-    // ---------------------------------------------------------
-    let udp_len = (8 + payload_len) as u16;
-
-    frame[34] = (src_port >> 8) as u8;
-    frame[35] = (src_port & 0xFF) as u8;
-    frame[36] = (dst_port >> 8) as u8;
-    frame[37] = (dst_port & 0xFF) as u8;
-    frame[38] = (udp_len >> 8) as u8;
-    frame[39] = (udp_len & 0xFF) as u8;
-    frame[40] = 0x00; frame[41] = 0x00; // UDP Checksum (Optional for IPv4)
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, SocketAddrV4, SocketAddrV6};
+
+const ETH_HDR_LEN: usize = 14;
+const IPV4_HDR_LEN: usize = 20;
+const IPV6_HDR_LEN: usize = 40;
+const UDP_HDR_LEN: usize = 8;
+
+const ETHERTYPE_IPV4: u16 = 0x0800;
+const ETHERTYPE_ARP: u16 = 0x0806;
+const ETHERTYPE_IPV6: u16 = 0x86DD;
+const ETHERTYPE_VLAN: u16 = 0x8100;
+const ETHERTYPE_QINQ: u16 = 0x88A8;
+const VLAN_TAG_LEN: usize = 4;
+// 802.1ad outer tag plus 802.1Q inner tag
+const MAX_VLAN_TAGS: usize = 2;
+const IPPROTO_UDP: u8 = 17;
+const IPPROTO_ICMPV6: u8 = 58;
+
+/// Length of an ARP request frame, padded to the Ethernet minimum.
+pub const ARP_FRAME_LEN: usize = 60;
+/// Length of a Neighbor Solicitation carrying the source link-layer option.
+pub const NS_FRAME_LEN: usize = ETH_HDR_LEN + IPV6_HDR_LEN + 32;
+
+/// Link-layer addresses for a built frame.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct EthAddrs {
+    pub src: [u8; 6],
+    pub dst: [u8; 6],
+}
+
+/// Largest header stack we build (Ethernet + IPv6 + UDP). TX frames reserve this much
+/// in front of the payload so the headers can be chosen once the destination is known.
+pub const MAX_HEADER_LEN: usize = ETH_HDR_LEN + IPV6_HDR_LEN + UDP_HDR_LEN;
+
+/// Header bytes in front of the UDP payload of a frame sent to `to`.
+fn header_len(to: &SocketAddr) -> usize {
+    match to {
+        SocketAddr::V4(_) => ETH_HDR_LEN + IPV4_HDR_LEN + UDP_HDR_LEN,
+        SocketAddr::V6(_) => ETH_HDR_LEN + IPV6_HDR_LEN + UDP_HDR_LEN,
+    }
+}
+
+/// A received UDP datagram, borrowed from its frame.
+#[derive(Debug)]
+pub struct UdpFrame<'a> {
+    pub src: SocketAddr,
+    pub dst: SocketAddr,
+    /// Offset of `payload` in the frame
+    pub offset: usize,
+    /// Exactly the UDP length's worth; Ethernet padding is cut off
+    pub payload: &'a [u8],
+}
+
+impl UdpFrame<'_> {
+    /// Sent to `local`; an unspecified IP there matches any address on its port, as the
+    /// XDP redirect rules do.
+    pub fn is_to(&self, local: SocketAddr) -> bool {
+        self.dst.port() == local.port() && (local.ip().is_unspecified() || self.dst.ip() == local.ip())
+    }
+}
+
+/// Why `parse_frame` turned a frame down.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Reject {
+    /// Shorter than its headers or their length fields claim
+    Truncated,
+    /// Not IPv4 or IPv6, or more VLAN tags than we strip
+    NotIp,
+    /// IP packet carrying something else, including IPv6 extension headers
+    NotUdp,
+    /// Wrong version or an IHL under 5
+    BadIpHeader,
+    BadIpChecksum,
+    /// Any fragment; QUIC datagrams are never fragmented
+    Fragment,
+    BadUdpLength,
+    /// Wrong UDP checksum, or none over IPv6
+    BadUdpChecksum,
+    /// Valid, but addressed to something other than our local address
+    NotOurs,
+}
+
+impl Reject {
+    pub const ALL: [Reject; 9] = [
+        Reject::Truncated, Reject::NotIp, Reject::NotUdp, Reject::BadIpHeader,
+        Reject::BadIpChecksum, Reject::Fragment, Reject::BadUdpLength, Reject::BadUdpChecksum,
+        Reject::NotOurs,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Reject::Truncated => "truncated",
+            Reject::NotIp => "not_ip",
+            Reject::NotUdp => "not_udp",
+            Reject::BadIpHeader => "bad_ip",
+            Reject::BadIpChecksum => "ip_csum",
+            Reject::Fragment => "fragment",
+            Reject::BadUdpLength => "udp_len",
+            Reject::BadUdpChecksum => "udp_csum",
+            Reject::NotOurs => "not_ours",
+        }
+    }
+}
+
+/// Rejected frames by reason.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct RejectCounts([u64; Reject::ALL.len()]);
+
+impl RejectCounts {
+    pub fn add(&mut self, reason: Reject) {
+        self.0[reason as usize] += 1;
+    }
+
+    pub fn get(&self, reason: Reject) -> u64 {
+        self.0[reason as usize]
+    }
+
+    pub fn total(&self) -> u64 {
+        self.0.iter().sum()
+    }
+}
+
+/// Validate an Ethernet frame down to UDP and locate the payload, without copying.
+/// Up to two VLAN tags and IPv4 options are skipped; IP and UDP checksums are checked.
+pub fn parse_frame(frame: &[u8]) -> Result<UdpFrame<'_>, Reject> {
+    let mut l3 = ETH_HDR_LEN;
+    let mut ethertype = read_u16(frame, 12).ok_or(Reject::Truncated)?;
+    for _ in 0..MAX_VLAN_TAGS {
+        if ethertype != ETHERTYPE_VLAN && ethertype != ETHERTYPE_QINQ { break; }
+        ethertype = read_u16(frame, l3 + 2).ok_or(Reject::Truncated)?;
+        l3 += VLAN_TAG_LEN;
+    }
+
+    match ethertype {
+        ETHERTYPE_IPV4 => parse_ipv4(frame, l3),
+        ETHERTYPE_IPV6 => parse_ipv6(frame, l3),
+        _ => Err(Reject::NotIp),
+    }
+}
+
+fn parse_ipv4(frame: &[u8], l3: usize) -> Result<UdpFrame<'_>, Reject> {
+    let ip = frame.get(l3..l3 + IPV4_HDR_LEN).ok_or(Reject::Truncated)?;
+    let ihl = (ip[0] & 0x0F) as usize * 4;
+    if ip[0] >> 4 != 4 || ihl < IPV4_HDR_LEN { return Err(Reject::BadIpHeader); }
+    let total_len = u16::from_be_bytes([ip[2], ip[3]]) as usize;
+    if total_len < ihl || l3 + total_len > frame.len() { return Err(Reject::Truncated); }
+    // More-fragments flag or a non-zero fragment offset
+    if u16::from_be_bytes([ip[6], ip[7]]) & 0x3FFF != 0 { return Err(Reject::Fragment); }
+    if ip[9] != IPPROTO_UDP { return Err(Reject::NotUdp); }
+
+    let header = &frame[l3..l3 + ihl];
+    if fold(sum_words(0, header)) != 0 { return Err(Reject::BadIpChecksum); }
+
+    let src = Ipv4Addr::new(header[12], header[13], header[14], header[15]);
+    let dst = Ipv4Addr::new(header[16], header[17], header[18], header[19]);
+    let udp = parse_udp(&frame[l3 + ihl..l3 + total_len])?;
+
+    // Zero means the sender didn't compute one (allowed over IPv4)
+    if udp.checksum != 0 {
+        let mut sum = sum_words(0, &header[12..20]);
+        sum += udp.datagram.len() as u32 + IPPROTO_UDP as u32;
+        if fold(sum_words(sum, udp.datagram)) != 0 { return Err(Reject::BadUdpChecksum); }
+    }
+
+    Ok(UdpFrame {
+        src: SocketAddrV4::new(src, udp.src_port).into(),
+        dst: SocketAddrV4::new(dst, udp.dst_port).into(),
+        offset: l3 + ihl + UDP_HDR_LEN,
+        payload: &udp.datagram[UDP_HDR_LEN..],
+    })
+}
+
+fn parse_ipv6(frame: &[u8], l3: usize) -> Result<UdpFrame<'_>, Reject> {
+    let ip = frame.get(l3..l3 + IPV6_HDR_LEN).ok_or(Reject::Truncated)?;
+    if ip[0] >> 4 != 6 { return Err(Reject::BadIpHeader); }
+    let payload_len = u16::from_be_bytes([ip[4], ip[5]]) as usize;
+    let end = l3 + IPV6_HDR_LEN + payload_len;
+    if end > frame.len() { return Err(Reject::Truncated); }
+    // Extension headers (fragments included) would sit between here and UDP
+    if ip[6] != IPPROTO_UDP { return Err(Reject::NotUdp); }
+
+    let src = Ipv6Addr::from(<[u8; 16]>::try_from(&ip[8..24]).unwrap());
+    let dst = Ipv6Addr::from(<[u8; 16]>::try_from(&ip[24..40]).unwrap());
+    let udp = parse_udp(&frame[l3 + IPV6_HDR_LEN..end])?;
+
+    // Mandatory over IPv6; a datagram with a valid checksum sums to zero
+    let mut sum = sum_words(0, &ip[8..40]);
+    sum += udp.datagram.len() as u32 + IPPROTO_UDP as u32;
+    if udp.checksum == 0 || fold(sum_words(sum, udp.datagram)) != 0 { return Err(Reject::BadUdpChecksum); }
+
+    Ok(UdpFrame {
+        src: SocketAddrV6::new(src, udp.src_port, 0, 0).into(),
+        dst: SocketAddrV6::new(dst, udp.dst_port, 0, 0).into(),
+        offset: l3 + IPV6_HDR_LEN + UDP_HDR_LEN,
+        payload: &udp.datagram[UDP_HDR_LEN..],
+    })
+}
+
+struct Udp<'a> {
+    src_port: u16,
+    dst_port: u16,
+    checksum: u16,
+    /// Header and payload, trimmed to the UDP length
+    datagram: &'a [u8],
+}
+
+/// `ip_payload` is everything the IP header says it carries.
+fn parse_udp(ip_payload: &[u8]) -> Result<Udp<'_>, Reject> {
+    let udp = ip_payload.get(..UDP_HDR_LEN).ok_or(Reject::Truncated)?;
+    let len = u16::from_be_bytes([udp[4], udp[5]]) as usize;
+    if len < UDP_HDR_LEN || len > ip_payload.len() { return Err(Reject::BadUdpLength); }
+    Ok(Udp {
+        src_port: u16::from_be_bytes([udp[0], udp[1]]),
+        dst_port: u16::from_be_bytes([udp[2], udp[3]]),
+        checksum: u16::from_be_bytes([udp[6], udp[7]]),
+        datagram: &ip_payload[..len],
+    })
+}
+
+fn read_u16(frame: &[u8], at: usize) -> Option<u16> {
+    Some(u16::from_be_bytes([*frame.get(at)?, *frame.get(at + 1)?]))
+}
+
+/// How the UDP checksum of an outgoing datagram is filled in.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, clap::ValueEnum)]
+pub enum UdpChecksum {
+    /// None over IPv4 (checksum 0); IPv6 always gets a software checksum
+    Zero,
+    /// Computed here over the whole datagram
+    Software,
+    /// Seeded with the pseudo-header sum and completed by the NIC from AF_XDP TX metadata
+    Offload,
+}
+
+/// Ethernet/IP/UDP headers for one `from` → `to` pair, built once. Per packet only the
+/// length fields, the IPv4 ID and the checksums are patched.
+pub struct HeaderTemplate {
+    bytes: [u8; MAX_HEADER_LEN],
+    len: usize,
+    from: SocketAddr,
+    to: SocketAddr,
+    checksum: UdpChecksum,
+    // IPv4 header checksum with total length and ID still zero
+    ip_checksum: u16,
+    ip_id: u16,
+    // Pseudo-header sum, less the UDP length
+    pseudo_sum: u32,
+}
+
+impl HeaderTemplate {
+    pub fn new(eth: &EthAddrs, from: SocketAddr, to: SocketAddr, checksum: UdpChecksum) -> Self {
+        let mut bytes = [0; MAX_HEADER_LEN];
+        let len = header_len(&to);
+        let (ip_checksum, pseudo_sum, checksum) = match (from.ip(), to.ip()) {
+            (src, IpAddr::V4(dst)) => {
+                let src = to_v4(src);
+                write_eth(&mut bytes, eth, ETHERTYPE_IPV4);
+                write_ipv4(&mut bytes[ETH_HDR_LEN..], src, dst);
+                let pseudo = sum_words(sum_words(IPPROTO_UDP as u32, &src.octets()), &dst.octets());
+                (u16::from_be_bytes([bytes[24], bytes[25]]), pseudo, checksum)
+            }
+            (src, IpAddr::V6(dst)) => {
+                let src = to_v6(src);
+                write_eth(&mut bytes, eth, ETHERTYPE_IPV6);
+                write_ipv6(&mut bytes[ETH_HDR_LEN..], src, dst);
+                let pseudo = sum_words(sum_words(IPPROTO_UDP as u32, &src.octets()), &dst.octets());
+                // Mandatory over IPv6 (RFC 8200 8.1)
+                let checksum = if checksum == UdpChecksum::Zero { UdpChecksum::Software } else { checksum };
+                (0, pseudo, checksum)
+            }
+        };
+        write_udp(&mut bytes[len - UDP_HDR_LEN..len], from.port(), to.port());
+
+        HeaderTemplate { bytes, len, from, to, checksum, ip_checksum, ip_id: 0, pseudo_sum }
+    }
+
+    /// Header bytes in front of the UDP payload.
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_for(&self, from: SocketAddr, to: SocketAddr) -> bool {
+        self.from == from && self.to == to
+    }
+
+    pub fn eth(&self) -> EthAddrs {
+        EthAddrs { dst: self.bytes[0..6].try_into().unwrap(), src: self.bytes[6..12].try_into().unwrap() }
+    }
+
+    pub fn set_eth(&mut self, eth: &EthAddrs) {
+        self.bytes[0..6].copy_from_slice(&eth.dst);
+        self.bytes[6..12].copy_from_slice(&eth.src);
+    }
+
+    /// Write the headers to `frame[..self.len()]` for the `payload_len` bytes that follow.
+    /// With `UdpChecksum::Offload` returns where the NIC should checksum from, and the
+    /// checksum field's offset from there.
+    pub fn write(&mut self, frame: &mut [u8], payload_len: usize) -> Option<(u16, u16)> {
+        let udp_start = self.len - UDP_HDR_LEN;
+        let udp_len = (UDP_HDR_LEN + payload_len) as u16;
+        frame[..self.len].copy_from_slice(&self.bytes[..self.len]);
+
+        let ip = &mut frame[ETH_HDR_LEN..udp_start];
+        if self.to.is_ipv4() {
+            let total_len = IPV4_HDR_LEN as u16 + udp_len;
+            let id = self.ip_id;
+            self.ip_id = self.ip_id.wrapping_add(1);
+            ip[2..4].copy_from_slice(&total_len.to_be_bytes());
+            ip[4..6].copy_from_slice(&id.to_be_bytes());
+            let checksum = checksum_adjust(checksum_adjust(self.ip_checksum, 0, total_len), 0, id);
+            ip[10..12].copy_from_slice(&checksum.to_be_bytes());
+        } else {
+            ip[4..6].copy_from_slice(&udp_len.to_be_bytes());
+        }
+
+        let udp = &mut frame[udp_start..udp_start + udp_len as usize];
+        udp[4..6].copy_from_slice(&udp_len.to_be_bytes());
+        let pseudo_sum = self.pseudo_sum + udp_len as u32;
+        match self.checksum {
+            UdpChecksum::Zero => None,
+            UdpChecksum::Software => {
+                // A computed zero goes on the wire as all ones; zero means "no checksum"
+                let checksum = match fold(sum_words(pseudo_sum, udp)) {
+                    0 => 0xFFFF,
+                    checksum => checksum,
+                };
+                udp[6..8].copy_from_slice(&checksum.to_be_bytes());
+                None
+            }
+            UdpChecksum::Offload => {
+                // Like CHECKSUM_PARTIAL: the field holds the folded, uncomplemented pseudo-header sum
+                udp[6..8].copy_from_slice(&(!fold(pseudo_sum)).to_be_bytes());
+                Some((udp_start as u16, 6))
+            }
+        }
+    }
+}
+
+/// Broadcast ARP request for `target`'s MAC. Returns the frame length.
+pub fn write_arp_request(frame: &mut [u8], src_mac: [u8; 6], src_ip: Ipv4Addr, target: Ipv4Addr) -> usize {
+    write_eth(frame, &EthAddrs { src: src_mac, dst: [0xFF; 6] }, ETHERTYPE_ARP);
+
+    let arp = &mut frame[ETH_HDR_LEN..ARP_FRAME_LEN];
+    arp[0] = 0x00; arp[1] = 0x01;  // Hardware: Ethernet
+    arp[2] = 0x08; arp[3] = 0x00;  // Protocol: IPv4
+    arp[4] = 6; arp[5] = 4;        // Address lengths
+    arp[6] = 0x00; arp[7] = 0x01;  // Op: request
+    arp[8..14].copy_from_slice(&src_mac);
+    arp[14..18].copy_from_slice(&src_ip.octets());
+    arp[18..24].fill(0);           // Target MAC: unknown
+    arp[24..28].copy_from_slice(&target.octets());
+    arp[28..].fill(0);             // Padding
+    ARP_FRAME_LEN
+}
+
+/// ICMPv6 Neighbor Solicitation for `target`, sent to its solicited-node multicast group.
+/// Returns the frame length.
+pub fn write_neighbor_solicit(frame: &mut [u8], src_mac: [u8; 6], src_ip: Ipv6Addr, target: Ipv6Addr) -> usize {
+    let t = target.octets();
+    let group = Ipv6Addr::new(0xff02, 0, 0, 0, 0, 1, 0xff00 | t[13] as u16, u16::from_be_bytes([t[14], t[15]]));
+    let g = group.octets();
+    write_eth(frame, &EthAddrs { src: src_mac, dst: [0x33, 0x33, g[12], g[13], g[14], g[15]] }, ETHERTYPE_IPV6);
+
+    let ip = &mut frame[ETH_HDR_LEN..ETH_HDR_LEN + IPV6_HDR_LEN];
+    ip[0] = 0x60; ip[1] = 0x00; ip[2] = 0x00; ip[3] = 0x00;
+    ip[4] = 0x00; ip[5] = 32;     // Payload length
+    ip[6] = IPPROTO_ICMPV6;
+    ip[7] = 255;                  // Hop limit: NDP requires 255
+    ip[8..24].copy_from_slice(&src_ip.octets());
+    ip[24..40].copy_from_slice(&g);
+
+    let icmp = &mut frame[ETH_HDR_LEN + IPV6_HDR_LEN..NS_FRAME_LEN];
+    icmp[0] = 135;                // Neighbor Solicitation
+    icmp[1] = 0;
+    icmp[2] = 0; icmp[3] = 0;     // Checksum (Placeholder)
+    icmp[4..8].fill(0);           // Reserved
+    icmp[8..24].copy_from_slice(&t);
+    icmp[24] = 1; icmp[25] = 1;   // Option: source link-layer address, 8 bytes
+    icmp[26..32].copy_from_slice(&src_mac);
+
+    let checksum = checksum_v6(&src_ip, &group, IPPROTO_ICMPV6, icmp);
+    icmp[2] = (checksum >> 8) as u8;
+    icmp[3] = (checksum & 0xFF) as u8;
+    NS_FRAME_LEN
+}
+
+/// MAC announced for `target` by an ARP reply or ICMPv6 Neighbor Advertisement, if `frame` is one.
+pub fn parse_neighbor_reply(frame: &[u8], target: IpAddr) -> Option<[u8; 6]> {
+    let ethertype = u16::from_be_bytes([*frame.get(12)?, *frame.get(13)?]);
+    match (ethertype, target) {
+        (ETHERTYPE_ARP, IpAddr::V4(target)) => {
+            let arp = frame.get(ETH_HDR_LEN..ETH_HDR_LEN + 28)?;
+            // Op 2 (reply), sender protocol address is the one we asked for
+            if arp[6..8] != [0x00, 0x02] || arp[14..18] != target.octets() { return None; }
+            arp[8..14].try_into().ok()
+        }
+        (ETHERTYPE_IPV6, IpAddr::V6(target)) => {
+            let icmp = frame.get(ETH_HDR_LEN + IPV6_HDR_LEN..)?;
+            if frame[ETH_HDR_LEN + 6] != IPPROTO_ICMPV6 || *icmp.first()? != 136 { return None; }
+            if *icmp.get(8..24)? != target.octets() { return None; }
+            // Target link-layer option if present, else the Ethernet source
+            match icmp.get(24..32) {
+                Some([2, 1, mac @ ..]) => mac.try_into().ok(),
+                _ => frame[6..12].try_into().ok(),
+            }
+        }
+        _ => None,
+    }
+}
+
+fn write_eth(frame: &mut [u8], eth: &EthAddrs, ethertype: u16) {
+    frame[0..6].copy_from_slice(&eth.dst);
+    frame[6..12].copy_from_slice(&eth.src);
+    frame[12] = (ethertype >> 8) as u8;
+    frame[13] = (ethertype & 0xFF) as u8;
+}
+
+// Total length and ID are left zero (and out of the checksum) for `HeaderTemplate::write`
+fn write_ipv4(ip: &mut [u8], src: Ipv4Addr, dst: Ipv4Addr) {
+    ip[0] = 0x45; // Version 4, Header Len 5
+    ip[1] = 0x00; // DSCP/ECN
+    ip[2] = 0x00; ip[3] = 0x00; // Total length
+
+    ip[4] = 0x00; ip[5] = 0x00; // ID
+    ip[6] = 0x40; ip[7] = 0x00; // Flags (Don't Fragment)
+
+    ip[8] = 64;   // TTL
+    ip[9] = IPPROTO_UDP;
+    ip[10] = 0x00; ip[11] = 0x00; // Checksum (Placeholder)
+
+    ip[12..16].copy_from_slice(&src.octets());
+    ip[16..20].copy_from_slice(&dst.octets());
+
+    let checksum = ipv4_checksum(&ip[..IPV4_HDR_LEN]);
+    ip[10] = (checksum >> 8) as u8;
+    ip[11] = (checksum & 0xFF) as u8;
+}
+
+fn write_ipv6(ip: &mut [u8], src: Ipv6Addr, dst: Ipv6Addr) {
+    ip[0] = 0x60; ip[1] = 0x00; ip[2] = 0x00; ip[3] = 0x00; // Version 6, no traffic class/flow label
+    ip[4] = 0x00; ip[5] = 0x00; // Payload length
+    ip[6] = IPPROTO_UDP; // Next header
+    ip[7] = 64;          // Hop limit
+
+    ip[8..24].copy_from_slice(&src.octets());
+    ip[24..40].copy_from_slice(&dst.octets());
+}
+
+fn write_udp(udp: &mut [u8], src_port: u16, dst_port: u16) {
+    udp[0] = (src_port >> 8) as u8;
+    udp[1] = (src_port & 0xFF) as u8;
+    udp[2] = (dst_port >> 8) as u8;
+    udp[3] = (dst_port & 0xFF) as u8;
+    udp[4] = 0x00; udp[5] = 0x00; // Length
+    udp[6] = 0x00; udp[7] = 0x00; // Checksum (0 = none over IPv4)
+}
+
+// A v4 source can only show up here through a mixed-family config; map it rather than panic
+fn to_v4(ip: IpAddr) -> Ipv4Addr {
+    match ip {
+        IpAddr::V4(ip) => ip,
+        IpAddr::V6(ip) => ip.to_ipv4_mapped().unwrap_or(Ipv4Addr::UNSPECIFIED),
+    }
+}
+
+fn to_v6(ip: IpAddr) -> Ipv6Addr {
+    match ip {
+        IpAddr::V4(ip) => ip.to_ipv6_mapped(),
+        IpAddr::V6(ip) => ip,
+    }
 }
 
 fn ipv4_checksum(header: &[u8]) -> u16 {
-    let mut sum: u32 = 0;
-    for i in (0..header.len()).step_by(2) {
-        let word = ((header[i] as u32) << 8) + (header[i+1] as u32);
-        sum = sum.wrapping_add(word);
+    fold(sum_words(0, header))
+}
+
+/// RFC 1624 eqn. 3: `checksum` after one 16-bit word it covers changed from `old` to `new`.
+fn checksum_adjust(checksum: u16, old: u16, new: u16) -> u16 {
+    fold(!checksum as u32 + !old as u32 + new as u32)
+}
+
+fn checksum_v6(src: &Ipv6Addr, dst: &Ipv6Addr, next_header: u8, data: &[u8]) -> u16 {
+    // Pseudo-header: source, destination, upper-layer length, next header
+    let mut sum = sum_words(0, &src.octets());
+    sum = sum_words(sum, &dst.octets());
+    sum += data.len() as u32;
+    sum += next_header as u32;
+    sum = sum_words(sum, data);
+
+    // A computed zero goes on the wire as all ones; zero means "no checksum"
+    match fold(sum) {
+        0 => 0xFFFF,
+        checksum => checksum,
     }
+}
+
+fn sum_words(mut sum: u32, data: &[u8]) -> u32 {
+    let mut chunks = data.chunks_exact(2);
+    for word in &mut chunks {
+        sum = sum.wrapping_add(((word[0] as u32) << 8) + (word[1] as u32));
+    }
+    if let [last] = chunks.remainder() {
+        sum = sum.wrapping_add((*last as u32) << 8);
+    }
+    // Fold early so long payloads can't overflow the accumulator
+    (sum & 0xFFFF) + (sum >> 16)
+}
+
+fn fold(mut sum: u32) -> u16 {
     while (sum >> 16) > 0 {
         sum = (sum & 0xFFFF) + (sum >> 16);
     }
     !sum as u16
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ETH: EthAddrs = EthAddrs { src: [0x02, 0, 0, 0, 0, 0x01], dst: [0x02, 0, 0, 0, 0, 0x02] };
+
+    fn template(from: &str, to: &str, checksum: UdpChecksum) -> HeaderTemplate {
+        HeaderTemplate::new(&ETH, from.parse().unwrap(), to.parse().unwrap(), checksum)
+    }
+
+    fn emit(template: &mut HeaderTemplate, payload: &[u8]) -> Vec<u8> {
+        let mut frame = vec![0; template.len() + payload.len()];
+        frame[template.len()..].copy_from_slice(payload);
+        template.write(&mut frame, payload.len());
+        frame
+    }
+
+    fn build(from: &str, to: &str, payload: &[u8]) -> Vec<u8> {
+        emit(&mut template(from, to, UdpChecksum::Zero), payload)
+    }
+
+    #[test]
+    fn ipv4_frame_round_trips() {
+        let frame = build("10.0.0.10:8000", "10.0.0.11:8004", b"quic");
+        let parsed = parse_frame(&frame).unwrap();
+        assert_eq!(parsed.src, "10.0.0.10:8000".parse().unwrap());
+        assert_eq!(parsed.dst, "10.0.0.11:8004".parse().unwrap());
+        assert_eq!(parsed.offset, 42);
+        assert_eq!(parsed.payload, b"quic");
+    }
+
+    #[test]
+    fn ipv6_frame_has_valid_udp_checksum() {
+        let mut frame = build("[fd00::10]:8000", "[fd00::11]:8004", b"odd");
+        assert_eq!(&frame[12..14], &[0x86, 0xDD]);
+        assert_ne!(&frame[60..62], &[0, 0]);
+        let parsed = parse_frame(&frame).unwrap();
+        assert_eq!((parsed.src.port(), parsed.offset, parsed.payload), (8000, 62, &b"odd"[..]));
+
+        frame[62] ^= 1;
+        assert_eq!(parse_frame(&frame).unwrap_err(), Reject::BadUdpChecksum);
+        frame[62] ^= 1;
+        frame[60..62].fill(0);
+        assert_eq!(parse_frame(&frame).unwrap_err(), Reject::BadUdpChecksum);
+    }
+
+    #[test]
+    fn template_patches_length_id_and_checksums() {
+        for checksum in [UdpChecksum::Zero, UdpChecksum::Software] {
+            let mut t = template("10.0.0.10:8000", "10.0.0.11:8004", checksum);
+            for (i, len) in [1, 1200, 35].into_iter().enumerate() {
+                let frame = emit(&mut t, &vec![0xA5; len]);
+                // parse_frame checks the IPv4 header checksum, and the UDP one when non-zero
+                assert_eq!(parse_frame(&frame).unwrap().payload.len(), len);
+                assert_eq!(u16::from_be_bytes([frame[18], frame[19]]), i as u16, "IP ID");
+                assert_eq!(frame[40..42] == [0, 0], checksum == UdpChecksum::Zero);
+            }
+        }
+    }
+
+    #[test]
+    fn template_ip_id_wraps_with_valid_checksum() {
+        let mut t = template("10.0.0.10:8000", "10.0.0.11:8004", UdpChecksum::Zero);
+        t.ip_id = u16::MAX;
+        assert!(parse_frame(&emit(&mut t, b"a")).is_ok());
+        let frame = emit(&mut t, b"b");
+        assert_eq!(&frame[18..20], &[0, 0]);
+        assert!(parse_frame(&frame).is_ok());
+    }
+
+    #[test]
+    fn offload_seeds_pseudo_header_sum() {
+        for (from, to) in [("10.0.0.10:8000", "10.0.0.11:8004"), ("[fd00::10]:8000", "[fd00::11]:8004")] {
+            let mut t = template(from, to, UdpChecksum::Offload);
+            let mut frame = vec![0; t.len() + 5];
+            frame[t.len()..].copy_from_slice(b"hello");
+            let (start, offset) = t.write(&mut frame, 5).unwrap();
+            assert!(parse_frame(&frame).is_err(), "seeded checksum alone must not verify");
+
+            // What the NIC does: checksum from `start` to the end, stored at `start + offset`
+            let (start, at) = (start as usize, (start + offset) as usize);
+            let checksum = fold(sum_words(0, &frame[start..]));
+            frame[at..at + 2].copy_from_slice(&checksum.to_be_bytes());
+            assert_eq!(parse_frame(&frame).unwrap().payload, b"hello");
+        }
+    }
+
+    #[test]
+    fn template_follows_mac_changes() {
+        let mut t = template("10.0.0.10:8000", "10.0.0.11:8004", UdpChecksum::Zero);
+        let eth = EthAddrs { src: [0x02, 0, 0, 0, 0, 0x01], dst: [0x02, 0, 0, 0, 0, 0x03] };
+        t.set_eth(&eth);
+        assert_eq!(t.eth(), eth);
+        assert_eq!(&emit(&mut t, b"x")[..6], &eth.dst);
+    }
+
+    #[test]
+    fn parse_skips_vlan_tags_and_ip_options_and_trims_padding() {
+        let frame = build("10.0.0.10:8000", "10.0.0.11:8004", b"quic");
+
+        // 802.1Q tag after the MACs, 4 bytes of IPv4 options (NOPs), 6 bytes of padding
+        let mut tagged = frame[..12].to_vec();
+        tagged.extend_from_slice(&[0x81, 0x00, 0x00, 0x2A]);
+        tagged.extend_from_slice(&frame[12..34]);
+        tagged.extend_from_slice(&[1; 4]);
+        tagged.extend_from_slice(&frame[34..]);
+        tagged.extend_from_slice(&[0; 6]);
+        let ip = &mut tagged[18..42];
+        ip[0] = 0x46;
+        ip[3] += 4;
+        ip[10..12].fill(0);
+        let checksum = ipv4_checksum(ip);
+        ip[10..12].copy_from_slice(&checksum.to_be_bytes());
+
+        let parsed = parse_frame(&tagged).unwrap();
+        assert_eq!(parsed.offset, 18 + 24 + 8);
+        assert_eq!(parsed.payload, b"quic");
+    }
+
+    #[test]
+    fn parse_rejects_bad_lengths_and_checksums() {
+        let frame = build("10.0.0.10:8000", "10.0.0.11:8004", b"quic");
+        let reject = |edit: &dyn Fn(&mut Vec<u8>)| {
+            let mut f = frame.clone();
+            edit(&mut f);
+            parse_frame(&f).unwrap_err()
+        };
+
+        assert_eq!(reject(&|f| f.truncate(40)), Reject::Truncated);
+        assert_eq!(reject(&|f| f[13] = 0x06), Reject::NotIp);
+        assert_eq!(reject(&|f| f[23] = 6), Reject::NotUdp);
+        assert_eq!(reject(&|f| f[14] = 0x44), Reject::BadIpHeader);
+        assert_eq!(reject(&|f| f[22] -= 1), Reject::BadIpChecksum);
+        assert_eq!(reject(&|f| f[20] |= 0x20), Reject::Fragment);
+        assert_eq!(reject(&|f| f[39] = 50), Reject::BadUdpLength);
+        // Over IPv4 a zero UDP checksum is accepted, a wrong one is not
+        assert_eq!(reject(&|f| { f[40..42].copy_from_slice(&[0x12, 0x34]); }), Reject::BadUdpChecksum);
+    }
+
+    #[test]
+    fn frames_are_matched_to_the_local_address() {
+        let frame = build("10.0.0.11:8004", "10.0.0.10:8000", b"quic");
+        let parsed = parse_frame(&frame).unwrap();
+        assert!(parsed.is_to("10.0.0.10:8000".parse().unwrap()));
+        assert!(parsed.is_to("0.0.0.0:8000".parse().unwrap()), "unspecified IP, same port");
+        assert!(!parsed.is_to("10.0.0.10:8001".parse().unwrap()));
+        assert!(!parsed.is_to("10.0.0.12:8000".parse().unwrap()));
+    }
+
+    #[test]
+    fn neighbor_replies_yield_the_target_mac() {
+        let peer_mac = [0x02, 0, 0, 0, 0, 0x11];
+        let (our_ip, peer_ip) = (Ipv4Addr::new(10, 0, 0, 10), Ipv4Addr::new(10, 0, 0, 1));
+
+        // An ARP reply is a request from the peer with op 2
+        let mut arp = [0; ARP_FRAME_LEN];
+        write_arp_request(&mut arp, peer_mac, peer_ip, our_ip);
+        assert_eq!(parse_neighbor_reply(&arp, peer_ip.into()), None, "still a request");
+        arp[20..22].copy_from_slice(&[0x00, 0x02]);
+        assert_eq!(parse_neighbor_reply(&arp, peer_ip.into()), Some(peer_mac));
+        assert_eq!(parse_neighbor_reply(&arp, our_ip.into()), None, "answers for someone else");
+        assert_eq!(parse_neighbor_reply(&arp[..40], peer_ip.into()), None, "truncated");
+
+        // Neighbor Advertisement, with and without the target link-layer address option
+        let target: Ipv6Addr = "fe80::11".parse().unwrap();
+        let mut na = vec![0; ETH_HDR_LEN + IPV6_HDR_LEN + 32];
+        na[6..12].copy_from_slice(&[0x02, 0, 0, 0, 0, 0x22]);
+        na[12..14].copy_from_slice(&ETHERTYPE_IPV6.to_be_bytes());
+        na[ETH_HDR_LEN + 6] = IPPROTO_ICMPV6;
+        let icmp = ETH_HDR_LEN + IPV6_HDR_LEN;
+        na[icmp] = 136;
+        na[icmp + 8..icmp + 24].copy_from_slice(&target.octets());
+        na[icmp + 24..icmp + 26].copy_from_slice(&[2, 1]);
+        na[icmp + 26..icmp + 32].copy_from_slice(&peer_mac);
+        assert_eq!(parse_neighbor_reply(&na, target.into()), Some(peer_mac));
+        assert_eq!(parse_neighbor_reply(&na[..icmp + 24], target.into()), Some([0x02, 0, 0, 0, 0, 0x22]));
+        assert_eq!(parse_neighbor_reply(&na, "fe80::12".parse().unwrap()), None);
+        assert_eq!(parse_neighbor_reply(&na, peer_ip.into()), None, "wrong family");
+        na[icmp] = 135;
+        assert_eq!(parse_neighbor_reply(&na, target.into()), None, "a solicitation");
+    }
+
+    #[test]
+    fn neighbor_solicit_targets_solicited_node_group() {
+        let mut frame = [0; NS_FRAME_LEN];
+        let target: Ipv6Addr = "fd00::12:3456".parse().unwrap();
+        write_neighbor_solicit(&mut frame, [0x02, 0, 0, 0, 0, 0x01], "fd00::10".parse().unwrap(), target);
+
+        assert_eq!(&frame[..6], &[0x33, 0x33, 0xff, 0x12, 0x34, 0x56]);
+        assert_eq!(&frame[38..54], &"ff02::1:ff12:3456".parse::<Ipv6Addr>().unwrap().octets());
+        assert_eq!(&frame[62..78], &target.octets());
+
+        let mut sum = sum_words(0, &frame[22..54]);
+        sum += 32 + IPPROTO_ICMPV6 as u32;
+        assert_eq!(fold(sum_words(sum, &frame[54..])), 0, "bad ICMPv6 checksum");
+    }
+}
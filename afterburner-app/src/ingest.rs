@@ -0,0 +1,363 @@
+use std::fs::{self, File};
+use std::io::{self, BufRead, BufReader, Read};
+use std::os::unix::fs::FileTypeExt;
+use std::os::unix::net::{UnixDatagram, UnixListener};
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::{Duration, Instant};
+use base64::{engine::general_purpose::STANDARD, Engine as _};
+use crate::emit::Transaction;
+use crate::spsc::{self, Consumer, Producer};
+
+// Per worker; a full queue holds back file/stream readers and drops datagrams
+const QUEUE_DEPTH: usize = 4096;
+const LOG_INTERVAL: Duration = Duration::from_secs(1);
+// How long a reader waits on one worker's full queue before dropping for it instead
+const STALL_TIMEOUT: Duration = Duration::from_millis(100);
+
+/// Where externally built (signed, wire-format) transactions come from.
+#[derive(Clone, Debug)]
+pub enum Source {
+    /// One transaction per datagram
+    UnixDatagram(PathBuf),
+    /// Length-prefixed transactions (`binary` framing), one client at a time
+    UnixStream(PathBuf),
+    /// Replayed once in the given framing; `-` is stdin
+    File(PathBuf),
+}
+
+impl Source {
+    /// `unix-dgram:<path>`, `unix-stream:<path>`, `file:<path>` or `-` for stdin.
+    pub fn parse(s: &str) -> Result<Self, String> {
+        match s.split_once(':') {
+            _ if s == "-" => Ok(Source::File(s.into())),
+            Some(("unix-dgram", path)) => Ok(Source::UnixDatagram(path.into())),
+            Some(("unix-stream", path)) => Ok(Source::UnixStream(path.into())),
+            Some(("file", path)) => Ok(Source::File(path.into())),
+            _ => Err(format!("{:?}: expected unix-dgram:<path>, unix-stream:<path>, file:<path> or -", s)),
+        }
+    }
+}
+
+/// Record framing for files and stdin.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, clap::ValueEnum)]
+pub enum Framing {
+    /// One base64 transaction per line
+    Base64,
+    /// Each transaction preceded by its length as a little-endian u16
+    Binary,
+}
+
+#[derive(Default)]
+struct Counts {
+    accepted: u64,
+    invalid: u64,
+    dropped: u64,
+    last_error: Option<String>,
+}
+
+/// Validates transactions and copies them to every worker's queue, since each worker
+/// floods its own share of the peers.
+struct Fanout {
+    label: String,
+    queues: Vec<Producer<Vec<u8>>>,
+    // Queues that stayed full past STALL_TIMEOUT; not waited on again until they take one
+    stalled: Vec<bool>,
+    counts: Counts,
+    last_log: Instant,
+    logged: (u64, u64, u64),
+}
+
+impl Fanout {
+    fn new(label: String, queues: Vec<Producer<Vec<u8>>>) -> Self {
+        Fanout {
+            label,
+            stalled: vec![false; queues.len()],
+            queues,
+            counts: Counts::default(),
+            last_log: Instant::now(),
+            logged: (0, 0, 0),
+        }
+    }
+
+    fn reject(&mut self, err: impl ToString) {
+        self.counts.invalid += 1;
+        self.counts.last_error = Some(err.to_string());
+    }
+
+    /// Queue one wire-format transaction for every worker. With `wait` a full queue is
+    /// waited out, for up to `STALL_TIMEOUT`, instead of dropping that worker's copy; a
+    /// worker that doesn't drain (no ready peer) can't hold up the others for longer.
+    fn offer(&mut self, tx: &[u8], wait: bool) {
+        if let Err(e) = Transaction::deserialize(tx) {
+            self.reject(e);
+            return self.maybe_log();
+        }
+        for (i, (queue, stalled)) in self.queues.iter_mut().zip(&mut self.stalled).enumerate() {
+            let mut copy = tx.to_vec();
+            let mut deadline = None;
+            loop {
+                match queue.push(copy) {
+                    Ok(()) => {
+                        *stalled = false;
+                        break;
+                    }
+                    Err(back) if wait && !*stalled => {
+                        let deadline = *deadline.get_or_insert_with(|| Instant::now() + STALL_TIMEOUT);
+                        if Instant::now() >= deadline {
+                            println!("[INGEST] {} worker {} queue is not draining, dropping its copies until it does", self.label, i);
+                            *stalled = true;
+                        }
+                        copy = back;
+                        thread::sleep(Duration::from_micros(50));
+                    }
+                    Err(_) => {
+                        self.counts.dropped += 1;
+                        break;
+                    }
+                }
+            }
+        }
+        self.counts.accepted += 1;
+        self.maybe_log();
+    }
+
+    fn maybe_log(&mut self) {
+        let c = &self.counts;
+        if self.last_log.elapsed() >= LOG_INTERVAL && (c.accepted, c.invalid, c.dropped) != self.logged {
+            self.log();
+        }
+    }
+
+    fn log(&mut self) {
+        let c = &self.counts;
+        match &c.last_error {
+            Some(e) => println!("[INGEST] {} accepted={} invalid={} (last: {}) dropped={}", self.label, c.accepted, c.invalid, e, c.dropped),
+            None => println!("[INGEST] {} accepted={} invalid={} dropped={}", self.label, c.accepted, c.invalid, c.dropped),
+        }
+        self.logged = (c.accepted, c.invalid, c.dropped);
+        self.last_log = Instant::now();
+    }
+
+    /// `binary` framing until EOF.
+    fn read_frames(&mut self, mut reader: impl Read) -> io::Result<()> {
+        let mut buf = [0; u16::MAX as usize];
+        loop {
+            let mut len = [0; 2];
+            match reader.read_exact(&mut len) {
+                Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(()),
+                r => r?,
+            }
+            let len = u16::from_le_bytes(len) as usize;
+            reader.read_exact(&mut buf[..len])?;
+            self.offer(&buf[..len], true);
+        }
+    }
+
+    fn read_base64(&mut self, reader: impl BufRead) -> io::Result<()> {
+        for line in reader.lines() {
+            let line = line?;
+            let line = line.trim();
+            if line.is_empty() {
+                continue;
+            }
+            match STANDARD.decode(line) {
+                Ok(tx) => self.offer(&tx, true),
+                Err(e) => self.reject(format!("base64: {}", e)),
+            }
+        }
+        Ok(())
+    }
+}
+
+/// A stale socket from an earlier run is replaced; anything else at `path` is left alone.
+fn unlink_socket(path: &Path) {
+    if fs::symlink_metadata(path).is_ok_and(|m| m.file_type().is_socket()) {
+        let _ = fs::remove_file(path);
+    }
+}
+
+/// A source bound or opened on the caller's thread, so errors show up at startup.
+enum Opened {
+    Datagram(UnixDatagram),
+    Stream(UnixListener),
+    Replay(Box<dyn Read + Send>, Framing),
+}
+
+impl Opened {
+    fn open(source: &Source, framing: Framing) -> io::Result<(Self, String)> {
+        Ok(match source {
+            Source::UnixDatagram(path) => {
+                unlink_socket(path);
+                (Opened::Datagram(UnixDatagram::bind(path)?), path.display().to_string())
+            }
+            Source::UnixStream(path) => {
+                unlink_socket(path);
+                (Opened::Stream(UnixListener::bind(path)?), path.display().to_string())
+            }
+            Source::File(path) if path.as_os_str() == "-" => (Opened::Replay(Box::new(io::stdin()), framing), "stdin".into()),
+            Source::File(path) => (Opened::Replay(Box::new(File::open(path)?), framing), path.display().to_string()),
+        })
+    }
+
+    fn run(self, fanout: &mut Fanout) -> io::Result<()> {
+        match self {
+            Opened::Datagram(socket) => {
+                let mut buf = [0; u16::MAX as usize];
+                loop {
+                    let n = socket.recv(&mut buf)?;
+                    fanout.offer(&buf[..n], false);
+                }
+            }
+            Opened::Stream(listener) => {
+                for stream in listener.incoming() {
+                    if let Err(e) = fanout.read_frames(stream?) {
+                        println!("[INGEST] {} client dropped: {}", fanout.label, e);
+                    }
+                }
+                Ok(())
+            }
+            Opened::Replay(reader, framing) => {
+                match framing {
+                    Framing::Base64 => fanout.read_base64(BufReader::new(reader))?,
+                    Framing::Binary => fanout.read_frames(BufReader::new(reader))?,
+                }
+                println!("[INGEST] {} replayed", fanout.label);
+                Ok(())
+            }
+        }
+    }
+}
+
+/// Open `source` and feed it on its own thread into one queue per worker, returned in
+/// worker order.
+pub fn start(source: &Source, framing: Framing, workers: usize) -> io::Result<Vec<Consumer<Vec<u8>>>> {
+    let (opened, label) = Opened::open(source, framing)?;
+    let (producers, consumers) = (0..workers.max(1)).map(|_| spsc::channel(QUEUE_DEPTH)).unzip();
+    let mut fanout = Fanout::new(label, producers);
+
+    println!("[INGEST] Reading transactions from {} into {} worker queue(s)", fanout.label, workers.max(1));
+    thread::Builder::new().name("ingest".into()).spawn(move || {
+        if let Err(e) = opened.run(&mut fanout) {
+            println!("[INGEST] {} stopped: {}", fanout.label, e);
+        }
+        fanout.log();
+    })?;
+    Ok(consumers)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+    use std::os::unix::net::UnixStream;
+    use super::*;
+    use crate::emit::{Message, PACKET_DATA_SIZE};
+    use crate::fixture::{establish, pump, setup, transfer, TempDir, TestServer, LINK_1MS, TX_LEN};
+    use crate::quic_driver::ClientTls;
+
+    fn wire(lamports: u64) -> Vec<u8> {
+        let mut buf = [0; PACKET_DATA_SIZE];
+        let len = Transaction::new(Message::transfer([1; 32], [2; 32], lamports, [0; 32])).serialize(&mut buf).unwrap();
+        buf[..len].to_vec()
+    }
+
+    /// Pop `n` transactions from the queues in turn, waiting for the ingest thread.
+    fn collect(queues: &mut [Consumer<Vec<u8>>], n: usize) -> Vec<Vec<u8>> {
+        let deadline = Instant::now() + Duration::from_secs(5);
+        let mut out = Vec::new();
+        while out.len() < n && Instant::now() < deadline {
+            for q in queues.iter_mut() {
+                out.extend(q.pop());
+            }
+            thread::yield_now();
+        }
+        out
+    }
+
+    #[test]
+    fn parses_sources() {
+        assert!(matches!(Source::parse("-"), Ok(Source::File(p)) if p.as_os_str() == "-"));
+        assert!(matches!(Source::parse("unix-dgram:/run/tx.sock"), Ok(Source::UnixDatagram(_))));
+        assert!(matches!(Source::parse("file:txs.b64"), Ok(Source::File(_))));
+        assert!(Source::parse("tcp:1.2.3.4:80").is_err());
+    }
+
+    #[test]
+    fn replays_base64_file_to_every_worker() {
+        let dir = TempDir::new("ingest");
+        let path = dir.join("replay.b64");
+        let mut f = File::create(&path).unwrap();
+        for i in 1..=6 {
+            writeln!(f, "{}", STANDARD.encode(wire(i))).unwrap();
+        }
+        writeln!(f, "not base64!").unwrap();
+        writeln!(f, "{}", STANDARD.encode(b"short")).unwrap();
+        drop(f);
+
+        let mut queues = start(&Source::File(path.clone()), Framing::Base64, 2).unwrap();
+        for queue in queues.iter_mut() {
+            let got = collect(std::slice::from_mut(queue), 6);
+            assert_eq!(got, (1..=6).map(wire).collect::<Vec<_>>());
+        }
+    }
+
+    #[test]
+    fn a_stalled_worker_does_not_hold_up_the_others() {
+        let (stuck, _never_popped) = spsc::channel(4);
+        let (live, mut popped) = spsc::channel(4);
+        let mut fanout = Fanout::new("test".into(), vec![stuck, live]);
+
+        let started = Instant::now();
+        for i in 1..=20 {
+            fanout.offer(&wire(i), true);
+            assert_eq!(popped.pop(), Some(wire(i)));
+        }
+        // Waited out once, then dropped for without waiting
+        assert!(started.elapsed() < STALL_TIMEOUT * 5, "waited {:?}", started.elapsed());
+        assert_eq!((fanout.counts.accepted, fanout.counts.dropped), (20, 16));
+        assert!(fanout.stalled[0] && !fanout.stalled[1]);
+    }
+
+    #[test]
+    fn unix_sockets_take_raw_transactions() {
+        let dir = TempDir::new("ingest");
+        let dgram = dir.join("dgram.sock");
+        let mut queues = start(&Source::UnixDatagram(dgram.clone()), Framing::Binary, 1).unwrap();
+        let client = UnixDatagram::unbound().unwrap();
+        client.send_to(&wire(7), &dgram).unwrap();
+        client.send_to(b"garbage", &dgram).unwrap();
+        client.send_to(&wire(8), &dgram).unwrap();
+        assert_eq!(collect(&mut queues, 2), [wire(7), wire(8)]);
+
+        let stream = dir.join("stream.sock");
+        let mut queues = start(&Source::UnixStream(stream.clone()), Framing::Binary, 1).unwrap();
+        let mut client = UnixStream::connect(&stream).unwrap();
+        for tx in [wire(9), wire(10)] {
+            client.write_all(&(tx.len() as u16).to_le_bytes()).unwrap();
+            client.write_all(&tx).unwrap();
+        }
+        assert_eq!(collect(&mut queues, 2), [wire(9), wire(10)]);
+    }
+
+    #[test]
+    fn ingested_transactions_reach_every_worker() {
+        // Fanned out in-process, as the ingest thread does, to two workers flooding their own server
+        let (producers, queues): (Vec<_>, Vec<_>) = (0..2).map(|_| spsc::channel(QUEUE_DEPTH)).unzip();
+        let mut fanout = Fanout::new("test".into(), producers);
+        for lamports in 1..=20 {
+            fanout.offer(&transfer(lamports), false);
+        }
+        assert_eq!((fanout.counts.accepted, fanout.counts.dropped), (20, 0));
+
+        for queue in queues {
+            let (client, mut servers, clock) = setup(LINK_1MS, &ClientTls::default(), &[&TestServer::new]);
+            let mut client = client.with_ingest(queue);
+            establish(&mut client, &mut servers, &clock);
+
+            pump(&mut client, &mut servers, &clock, 2000);
+            assert_eq!(client.driver().tx_count(), 20);
+            assert_eq!(servers[0].finished.len(), 20, "each transaction once, on its own stream");
+            assert!(servers[0].finished.iter().all(|(_, len)| *len == TX_LEN));
+        }
+    }
+}
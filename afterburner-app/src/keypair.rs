@@ -0,0 +1,220 @@
+use std::fs::{self, OpenOptions};
+use std::io::{self, Write};
+use std::net::{IpAddr, Ipv4Addr};
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use ring::signature::Ed25519KeyPair;
+use x509_parser::oid_registry::OID_SIG_ED25519;
+
+// PKCS#8 v1 wrapper for a raw ed25519 seed (RFC 8410, section 7):
+// SEQUENCE { INTEGER 0, SEQUENCE { OID 1.3.101.112 }, OCTET STRING { OCTET STRING (32) } }
+const PKCS8_PREFIX: [u8; 16] = [
+    0x30, 0x2e, 0x02, 0x01, 0x00, 0x30, 0x05, 0x06, 0x03, 0x2b, 0x65, 0x70, 0x04, 0x22, 0x04, 0x20,
+];
+
+fn invalid(msg: impl Into<String>) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg.into())
+}
+
+/// A Solana ed25519 keypair: the 32-byte seed followed by the 32-byte public key, as stored
+/// by `solana-keygen` (a JSON byte array) or exported by wallets (base58).
+#[derive(Clone)]
+pub struct Keypair {
+    bytes: [u8; 64],
+}
+
+impl Keypair {
+    pub fn read_file(path: &Path) -> io::Result<Self> {
+        Self::parse(&fs::read_to_string(path)?)
+    }
+
+    /// Accepts `[12,34,...]` or a base58 string; the public half must match the seed.
+    pub fn parse(text: &str) -> io::Result<Self> {
+        let text = text.trim();
+        let bytes = match text.strip_prefix('[').and_then(|t| t.strip_suffix(']')) {
+            Some(list) => list
+                .split(',')
+                .map(|b| b.trim().parse::<u8>().map_err(|e| invalid(format!("bad byte {:?}: {}", b.trim(), e))))
+                .collect::<io::Result<Vec<u8>>>()?,
+            None => bs58::decode(text).into_vec().map_err(|e| invalid(format!("not base58 or a JSON byte array: {}", e)))?,
+        };
+        let bytes: [u8; 64] = bytes
+            .try_into()
+            .map_err(|b: Vec<u8>| invalid(format!("keypair is {} bytes, expected 64", b.len())))?;
+        Ed25519KeyPair::from_seed_and_public_key(&bytes[..32], &bytes[32..])
+            .map_err(|_| invalid("public key does not match the secret key"))?;
+        Ok(Keypair { bytes })
+    }
+
+    pub fn pubkey(&self) -> &[u8; 32] {
+        self.bytes[32..].try_into().unwrap()
+    }
+
+    pub fn pubkey_base58(&self) -> String {
+        bs58::encode(self.pubkey()).into_string()
+    }
+
+    pub fn signing_key(&self) -> Ed25519KeyPair {
+        Ed25519KeyPair::from_seed_and_public_key(&self.bytes[..32], &self.bytes[32..]).expect("checked in parse")
+    }
+
+    /// Self-signed ed25519 certificate for this identity, as agave's QUIC client builds it:
+    /// CN "Solana node", the unspecified address as its only SAN. Validators only look at
+    /// the certificate's public key to find our stake.
+    fn certificate(&self) -> Result<(rcgen::Certificate, rcgen::KeyPair), rcgen::Error> {
+        let mut pkcs8 = Vec::with_capacity(PKCS8_PREFIX.len() + 32);
+        pkcs8.extend_from_slice(&PKCS8_PREFIX);
+        pkcs8.extend_from_slice(&self.bytes[..32]);
+        let key = rcgen::KeyPair::from_pkcs8_der_and_sign_algo(&pkcs8.into(), &rcgen::PKCS_ED25519)?;
+
+        let mut params = rcgen::CertificateParams::default();
+        params.subject_alt_names = vec![rcgen::SanType::IpAddress(IpAddr::V4(Ipv4Addr::UNSPECIFIED))];
+        params.distinguished_name = rcgen::DistinguishedName::new();
+        params.distinguished_name.push(rcgen::DnType::CommonName, "Solana node");
+        Ok((params.self_signed(&key)?, key))
+    }
+
+    /// Present this identity as the client certificate. quiche only loads PEM files (without
+    /// its `boring` feature), so the certificate and key go through a private temporary file
+    /// of this call's own that is removed right after; workers load theirs concurrently.
+    pub fn load_into(&self, config: &mut quiche::Config) -> io::Result<()> {
+        let (cert, key) = self.certificate().map_err(|e| invalid(format!("rcgen: {}", e)))?;
+        let file = TempFile::create(&self.pubkey_base58(), format!("{}{}", cert.pem(), key.serialize_pem()).as_bytes())?;
+
+        let path = file.0.to_str().ok_or_else(|| invalid("temp dir is not UTF-8"))?;
+        config.load_cert_chain_from_pem_file(path).map_err(|e| invalid(format!("load_cert_chain_from_pem_file: {}", e)))?;
+        config.load_priv_key_from_pem_file(path).map_err(|e| invalid(format!("load_priv_key_from_pem_file: {}", e)))?;
+        Ok(())
+    }
+}
+
+/// Base58 pubkey (e.g. a validator identity from `solana gossip`) or blockhash.
+pub fn parse_pubkey(text: &str) -> io::Result<[u8; 32]> {
+    let bytes = bs58::decode(text.trim()).into_vec().map_err(|e| invalid(format!("{:?} is not base58: {}", text, e)))?;
+    bytes.try_into().map_err(|b: Vec<u8>| invalid(format!("{:?} is {} bytes, expected 32", text, b.len())))
+}
+
+/// The ed25519 key a DER certificate was issued for, if it is an ed25519 certificate.
+/// Only the TBSCertificate's subjectPublicKeyInfo counts (RFC 8410, section 4), whatever
+/// the names or extensions carry.
+pub fn cert_ed25519_pubkey(der: &[u8]) -> Option<[u8; 32]> {
+    let (rest, cert) = x509_parser::parse_x509_certificate(der).ok()?;
+    let spki = cert.public_key();
+    if !rest.is_empty() || spki.algorithm.algorithm != OID_SIG_ED25519 || spki.subject_public_key.unused_bits != 0 {
+        return None;
+    }
+    spki.subject_public_key.data.as_ref().try_into().ok()
+}
+
+/// Owner-only file in the temp dir that is deleted on drop. Each one gets a fresh name, and
+/// an existing file is never opened or removed.
+struct TempFile(PathBuf);
+
+impl TempFile {
+    fn create(name: &str, contents: &[u8]) -> io::Result<Self> {
+        static NEXT: AtomicUsize = AtomicUsize::new(0);
+        loop {
+            let n = NEXT.fetch_add(1, Ordering::Relaxed);
+            let path = std::env::temp_dir().join(format!("afterburner-{}-{}-{}.pem", std::process::id(), n, name));
+            let mut f = match OpenOptions::new().write(true).create_new(true).mode(0o600).open(&path) {
+                // Left over from an earlier process with our pid
+                Err(e) if e.kind() == io::ErrorKind::AlreadyExists => continue,
+                r => r?,
+            };
+            let file = TempFile(path);
+            f.write_all(contents)?;
+            return Ok(file);
+        }
+    }
+}
+
+impl Drop for TempFile {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.0);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ring::signature::KeyPair as _;
+
+    // solana-keygen output for a fixed seed of 0x01..=0x20
+    fn keygen_bytes() -> Vec<u8> {
+        let seed: Vec<u8> = (1..=32).collect();
+        let pair = Ed25519KeyPair::from_seed_unchecked(&seed).unwrap();
+        [&seed[..], pair.public_key().as_ref()].concat()
+    }
+
+    #[test]
+    fn parses_json_and_base58() {
+        let bytes = keygen_bytes();
+        let json = format!("[{}]\n", bytes.iter().map(u8::to_string).collect::<Vec<_>>().join(","));
+        let from_json = Keypair::parse(&json).unwrap();
+        let from_b58 = Keypair::parse(&bs58::encode(&bytes).into_string()).unwrap();
+        assert_eq!(from_json.pubkey(), from_b58.pubkey());
+        assert_eq!(&from_json.pubkey()[..], &bytes[32..]);
+    }
+
+    #[test]
+    fn rejects_mismatched_or_short_keys() {
+        let mut bytes = keygen_bytes();
+        assert!(Keypair::parse(&bs58::encode(&bytes[..63]).into_string()).is_err());
+        bytes[40] ^= 1;
+        assert!(Keypair::parse(&bs58::encode(&bytes).into_string()).is_err());
+        assert!(Keypair::parse("[1, 2, 300]").is_err());
+    }
+
+    #[test]
+    fn certificate_carries_the_identity() {
+        let keypair = Keypair::parse(&bs58::encode(keygen_bytes()).into_string()).unwrap();
+        let (cert, key) = keypair.certificate().unwrap();
+        assert_eq!(key.public_key_raw(), &keypair.pubkey()[..]);
+        assert_eq!(cert_ed25519_pubkey(cert.der()).as_ref(), Some(keypair.pubkey()));
+        assert_eq!(parse_pubkey(&keypair.pubkey_base58()).unwrap(), *keypair.pubkey());
+
+        // Not ed25519: no key to pin against
+        let other = rcgen::generate_simple_self_signed(vec!["localhost".to_string()]).unwrap();
+        assert_eq!(cert_ed25519_pubkey(other.cert.der()), None);
+        assert_eq!(cert_ed25519_pubkey(b"\x30\x03garbage"), None);
+    }
+
+    #[test]
+    fn temp_files_are_private_and_per_call() {
+        use std::os::unix::fs::PermissionsExt;
+
+        // Two workers loading the same identity at once
+        let (a, b) = (TempFile::create("same", b"a").unwrap(), TempFile::create("same", b"b").unwrap());
+        assert_ne!(a.0, b.0);
+        assert_eq!(fs::metadata(&a.0).unwrap().permissions().mode() & 0o777, 0o600);
+
+        let path = a.0.clone();
+        drop(a);
+        assert!(!path.exists());
+        assert_eq!(fs::read(&b.0).unwrap(), b"b");
+    }
+
+    #[test]
+    fn names_cannot_smuggle_a_key() {
+        // The victim's SubjectPublicKeyInfo, as it would appear in its own certificate
+        let victim = [0x07; 32];
+        let mut spki = vec![0x30, 0x2a, 0x30, 0x05, 0x06, 0x03, 0x2b, 0x65, 0x70, 0x03, 0x21, 0x00];
+        spki.extend_from_slice(&victim);
+
+        // An attacker's own ed25519 certificate with those bytes in its (issuer and subject) CN,
+        // which both come before the real subjectPublicKeyInfo
+        let attacker = rcgen::KeyPair::generate_for(&rcgen::PKCS_ED25519).unwrap();
+        let mut params = rcgen::CertificateParams::default();
+        params.distinguished_name = rcgen::DistinguishedName::new();
+        let cn = rcgen::BmpString::from_utf16be(spki.clone()).unwrap();
+        params.distinguished_name.push(rcgen::DnType::CommonName, rcgen::DnValue::BmpString(cn));
+        let cert = params.self_signed(&attacker).unwrap();
+
+        let der = cert.der();
+        let smuggled = der.windows(spki.len()).position(|w| w == spki).expect("SPKI bytes in the DN");
+        let real = der.windows(attacker.public_key_raw().len()).position(|w| w == attacker.public_key_raw()).unwrap();
+        assert!(smuggled < real);
+        assert_eq!(cert_ed25519_pubkey(der).as_ref().map(|k| &k[..]), Some(attacker.public_key_raw()));
+    }
+}
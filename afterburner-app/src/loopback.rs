@@ -0,0 +1,334 @@
+use std::cell::{Cell, RefCell};
+use std::collections::VecDeque;
+use std::net::SocketAddr;
+use std::ops::{Deref, DerefMut};
+use std::rc::Rc;
+use std::time::Duration;
+use crate::packet_io::{PacketIo, TxPacket};
+
+// Largest UDP payload; anything over the link MTU is dropped on submit, not truncated
+const MAX_DATAGRAM: usize = 65_507;
+
+/// Virtual time shared by both ends of a pair. It only moves when the test advances it, and
+/// only schedules link delivery: quiche's own timers (PTO, idle, pacing) still use `Instant`.
+#[derive(Clone, Default)]
+pub struct Clock(Rc<Cell<Duration>>);
+
+impl Clock {
+    pub fn now(&self) -> Duration {
+        self.0.get()
+    }
+
+    pub fn advance(&self, by: Duration) {
+        self.0.set(self.0.get() + by);
+    }
+}
+
+/// Impairments applied to one direction of a pair.
+#[derive(Clone, Debug)]
+pub struct LinkConfig {
+    pub latency: Duration,
+    /// Probability (0..=1) that a datagram is dropped
+    pub loss: f64,
+    /// Probability (0..=1) that a datagram is held back one extra `latency` (min 1 µs)
+    pub reorder: f64,
+    /// Largest datagram the link carries
+    pub mtu: usize,
+    pub seed: u64,
+}
+
+impl Default for LinkConfig {
+    fn default() -> Self {
+        LinkConfig { latency: Duration::ZERO, loss: 0.0, reorder: 0.0, mtu: 1500, seed: 1 }
+    }
+}
+
+/// What happened to the datagrams sent over one direction.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct LinkStats {
+    pub sent: u64,
+    pub delivered: u64,
+    pub lost: u64,
+    pub oversized: u64,
+    pub reordered: u64,
+}
+
+struct Link {
+    config: LinkConfig,
+    rng: u64,
+    // Kept sorted by delivery time; ties keep send order
+    in_flight: VecDeque<(Duration, SocketAddr, Vec<u8>)>,
+    stats: LinkStats,
+}
+
+impl Link {
+    fn new(config: LinkConfig) -> Self {
+        Link { rng: config.seed.max(1), config, in_flight: VecDeque::new(), stats: LinkStats::default() }
+    }
+
+    // xorshift64: deterministic per seed, no extra dependency
+    fn roll(&mut self) -> f64 {
+        self.rng ^= self.rng << 13;
+        self.rng ^= self.rng >> 7;
+        self.rng ^= self.rng << 17;
+        (self.rng >> 11) as f64 / (1u64 << 53) as f64
+    }
+
+    fn send(&mut self, from: SocketAddr, datagram: Vec<u8>, now: Duration) {
+        self.stats.sent += 1;
+        if datagram.len() > self.config.mtu {
+            self.stats.oversized += 1;
+            return;
+        }
+        if self.roll() < self.config.loss {
+            self.stats.lost += 1;
+            return;
+        }
+
+        let mut at = now + self.config.latency;
+        if self.roll() < self.config.reorder {
+            at += self.config.latency.max(Duration::from_micros(1));
+            self.stats.reordered += 1;
+        }
+        let pos = self.in_flight.partition_point(|(t, _, _)| *t <= at);
+        self.in_flight.insert(pos, (at, from, datagram));
+    }
+
+    fn recv(&mut self, now: Duration) -> Option<(SocketAddr, Vec<u8>)> {
+        if self.in_flight.front()?.0 > now { return None; }
+        self.stats.delivered += 1;
+        self.in_flight.pop_front().map(|(_, from, d)| (from, d))
+    }
+}
+
+/// Both directions to one far end. `to` picks the port for an outgoing datagram; `None`
+/// takes everything.
+struct Port {
+    to: Option<SocketAddr>,
+    outbound: Rc<RefCell<Link>>,
+    inbound: Rc<RefCell<Link>>,
+}
+
+/// In-process `PacketIo` endpoint. Endpoints made by `pair` or `star` exchange datagrams over
+/// simulated links driven by a virtual `Clock`, so the QUIC stack runs without root or a netns.
+pub struct Loopback {
+    clock: Clock,
+    ports: Vec<Port>,
+    rx_buf: Vec<u8>,
+    tx_buf: Vec<u8>,
+    // (from, to, datagram)
+    tx_pending: Vec<(SocketAddr, SocketAddr, Vec<u8>)>,
+}
+
+/// Links both ways for one pair of endpoints; the second direction gets the next seed.
+fn links(config: &LinkConfig, seed: u64) -> (Rc<RefCell<Link>>, Rc<RefCell<Link>>) {
+    let there = Rc::new(RefCell::new(Link::new(LinkConfig { seed, ..config.clone() })));
+    let back = Rc::new(RefCell::new(Link::new(LinkConfig { seed: seed.wrapping_add(1), ..config.clone() })));
+    (there, back)
+}
+
+fn endpoint(clock: &Clock, ports: Vec<Port>) -> Loopback {
+    Loopback {
+        clock: clock.clone(),
+        ports,
+        rx_buf: Vec::with_capacity(MAX_DATAGRAM),
+        tx_buf: vec![0; MAX_DATAGRAM],
+        tx_pending: Vec::new(),
+    }
+}
+
+/// Two endpoints joined back to back; `config` applies to both directions.
+pub fn pair(config: LinkConfig, clock: &Clock) -> (Loopback, Loopback) {
+    let (a_to_b, b_to_a) = links(&config, config.seed);
+    (
+        endpoint(clock, vec![Port { to: None, outbound: Rc::clone(&a_to_b), inbound: Rc::clone(&b_to_a) }]),
+        endpoint(clock, vec![Port { to: None, outbound: b_to_a, inbound: a_to_b }]),
+    )
+}
+
+/// A hub joined to one endpoint per address in `peers`, returned in the same order. The hub
+/// sends each datagram down the link to its destination and drops those for other addresses.
+pub fn star(config: LinkConfig, clock: &Clock, peers: &[SocketAddr]) -> (Loopback, Vec<Loopback>) {
+    let mut hub = Vec::new();
+    let mut spokes = Vec::new();
+    for (i, peer) in peers.iter().enumerate() {
+        let (out, back) = links(&config, config.seed.wrapping_add(2 * i as u64));
+        hub.push(Port { to: Some(*peer), outbound: Rc::clone(&out), inbound: Rc::clone(&back) });
+        spokes.push(endpoint(clock, vec![Port { to: None, outbound: back, inbound: out }]));
+    }
+    (endpoint(clock, hub), spokes)
+}
+
+impl Loopback {
+    /// Change the impairments on this end's outbound links. The RNG state is kept.
+    pub fn set_link(&self, config: LinkConfig) {
+        for port in &self.ports {
+            port.outbound.borrow_mut().config = config.clone();
+        }
+    }
+
+    /// Summed over this end's outbound links.
+    pub fn outbound_stats(&self) -> LinkStats {
+        self.ports.iter().fold(LinkStats::default(), |sum, port| {
+            let s = port.outbound.borrow().stats;
+            LinkStats {
+                sent: sum.sent + s.sent,
+                delivered: sum.delivered + s.delivered,
+                lost: sum.lost + s.lost,
+                oversized: sum.oversized + s.oversized,
+                reordered: sum.reordered + s.reordered,
+            }
+        })
+    }
+}
+
+impl PacketIo for Loopback {
+    type Rx<'a> = &'a mut [u8];
+    type Tx<'a> = LoopbackTx<'a>;
+
+    fn poll_rx(&mut self) -> Option<(SocketAddr, &mut [u8])> {
+        let now = self.clock.now();
+        let (from, datagram) = self.ports.iter().find_map(|port| port.inbound.borrow_mut().recv(now))?;
+        self.rx_buf = datagram;
+        Some((from, &mut self.rx_buf))
+    }
+
+    fn get_tx_frame(&mut self) -> Option<LoopbackTx<'_>> {
+        Some(LoopbackTx { io: self })
+    }
+
+    fn tx_submit(&mut self) -> usize {
+        let now = self.clock.now();
+        let n = self.tx_pending.len();
+        for (from, to, datagram) in self.tx_pending.drain(..) {
+            if let Some(port) = self.ports.iter().find(|port| port.to.is_none_or(|addr| addr == to)) {
+                port.outbound.borrow_mut().send(from, datagram, now);
+            }
+        }
+        n
+    }
+}
+
+pub struct LoopbackTx<'a> {
+    io: &'a mut Loopback,
+}
+
+impl Deref for LoopbackTx<'_> {
+    type Target = [u8];
+    fn deref(&self) -> &[u8] { &self.io.tx_buf }
+}
+
+impl DerefMut for LoopbackTx<'_> {
+    fn deref_mut(&mut self) -> &mut [u8] { &mut self.io.tx_buf }
+}
+
+impl TxPacket for LoopbackTx<'_> {
+    fn submit(self, len: usize, from: SocketAddr, to: SocketAddr) {
+        self.io.tx_pending.push((from, to, self.io.tx_buf[..len].to_vec()));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ADDR: SocketAddr = SocketAddr::new(std::net::IpAddr::V4(std::net::Ipv4Addr::LOCALHOST), 9);
+
+    fn send(io: &mut Loopback, payload: &[u8]) {
+        let mut frame = io.get_tx_frame().unwrap();
+        frame[..payload.len()].copy_from_slice(payload);
+        frame.submit(payload.len(), ADDR, ADDR);
+        io.tx_submit();
+    }
+
+    fn recv(io: &mut Loopback) -> Option<Vec<u8>> {
+        io.poll_rx().map(|(_, p)| p.to_vec())
+    }
+
+    #[test]
+    fn delivers_after_latency() {
+        let clock = Clock::default();
+        let (mut a, mut b) = pair(LinkConfig { latency: Duration::from_millis(1), ..Default::default() }, &clock);
+
+        send(&mut a, b"ping");
+        assert_eq!(recv(&mut b), None);
+        clock.advance(Duration::from_micros(999));
+        assert_eq!(recv(&mut b), None);
+        clock.advance(Duration::from_micros(1));
+        assert_eq!(recv(&mut b).as_deref(), Some(&b"ping"[..]));
+        assert_eq!(recv(&mut a), None);
+    }
+
+    #[test]
+    fn drops_datagrams_over_mtu() {
+        let clock = Clock::default();
+        let (mut a, mut b) = pair(LinkConfig { mtu: 100, ..Default::default() }, &clock);
+
+        send(&mut a, &[0; 101]);
+        send(&mut a, &[1; 100]);
+        assert_eq!(recv(&mut b), Some(vec![1; 100]));
+        assert_eq!(recv(&mut b), None);
+        assert_eq!(a.outbound_stats().oversized, 1);
+    }
+
+    #[test]
+    fn loss_is_seeded_and_roughly_proportional() {
+        let run = || {
+            let clock = Clock::default();
+            let (mut a, mut b) = pair(LinkConfig { loss: 0.25, seed: 7, ..Default::default() }, &clock);
+            let received: Vec<u8> = (0..1000u32)
+                .filter_map(|i| {
+                    send(&mut a, &i.to_le_bytes());
+                    recv(&mut b).map(|p| p[0])
+                })
+                .collect();
+            (received, a.outbound_stats())
+        };
+
+        let (first, stats) = run();
+        assert_eq!(first, run().0);
+        assert_eq!(stats.lost + stats.delivered, 1000);
+        assert!((150..350).contains(&stats.lost), "lost {}", stats.lost);
+    }
+
+    #[test]
+    fn star_routes_by_destination() {
+        let clock = Clock::default();
+        let peers: [SocketAddr; 2] = ["10.0.0.11:8004".parse().unwrap(), "10.0.0.12:8004".parse().unwrap()];
+        let (mut hub, mut spokes) = star(LinkConfig::default(), &clock, &peers);
+
+        for (to, payload) in [(peers[1], b"to b"), (peers[0], b"to a"), (ADDR, b"nowh")] {
+            let mut frame = hub.get_tx_frame().unwrap();
+            frame[..4].copy_from_slice(payload);
+            frame.submit(4, ADDR, to);
+        }
+        hub.tx_submit();
+        assert_eq!(recv(&mut spokes[0]).as_deref(), Some(&b"to a"[..]));
+        assert_eq!(recv(&mut spokes[1]).as_deref(), Some(&b"to b"[..]));
+        assert_eq!((recv(&mut spokes[0]), recv(&mut spokes[1])), (None, None));
+
+        // Replies from either end come out of the hub
+        send(&mut spokes[1], b"from b");
+        send(&mut spokes[0], b"from a");
+        let mut got = vec![recv(&mut hub).unwrap(), recv(&mut hub).unwrap()];
+        got.sort();
+        assert_eq!(got, [b"from a".to_vec(), b"from b".to_vec()]);
+        assert_eq!(hub.outbound_stats().sent, 2);
+    }
+
+    #[test]
+    fn reordered_datagrams_arrive_after_later_ones() {
+        let clock = Clock::default();
+        let latency = Duration::from_millis(1);
+        let (mut a, mut b) = pair(LinkConfig { latency, reorder: 1.0, ..Default::default() }, &clock);
+
+        send(&mut a, b"first");
+        a.set_link(LinkConfig { latency, ..Default::default() });
+        send(&mut a, b"second");
+
+        clock.advance(latency * 2);
+        assert_eq!(recv(&mut b).as_deref(), Some(&b"second"[..]));
+        assert_eq!(recv(&mut b).as_deref(), Some(&b"first"[..]));
+        assert_eq!(a.outbound_stats().reordered, 1);
+    }
+}
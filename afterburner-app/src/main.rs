@@ -1,20 +1,231 @@
-use std::net::SocketAddr;
-use std::sync::atomic::{AtomicBool, Ordering};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::sync::atomic::AtomicBool;
 use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
 use clap::Parser;
-use aya::{programs::{Xdp, XdpFlags}, maps::XskMap, Ebpf};
+use aya::{programs::{Xdp, XdpFlags}, maps::{HashMap, MapData, XskMap}, Ebpf};
+use afterburner_common::{RedirectKey, RedirectRule, XSK_MAX_QUEUES};
+use keypair::Keypair;
+use quic_driver::{ClientTls, PeerVerify};
+use neigh::{LinkAddrs, Resolver};
+use packet_io::{PacketIo, XdpIo};
+
+// How long startup waits for an ARP/NDP reply before falling back to broadcast
+const SOLICIT_TIMEOUT: Duration = Duration::from_secs(2);
 
 mod xsk;
 mod headers;
 mod quic_driver;
+mod keypair;
+mod session;
+mod signer;
 mod emit;
 mod flood;
+mod ingest;
+mod pacer;
+mod spsc;
+mod nic;
+mod neigh;
+mod worker;
+mod packet_io;
+mod udp;
+#[cfg(test)]
+mod loopback;
+#[cfg(test)]
+mod fixture;
 
 #[derive(Parser, Debug)]
 #[command(author, version, about, long_about = None)]
 struct Args {
+    /// Interface to attach XDP to (required for the xdp backend)
     #[arg(short, long)]
-    iface: String,
+    iface: Option<String>,
+
+    /// Packet I/O backend: AF_XDP bypass, or a plain kernel UDP socket (no root needed)
+    #[arg(long, value_enum, default_value = "xdp")]
+    backend: Backend,
+
+    /// UDP addresses to bypass, comma separated; the first is the QUIC client's local address.
+    /// An unspecified IP (0.0.0.0) matches the port on any address
+    #[arg(long, value_delimiter = ',', default_value = "10.0.0.10:8000")]
+    listen: Vec<SocketAddr>,
+
+    /// Only bypass packets from this source network, e.g. `10.0.0.0/24` or `fd00::/64`
+    #[arg(long, value_parser = parse_prefix)]
+    source_prefix: Option<(IpAddr, u8)>,
+
+    /// QUIC server (TPU) addresses, comma separated; each worker connects to all of them.
+    /// Must match the family of the first `--listen` and, for xdp, share its next hop
+    #[arg(long, value_delimiter = ',', default_value = "10.0.0.11:8004")]
+    peer: Vec<SocketAddr>,
+
+    /// Solana keypair file (JSON byte array or base58) to present as the client certificate,
+    /// so validators apply our stake-weighted QoS; without it we connect unstaked
+    #[arg(long)]
+    identity: Option<std::path::PathBuf>,
+
+    /// Server certificate check
+    #[arg(long, value_enum, default_value = "none")]
+    verify: VerifyMode,
+
+    /// PEM CA bundle for `--verify ca`
+    #[arg(long)]
+    ca_file: Option<std::path::PathBuf>,
+
+    /// Validator identity (base58 pubkey) of each `--peer`, in the same order, for `--verify pin`
+    #[arg(long, value_delimiter = ',')]
+    peer_identity: Vec<String>,
+
+    /// Keep TLS session tickets here too, so 0-RTT also works right after a restart
+    #[arg(long)]
+    session_dir: Option<std::path::PathBuf>,
+
+    /// Keypair files that sign the flood transactions, taking turns as fee payer.
+    /// Without any, an unsigned transfer is sent
+    #[arg(long, value_delimiter = ',', requires = "blockhash")]
+    signer: Vec<std::path::PathBuf>,
+
+    /// Transactions signed before flooding starts; each is sent once, then flooding stops
+    #[arg(long, default_value_t = 4096, value_parser = clap::builder::RangedU64ValueParser::<usize>::new().range(1..))]
+    presign: usize,
+
+    /// Recent blockhash (base58) for the signed transactions; required by `--signer`
+    #[arg(long)]
+    blockhash: Option<String>,
+
+    /// Flood transactions signed elsewhere: `unix-dgram:<path>`, `unix-stream:<path>`,
+    /// `file:<path>` or `-` for stdin
+    #[arg(long, value_parser = ingest::Source::parse, conflicts_with = "signer")]
+    ingest: Option<ingest::Source>,
+
+    /// Framing of --ingest files and stdin (unix-stream is always binary)
+    #[arg(long, value_enum, default_value = "base64")]
+    ingest_format: ingest::Framing,
+
+    /// Target transactions per second across all workers (token bucket). Without it the
+    /// flooder sends --burst per connection on every loop iteration
+    #[arg(long, value_parser = parse_tps)]
+    tps: Option<f64>,
+
+    /// Most transactions sent back to back (default: 1ms worth of --tps, at least 4)
+    #[arg(long, value_parser = clap::builder::RangedU64ValueParser::<usize>::new().range(1..))]
+    burst: Option<usize>,
+
+    /// Step up to --tps: start at STEP TPS and add STEP every SECS, e.g. `5000/10s`
+    #[arg(long, value_parser = pacer::Ramp::parse, requires = "tps")]
+    ramp: Option<pacer::Ramp>,
+
+    /// Run one pinned busy-poll worker per RX queue instead of a single loop
+    #[arg(long)]
+    workers: bool,
+
+    /// Cores to pin to, e.g. `2,4-7` (one per worker; defaults to the NIC's NUMA node)
+    #[arg(long)]
+    cores: Option<String>,
+
+    /// XDP attach mode (default: native if the driver supports it, else generic)
+    #[arg(long, value_enum)]
+    xdp_mode: Option<XdpMode>,
+
+    /// AF_XDP bind mode
+    #[arg(long, value_enum, default_value = "auto")]
+    bind: xsk::BindMode,
+
+    /// UDP checksum on transmitted datagrams (xdp backend). `offload` needs Linux 6.11+
+    /// and, in zero-copy mode, a driver with AF_XDP TX checksum offload
+    #[arg(long, value_enum, default_value = "zero")]
+    udp_checksum: headers::UdpChecksum,
+
+    #[command(flatten)]
+    umem: UmemArgs,
+}
+
+/// UMEM geometry overrides; anything left unset keeps the `XskConfig` default.
+#[derive(clap::Args, Debug)]
+struct UmemArgs {
+    /// UMEM size in bytes per socket (default 8 MiB)
+    #[arg(long)]
+    umem_size: Option<usize>,
+
+    /// UMEM frame (chunk) size: 2048 or 4096
+    #[arg(long)]
+    frame_size: Option<usize>,
+
+    /// Fill ring entries (power of two)
+    #[arg(long)]
+    fill_ring: Option<u32>,
+
+    /// Completion ring entries (power of two)
+    #[arg(long)]
+    comp_ring: Option<u32>,
+
+    /// RX ring entries (power of two)
+    #[arg(long)]
+    rx_ring: Option<u32>,
+
+    /// TX ring entries (power of two)
+    #[arg(long)]
+    tx_ring: Option<u32>,
+
+    /// Frames given to RX; the rest are used for TX (default: half)
+    #[arg(long)]
+    rx_frames: Option<usize>,
+
+    /// Headroom in bytes reserved in front of each RX packet
+    #[arg(long)]
+    headroom: Option<u32>,
+}
+
+impl UmemArgs {
+    fn to_config(&self, bind: xsk::BindMode) -> xsk::XskConfig {
+        let mut config = xsk::XskConfig::default().bind_mode(bind);
+        if let Some(v) = self.umem_size { config = config.umem_size(v); }
+        if let Some(v) = self.frame_size { config = config.frame_size(v); }
+        if let Some(v) = self.fill_ring { config = config.fill_ring_size(v); }
+        if let Some(v) = self.comp_ring { config = config.comp_ring_size(v); }
+        if let Some(v) = self.rx_ring { config = config.rx_ring_size(v); }
+        if let Some(v) = self.tx_ring { config = config.tx_ring_size(v); }
+        if let Some(v) = self.rx_frames { config = config.rx_frames(v); }
+        if let Some(v) = self.headroom { config = config.headroom(v); }
+        config
+    }
+}
+
+#[derive(Clone, Copy, Debug, clap::ValueEnum)]
+enum Backend {
+    Xdp,
+    Udp,
+}
+
+#[derive(Clone, Copy, Debug, clap::ValueEnum)]
+enum VerifyMode {
+    /// Accept any server certificate (validators' are self-signed)
+    None,
+    /// Require a chain to a CA in `--ca-file`
+    Ca,
+    /// Require each peer's certificate key to be its `--peer-identity`
+    Pin,
+}
+
+#[derive(Clone, Copy, Debug, clap::ValueEnum)]
+enum XdpMode {
+    /// Generic XDP in the kernel stack (XDP_FLAGS_SKB_MODE), never zero-copy
+    Skb,
+    /// Native XDP in the driver (XDP_FLAGS_DRV_MODE)
+    Drv,
+    /// Offloaded to the NIC (XDP_FLAGS_HW_MODE)
+    Hw,
+}
+
+impl XdpMode {
+    fn flags(self) -> XdpFlags {
+        match self {
+            XdpMode::Skb => XdpFlags::SKB_MODE,
+            XdpMode::Drv => XdpFlags::DRV_MODE,
+            XdpMode::Hw => XdpFlags::HW_MODE,
+        }
+    }
 }
 
 fn main() {
@@ -23,76 +234,339 @@ fn main() {
     let term = Arc::new(AtomicBool::new(false));
     signal_hook::flag::register(signal_hook::consts::SIGINT, Arc::clone(&term)).expect("register");
 
-    println!("Starting Afterburner QUIC on: {}", args.iface);
+    let (local, peers) = (args.listen[0], &args.peer[..]);
+    if let Some(peer) = peers.iter().find(|p| p.is_ipv4() != local.is_ipv4()) {
+        panic!("--peer {} and the first --listen {} must be the same IP version", peer, local);
+    }
+
+    let identity = args.identity.as_ref().map(|path| {
+        let keypair = Keypair::read_file(path).unwrap_or_else(|e| panic!("--identity {}: {}", path.display(), e));
+        println!("[QUIC] Identity {}", keypair.pubkey_base58());
+        keypair
+    });
+    let sessions = session::SessionCache::new(args.session_dir.clone(), identity.as_ref())
+        .unwrap_or_else(|e| panic!("--session-dir: {}", e));
+    let tls = ClientTls { identity, verify: peer_verify(&args, peers), sessions };
+    let txs = (!args.signer.is_empty()).then(|| flood_transactions(&args));
+    let ingest = args.ingest.clone().map(|source| (source, args.ingest_format));
+    let pace = pacer::PaceConfig { tps: args.tps, burst: args.burst, ramp: args.ramp };
+    let quic = QuicSetup { local, peers: peers.to_vec(), tls, txs, ingest, pace };
+
+    let total_tx = match args.backend {
+        Backend::Xdp => run_xdp(&args, quic, &term),
+        Backend::Udp => run_udp(&args, quic, &term),
+    };
+
+    println!("Shutting down. Total TX Sent: {}", total_tx);
+}
+
+fn run_xdp(args: &Args, mut quic: QuicSetup, term: &Arc<AtomicBool>) -> u64 {
+    let iface = args.iface.as_deref().expect("--iface is required with --backend xdp");
+    println!("Starting Afterburner QUIC on: {}", iface);
+
+    // Frames carry a single destination MAC, so the first peer picks the next hop
+    let peer = quic.peers[0];
+    let resolver = match Resolver::new(iface, peer.ip()) {
+        Ok(resolver) => {
+            let i = resolver.interface();
+            println!("[NEIGH] {} is {} (ifindex {}), next hop to {} is {}", iface, neigh::fmt_mac(&i.mac), i.index, peer.ip(), resolver.next_hop());
+            Some(Arc::new(resolver))
+        }
+        Err(e) => {
+            println!("[NEIGH] Can't resolve {} ({}), sending to the broadcast MAC", peer.ip(), e);
+            None
+        }
+    };
+    if let Some(ip) = resolver.as_ref().and_then(|r| r.source_ip()).filter(|_| quic.local.ip().is_unspecified()) {
+        quic.local.set_ip(ip);
+    }
+    let watcher = resolver.clone().map(|r| {
+        let term = Arc::clone(term);
+        thread::Builder::new().name("neigh".into()).spawn(move || r.watch(&term)).expect("spawn neigh")
+    });
+    let frames = FrameSetup {
+        local: quic.local,
+        link: resolver.as_ref().map_or_else(|| Arc::new(LinkAddrs::default()), |r| r.link()),
+        resolver,
+        checksum: args.udp_checksum,
+    };
 
     let ebpf_path = std::path::Path::new("target/bpfel-unknown-none/release/afterburner");
     let mut bpf = Ebpf::load_file(ebpf_path).expect("Ebpf::load_file");
     
     let program: &mut Xdp = bpf.program_mut("afterburner").unwrap().try_into().expect("try_into");
     program.load().expect("load");
-    program.attach(&args.iface, XdpFlags::default()).expect("attach");
-    println!("[XDP] eBPF program attached to {}", args.iface);
+    let xdp_flags = args.xdp_mode.map_or_else(XdpFlags::default, XdpMode::flags);
+    program.attach(iface, xdp_flags).expect("attach");
+    match args.xdp_mode {
+        Some(mode) => println!("[XDP] eBPF program attached to {} ({:?} mode)", iface, mode),
+        None => println!("[XDP] eBPF program attached to {}", iface),
+    }
+
+    let mut queues = nic::rx_queue_count(iface).expect("rx_queue_count");
+    if queues > XSK_MAX_QUEUES {
+        println!("[XSK] {} has {} RX queues, only the first {} are bypassed", iface, queues, XSK_MAX_QUEUES);
+        queues = XSK_MAX_QUEUES;
+    }
+
+    let numa_node = nic::numa_node(iface);
+    let cores = match &args.cores {
+        Some(list) => Some(nic::parse_cores(list).unwrap_or_else(|e| panic!("--cores: {}", e))),
+        None if args.workers => numa_node.and_then(|node| nic::node_cpus(node).ok()),
+        None => None,
+    };
+    if let Some(node) = numa_node {
+        println!("[NUMA] {} is on node {}, UMEM will be allocated there", iface, node);
+    }
+
+    let xsk_config = args.umem.to_config(args.bind).tx_checksum_offload(args.udp_checksum == headers::UdpChecksum::Offload);
+    xsk_config.validate().unwrap_or_else(|e| panic!("{}", e));
+
+    let mut rules: HashMap<_, RedirectKey, RedirectRule> =
+        HashMap::try_from(bpf.map_mut("REDIRECT_RULES").unwrap()).expect("HashMap::try_from");
+    let rule = args.source_prefix.map_or(RedirectRule::ANY_SOURCE, |(addr, len)| RedirectRule::from_prefix(addr, len));
+    for addr in &args.listen {
+        if let Some((src, _)) = args.source_prefix.filter(|(src, _)| src.is_ipv4() != addr.is_ipv4()) {
+            panic!("--source-prefix {} and --listen {} must be the same IP version", src, addr);
+        }
+        rules.insert(RedirectKey::from(*addr), rule, 0).expect("REDIRECT_RULES insert");
+        match args.source_prefix {
+            Some((src, len)) => println!("[XDP] Redirecting UDP {} from {}/{} to AF_XDP", addr, src, len),
+            None => println!("[XDP] Redirecting UDP {} to AF_XDP", addr),
+        }
+    }
 
-    let mut socket = xsk::XdpSocket::new(&args.iface, 0).expect("XdpSocket::new");
-    
     let mut xsk_map = XskMap::try_from(bpf.map_mut("XSK").unwrap()).expect("XskMap::try_from");
-    xsk_map.set(0, socket.fd, 0).expect("XskMap::set");
-    println!("[XSK] AF_XDP socket registered");
-    
-    let local: SocketAddr = "10.0.0.10:8000".parse().expect("parse local addr");
-    let peer: SocketAddr = "10.0.0.11:8004".parse().expect("parse peer addr");
-    let scid = [0x55; 20];
-    let mut driver = quic_driver::QuicDriver::new(&scid, local, peer);
-    let mut flooder = flood::Flooder::new();
 
-    println!("[RUN] HFT Loop Running (Bidirectional Mode)");
+    // One UMEM shared by every queue; unless told otherwise, RX gets half of it split evenly
+    let socket_config = match args.umem.rx_frames {
+        Some(_) => xsk_config.clone(),
+        None => xsk_config.clone().rx_frames(xsk_config.num_frames() / 2 / queues as usize),
+    };
+    let sockets = bind_queues(iface, &socket_config, queues, numa_node, &mut xsk_map);
 
-    while !term.load(Ordering::Relaxed) {
-        if let Some((addr, len)) = socket.poll_rx() {
-            let ptr = unsafe { socket.umem_ptr.add(addr as usize) };
-            let slice = unsafe { std::slice::from_raw_parts_mut(ptr, len) };
-            if len > 42 {
-                driver.process_input(&mut slice[42..], local, peer);
-            }
+    let total_tx = if args.workers {
+        run_workers(sockets, &frames, cores.as_deref(), &quic, term)
+    } else {
+        if let Some(core) = cores.as_ref().and_then(|c| c.first()) {
+            nic::pin_to_core(*core).expect("pin_to_core");
+            println!("[CPU] Pinned to core {}", core);
         }
 
-        driver.on_timeout();
-        driver.drain_streams();
-        flooder.shoot(&mut driver);
-
-        while let Some(frame) = socket.get_tx_frame() {
-            match driver.write_transmit(&mut frame[42..]) {
-                Some(quic_len) if quic_len > 0 => {
-                    headers::write_headers(frame, quic_len, 8000, 8004);
-                    socket.tx_submit(42 + quic_len);
-                },
-                _ => {
-                    socket.cancel_tx();
-                    break;
-                }
+        let io: Vec<XdpIo> = sockets.into_iter().enumerate().map(|(i, socket)| frames.io(socket, i == 0)).collect();
+
+        let ingest = quic.start_ingest(1).pop().flatten();
+        println!("[RUN] HFT Loop Running (Bidirectional Mode)");
+        quic.worker(0, io, 1, ingest).run(term)
+    };
+
+    if let Some(watcher) = watcher {
+        watcher.join().expect("join neigh");
+    }
+    total_tx
+}
+
+/// How XDP workers address and checksum the frames they build, and which they accept.
+#[derive(Clone)]
+struct FrameSetup {
+    // Received frames to any other address are dropped as `not_ours`
+    local: SocketAddr,
+    resolver: Option<Arc<Resolver>>,
+    link: Arc<LinkAddrs>,
+    checksum: headers::UdpChecksum,
+}
+
+impl FrameSetup {
+    /// Wrap a worker's socket. With `solicit`, first ARP/NDP for the next hop through it
+    /// if the kernel had no neighbor entry; every socket shares the resulting MAC.
+    fn io(&self, mut socket: xsk::XdpSocket, solicit: bool) -> XdpIo {
+        if let Some(resolver) = self.resolver.as_ref().filter(|r| solicit && !r.is_resolved()) {
+            if let Err(e) = resolver.solicit(|frame| socket.send_raw(frame), SOLICIT_TIMEOUT) {
+                println!("[NEIGH] {}, sending to the broadcast MAC until the neighbor table has it", e);
             }
         }
-        
-        std::hint::spin_loop(); 
+        XdpIo::new(socket, self.local, Arc::clone(&self.link), self.checksum)
     }
+}
 
-    println!("Shutting down. Total TX Sent: {}", flooder.tx_count);
-    let _ = driver.conn.close(true, 0, b"done");
-    
-    for _ in 0..16 {
-        if let Some(frame) = socket.get_tx_frame() {
-            match driver.write_transmit(&mut frame[42..]) {
-                Some(quic_len) if quic_len > 0 => {
-                    headers::write_headers(frame, quic_len, 8000, 8004);
-                    socket.tx_submit(42 + quic_len);
-                },
-                _ => {
-                    socket.cancel_tx();
-                    break;
-                }
+/// What each worker's QUIC client gets.
+#[derive(Clone)]
+struct QuicSetup {
+    local: SocketAddr,
+    peers: Vec<SocketAddr>,
+    tls: ClientTls,
+    // Pre-signed flood transactions, shared by every worker
+    txs: Option<Arc<[Vec<u8>]>>,
+    ingest: Option<(ingest::Source, ingest::Framing)>,
+    // Split evenly between the flooding workers
+    pace: pacer::PaceConfig,
+}
+
+impl QuicSetup {
+    /// Of `workers`, how many get peers to flood: all of them, unless there are fewer peers.
+    fn flooders(&self, workers: usize) -> usize {
+        workers.min(self.peers.len()).max(1)
+    }
+
+    /// Start `--ingest`, if given, with a queue for each flooding worker; one entry per worker.
+    fn start_ingest(&self, workers: usize) -> Vec<Option<spsc::Consumer<Vec<u8>>>> {
+        let mut queues: Vec<_> = match &self.ingest {
+            Some((source, framing)) => ingest::start(source, *framing, self.flooders(workers))
+                .unwrap_or_else(|e| panic!("--ingest: {}", e))
+                .into_iter()
+                .map(Some)
+                .collect(),
+            None => Vec::new(),
+        };
+        queues.resize_with(workers, || None);
+        queues
+    }
+
+    /// Worker `id` of `workers`. The peers are split between the workers, one connection
+    /// each, and each flooding worker sends its own slice of the pre-signed batch or drains
+    /// its own ingest queue. Workers left without a peer only route what their queue receives.
+    fn worker<P: PacketIo>(&self, id: usize, sockets: Vec<P>, workers: usize, ingest: Option<spsc::Consumer<Vec<u8>>>) -> worker::Worker<P> {
+        let flooders = self.flooders(workers);
+        let peers: Vec<_> = self.peers.iter().copied().skip(id).step_by(flooders).collect();
+        let worker = worker::Worker::new(id, sockets, self.local, &peers, &self.tls).with_pace(self.pace.split(flooders));
+        match (&self.txs, ingest) {
+            _ if peers.is_empty() => worker,
+            (_, Some(queue)) => worker.with_ingest(queue),
+            (Some(txs), None) => worker.with_transactions(Arc::clone(txs), id * txs.len() / flooders..(id + 1) * txs.len() / flooders),
+            (None, None) => worker,
+        }
+    }
+}
+
+/// Sign `--presign` transfers, paid by the `--signer` keypairs in turn.
+fn flood_transactions(args: &Args) -> Arc<[Vec<u8>]> {
+    let keypairs: Vec<Keypair> = args.signer.iter()
+        .map(|path| Keypair::read_file(path).unwrap_or_else(|e| panic!("--signer {}: {}", path.display(), e)))
+        .collect();
+    let blockhash = args.blockhash.as_deref().expect("--signer requires --blockhash");
+    let blockhash = keypair::parse_pubkey(blockhash).unwrap_or_else(|e| panic!("--blockhash: {}", e));
+    let signer = signer::Signer::new(&keypairs);
+    let payers: Vec<_> = signer.pubkeys().copied().collect();
+
+    // A distinct amount per transaction keeps the signatures (and so the transactions) unique
+    let started = Instant::now();
+    let messages = (0..args.presign).map(|i| emit::Message::transfer(payers[i % payers.len()], flood::RECIPIENT, i as u64 + 1, blockhash));
+    let txs = signer.presign(messages).unwrap_or_else(|e| panic!("presign: {}", e));
+    println!("[EMIT] Pre-signed {} transactions for {} fee payer(s) in {:.1}ms", txs.len(), payers.len(), started.elapsed().as_secs_f64() * 1e3);
+    txs.into()
+}
+
+/// Plain UDP socket on the local port; no eBPF, no special privileges.
+fn run_udp(args: &Args, quic: QuicSetup, term: &Arc<AtomicBool>) -> u64 {
+    if args.workers {
+        println!("[UDP] --workers only applies to the xdp backend, running a single loop");
+    }
+    if let Some(core) = args.cores.as_ref().map(|list| nic::parse_cores(list).unwrap_or_else(|e| panic!("--cores: {}", e))[0]) {
+        nic::pin_to_core(core).expect("pin_to_core");
+        println!("[CPU] Pinned to core {}", core);
+    }
+
+    let any: IpAddr = if quic.local.is_ipv4() { Ipv4Addr::UNSPECIFIED.into() } else { Ipv6Addr::UNSPECIFIED.into() };
+    let bind_addr = SocketAddr::new(any, quic.local.port());
+    let socket = udp::UdpIo::bind(bind_addr).expect("UdpIo::bind");
+    println!("[UDP] Kernel UDP socket bound to {}", socket.local_addr().expect("local_addr"));
+
+    let ingest = quic.start_ingest(1).pop().flatten();
+    println!("[RUN] HFT Loop Running (Bidirectional Mode)");
+    quic.worker(0, vec![socket], 1, ingest).run(term)
+}
+
+
+/// Bind one AF_XDP socket per queue on a single UMEM, so memory stays the same however
+/// many queues are bypassed, and register each in the XSK map.
+fn bind_queues(iface: &str, config: &xsk::XskConfig, queues: u32, numa_node: Option<u32>, xsk_map: &mut XskMap<&mut MapData>) -> Vec<xsk::XdpSocket> {
+    let umem = xsk::Umem::new(config, numa_node).unwrap_or_else(|e| panic!("Umem::new: {}", e));
+    let sockets: Vec<xsk::XdpSocket> = (0..queues)
+        .map(|queue_id| {
+            xsk::XdpSocket::with_umem(iface, queue_id, Arc::clone(&umem), config)
+                .unwrap_or_else(|e| panic!("XdpSocket::with_umem: {}", e))
+        })
+        .collect();
+    for (queue_id, socket) in sockets.iter().enumerate() {
+        xsk_map.set(queue_id as u32, socket.fd, 0).expect("XskMap::set");
+        println!("[XSK] Queue {} bound in {} mode", queue_id, bind_mode_name(socket));
+    }
+    println!("[XSK] {} AF_XDP socket(s) registered", sockets.len());
+    sockets
+}
+
+/// Start one worker per queue, each pinned before it touches its socket.
+fn run_workers(
+    sockets: Vec<xsk::XdpSocket>,
+    frames: &FrameSetup,
+    cores: Option<&[usize]>,
+    quic: &QuicSetup,
+    term: &Arc<AtomicBool>,
+) -> u64 {
+    let workers = sockets.len();
+    let routes = worker::routes(workers);
+    let ingest = quic.start_ingest(workers);
+
+    let handles: Vec<_> = sockets.into_iter().zip(routes).zip(ingest).enumerate().map(|(id, ((socket, routes), ingest))| {
+        let core = cores.map(|c| c[id % c.len()]);
+        let quic = quic.clone();
+        let term = Arc::clone(term);
+        let frames = frames.clone();
+        match core {
+            Some(core) => println!("[CPU] Worker {} pinned to core {}", id, core),
+            None => println!("[CPU] Worker {} unpinned", id),
+        }
+
+        thread::Builder::new().name(format!("xsk-q{}", id)).spawn(move || {
+            if let Some(core) = core {
+                nic::pin_to_core(core).expect("pin_to_core");
             }
-        } else {
-            break;
+            // Worker 0 resolves the next hop for everyone
+            quic.worker(id, vec![frames.io(socket, id == 0)], workers, ingest)
+                .with_routing(routes)
+                .run(&term)
+        }).expect("spawn worker")
+    }).collect();
+
+    println!("[RUN] {} HFT workers running (Bidirectional Mode)", handles.len());
+    handles.into_iter().map(|h| h.join().expect("join worker")).sum()
+}
+
+fn peer_verify(args: &Args, peers: &[SocketAddr]) -> PeerVerify {
+    match args.verify {
+        VerifyMode::None => PeerVerify::None,
+        VerifyMode::Ca => PeerVerify::CaBundle(args.ca_file.clone().expect("--verify ca needs --ca-file")),
+        VerifyMode::Pin => {
+            if args.peer_identity.len() != peers.len() {
+                panic!("--verify pin needs one --peer-identity per --peer ({} given for {} peers)", args.peer_identity.len(), peers.len());
+            }
+            let pins = peers.iter().zip(&args.peer_identity).map(|(peer, key)| {
+                (*peer, keypair::parse_pubkey(key).unwrap_or_else(|e| panic!("--peer-identity: {}", e)))
+            });
+            PeerVerify::Pin(pins.collect())
         }
     }
-}
\ No newline at end of file
+}
+
+fn parse_prefix(s: &str) -> Result<(IpAddr, u8), String> {
+    let (addr, len) = s.split_once('/').unwrap_or((s, ""));
+    let addr: IpAddr = addr.parse().map_err(|e| format!("{}: {}", addr, e))?;
+    let max = if addr.is_ipv4() { 32 } else { 128 };
+    // A bare address is a host route
+    let parsed = if len.is_empty() { Ok(max) } else { len.parse::<u8>() };
+    match parsed {
+        Ok(len) if len <= max => Ok((addr, len)),
+        _ => Err(format!("invalid prefix length: {}", len)),
+    }
+}
+
+fn parse_tps(s: &str) -> Result<f64, String> {
+    match s.parse::<f64>() {
+        Ok(tps) if tps > 0.0 && tps.is_finite() => Ok(tps),
+        _ => Err(format!("invalid rate: {}", s)),
+    }
+}
+
+fn bind_mode_name(socket: &xsk::XdpSocket) -> &'static str {
+    if socket.is_zero_copy() { "zero-copy" } else { "copy" }
+}
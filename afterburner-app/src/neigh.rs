@@ -0,0 +1,667 @@
+use std::ffi::{CStr, CString};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::os::fd::RawFd;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use std::{io, mem, ptr};
+use libc::{close, recv, send, setsockopt, socket, SOL_SOCKET, SO_RCVTIMEO};
+use crate::headers::{self, EthAddrs};
+
+// What the veth test rig was built around, used until the next hop is resolved
+const DEFAULT_SRC_MAC: [u8; 6] = [0x02, 0x00, 0x00, 0x00, 0x00, 0x01];
+const BROADCAST_MAC: [u8; 6] = [0xFF; 6];
+
+const SOLICIT_RETRY: Duration = Duration::from_millis(200);
+const WATCH_POLL: Duration = Duration::from_millis(500);
+// For a whole route or neighbor query, dump included
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(2);
+
+const NLMSG_HDR_LEN: usize = 16;
+const RTMSG_LEN: usize = 12;
+const NDMSG_LEN: usize = 12;
+// Neighbor states that carry a usable link-layer address
+const NUD_VALID: u16 = libc::NUD_REACHABLE | libc::NUD_STALE | libc::NUD_DELAY | libc::NUD_PROBE
+    | libc::NUD_PERMANENT | libc::NUD_NOARP;
+
+/// Ethernet addresses for outgoing frames. The destination follows the next hop's
+/// neighbor entry and can change underneath the workers, which read it per packet.
+pub struct LinkAddrs {
+    src: [u8; 6],
+    dst: AtomicU64,
+}
+
+impl LinkAddrs {
+    pub fn new(src: [u8; 6], dst: [u8; 6]) -> Self {
+        LinkAddrs { src, dst: AtomicU64::new(pack(dst)) }
+    }
+
+    pub fn eth(&self) -> EthAddrs {
+        EthAddrs { src: self.src, dst: unpack(self.dst.load(Ordering::Relaxed)) }
+    }
+
+    /// Returns whether the MAC changed.
+    fn set_dst(&self, mac: [u8; 6]) -> bool {
+        self.dst.swap(pack(mac), Ordering::Relaxed) != pack(mac)
+    }
+}
+
+impl Default for LinkAddrs {
+    fn default() -> Self {
+        LinkAddrs::new(DEFAULT_SRC_MAC, BROADCAST_MAC)
+    }
+}
+
+fn pack(mac: [u8; 6]) -> u64 {
+    let mut bytes = [0; 8];
+    bytes[..6].copy_from_slice(&mac);
+    u64::from_ne_bytes(bytes)
+}
+
+fn unpack(v: u64) -> [u8; 6] {
+    let bytes = v.to_ne_bytes();
+    [bytes[0], bytes[1], bytes[2], bytes[3], bytes[4], bytes[5]]
+}
+
+pub fn fmt_mac(mac: &[u8; 6]) -> String {
+    format!("{:02x}:{:02x}:{:02x}:{:02x}:{:02x}:{:02x}", mac[0], mac[1], mac[2], mac[3], mac[4], mac[5])
+}
+
+/// A local interface's index, MAC and addresses.
+pub struct Interface {
+    pub index: u32,
+    pub mac: [u8; 6],
+    pub ipv4: Option<Ipv4Addr>,
+    /// A global address if there is one, else link-local
+    pub ipv6: Option<Ipv6Addr>,
+}
+
+impl Interface {
+    pub fn query(name: &str) -> io::Result<Interface> {
+        let c_name = CString::new(name)?;
+        let index = unsafe { libc::if_nametoindex(c_name.as_ptr()) };
+        if index == 0 { return Err(io::Error::last_os_error()); }
+
+        let mut iface = Interface { index, mac: [0; 6], ipv4: None, ipv6: None };
+        unsafe {
+            let mut addrs: *mut libc::ifaddrs = ptr::null_mut();
+            if libc::getifaddrs(&mut addrs) != 0 { return Err(io::Error::last_os_error()); }
+
+            let mut cur = addrs;
+            while let Some(ifa) = cur.as_ref() {
+                cur = ifa.ifa_next;
+                if ifa.ifa_addr.is_null() || CStr::from_ptr(ifa.ifa_name) != c_name.as_c_str() { continue; }
+
+                match (*ifa.ifa_addr).sa_family as i32 {
+                    libc::AF_PACKET => {
+                        let ll = &*(ifa.ifa_addr as *const libc::sockaddr_ll);
+                        if ll.sll_halen == 6 { iface.mac.copy_from_slice(&ll.sll_addr[..6]); }
+                    }
+                    libc::AF_INET => {
+                        let sin = &*(ifa.ifa_addr as *const libc::sockaddr_in);
+                        iface.ipv4.get_or_insert(Ipv4Addr::from(u32::from_be(sin.sin_addr.s_addr)));
+                    }
+                    libc::AF_INET6 => {
+                        let ip = Ipv6Addr::from((*(ifa.ifa_addr as *const libc::sockaddr_in6)).sin6_addr.s6_addr);
+                        if iface.ipv6.is_none_or(|cur| cur.is_unicast_link_local()) { iface.ipv6 = Some(ip); }
+                    }
+                    _ => {}
+                }
+            }
+            libc::freeifaddrs(addrs);
+        }
+        Ok(iface)
+    }
+}
+
+/// The kernel's route to a destination, as `ip route get` reports it.
+pub struct Route {
+    /// `None` when the destination is on-link
+    pub gateway: Option<IpAddr>,
+    pub oif: u32,
+    pub src: Option<IpAddr>,
+}
+
+pub fn route_get(dst: IpAddr) -> io::Result<Route> {
+    let (family, addr) = family_and_octets(dst);
+    let mut body = vec![0; RTMSG_LEN];
+    body[0] = family;
+    body[1] = (addr.len() * 8) as u8; // rtm_dst_len: host route
+    push_attr(&mut body, libc::RTA_DST, &addr);
+
+    let replies = Netlink::open(0)?.request(libc::RTM_GETROUTE, 0, &body)?;
+    let (_, payload) = replies
+        .iter()
+        .find(|(ty, _)| *ty == libc::RTM_NEWROUTE)
+        .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, format!("no route to {}", dst)))?;
+
+    let mut route = Route { gateway: None, oif: 0, src: None };
+    for (ty, data) in attrs(payload.get(RTMSG_LEN..).unwrap_or_default()) {
+        match ty {
+            libc::RTA_GATEWAY => route.gateway = parse_ip(data),
+            libc::RTA_PREFSRC => route.src = parse_ip(data),
+            libc::RTA_OIF if data.len() == 4 => route.oif = u32::from_ne_bytes(data.try_into().unwrap()),
+            _ => {}
+        }
+    }
+    Ok(route)
+}
+
+/// MAC in the kernel neighbor table for `ip` on interface `ifindex`, if it has a usable one.
+pub fn neighbor(ifindex: u32, ip: IpAddr) -> io::Result<Option<[u8; 6]>> {
+    let mut body = vec![0; NDMSG_LEN];
+    body[0] = family_and_octets(ip).0;
+
+    let replies = Netlink::open(0)?.request(libc::RTM_GETNEIGH, libc::NLM_F_DUMP as u16, &body)?;
+    Ok(replies
+        .iter()
+        .filter(|(ty, _)| *ty == libc::RTM_NEWNEIGH)
+        .filter_map(|(_, payload)| parse_neigh(payload))
+        .find(|n| n.ifindex == ifindex && n.ip == ip)
+        .and_then(|n| n.mac))
+}
+
+/// Keeps the destination MAC for traffic to one peer: the next hop comes from the routing
+/// table and its MAC from the neighbor table, or from our own ARP/NDP when the kernel has none.
+pub struct Resolver {
+    iface: Interface,
+    peer: IpAddr,
+    src_ip: Option<IpAddr>,
+    next_hop: Mutex<IpAddr>,
+    link: Arc<LinkAddrs>,
+    resolved: AtomicBool,
+}
+
+impl Resolver {
+    pub fn new(iface: &str, peer: IpAddr) -> io::Result<Resolver> {
+        let iface = Interface::query(iface)?;
+        let route = route_get(peer)?;
+        if route.oif != iface.index {
+            println!("[NEIGH] Warning: the kernel routes {} out of ifindex {}, not {}", peer, route.oif, iface.index);
+        }
+
+        let iface_ip = match peer {
+            IpAddr::V4(_) => iface.ipv4.map(IpAddr::V4),
+            IpAddr::V6(_) => iface.ipv6.map(IpAddr::V6),
+        };
+        let next_hop = route.gateway.unwrap_or(peer);
+        let resolver = Resolver {
+            link: Arc::new(LinkAddrs::new(iface.mac, BROADCAST_MAC)),
+            iface,
+            peer,
+            src_ip: route.src.or(iface_ip),
+            next_hop: Mutex::new(next_hop),
+            resolved: AtomicBool::new(false),
+        };
+        if let Some(mac) = neighbor(resolver.iface.index, next_hop)? {
+            resolver.update(next_hop, mac);
+        }
+        Ok(resolver)
+    }
+
+    pub fn interface(&self) -> &Interface {
+        &self.iface
+    }
+
+    pub fn link(&self) -> Arc<LinkAddrs> {
+        Arc::clone(&self.link)
+    }
+
+    /// Source address for traffic to the peer: the route's preferred source, else the interface's.
+    pub fn source_ip(&self) -> Option<IpAddr> {
+        self.src_ip
+    }
+
+    pub fn next_hop(&self) -> IpAddr {
+        *self.next_hop.lock().unwrap()
+    }
+
+    pub fn is_resolved(&self) -> bool {
+        self.resolved.load(Ordering::Relaxed)
+    }
+
+    /// Resolve the next hop with our own ARP request or Neighbor Solicitation, transmitted
+    /// by `send` (an XSK at startup). The XDP program passes replies on to the kernel, so
+    /// they are picked up from a packet socket.
+    pub fn solicit(&self, mut send: impl FnMut(&[u8]) -> io::Result<()>, timeout: Duration) -> io::Result<[u8; 6]> {
+        let target = self.next_hop();
+        let mut frame = [0; 128];
+        let len = match (self.src_ip, target) {
+            (Some(IpAddr::V4(src)), IpAddr::V4(dst)) => headers::write_arp_request(&mut frame, self.iface.mac, src, dst),
+            (Some(IpAddr::V6(src)), IpAddr::V6(dst)) => headers::write_neighbor_solicit(&mut frame, self.iface.mac, src, dst),
+            _ => return Err(io::Error::new(io::ErrorKind::AddrNotAvailable, format!("no local address to solicit {} from", target))),
+        };
+
+        let sniffer = PacketSocket::open(self.iface.index)?;
+        set_recv_timeout(sniffer.0, Duration::from_millis(20))?;
+        let mut buf = [0; 2048];
+        let deadline = Instant::now() + timeout;
+        while Instant::now() < deadline {
+            send(&frame[..len])?;
+            let retry_at = (Instant::now() + SOLICIT_RETRY).min(deadline);
+            while Instant::now() < retry_at {
+                let Ok(n) = sniffer.recv(&mut buf) else { continue };
+                if let Some(mac) = headers::parse_neighbor_reply(&buf[..n], target) {
+                    self.update(target, mac);
+                    return Ok(mac);
+                }
+            }
+        }
+        Err(io::Error::new(io::ErrorKind::TimedOut, format!("no ARP/NDP reply from {}", target)))
+    }
+
+    /// Follow the kernel's neighbor and route tables until `term` is set. A new entry for the
+    /// next hop updates the MAC; a route change re-resolves the next hop.
+    pub fn watch(&self, term: &AtomicBool) {
+        let groups = [libc::RTNLGRP_NEIGH, libc::RTNLGRP_IPV4_ROUTE, libc::RTNLGRP_IPV6_ROUTE]
+            .iter()
+            .fold(0, |acc, group| acc | 1 << (group - 1));
+        let monitor = match Netlink::open(groups).and_then(|nl| set_recv_timeout(nl.fd, WATCH_POLL).map(|_| nl)) {
+            Ok(nl) => nl,
+            Err(e) => return println!("[NEIGH] Not watching for neighbor changes: {}", e),
+        };
+
+        let mut buf = vec![0; 32 * 1024];
+        while !term.load(Ordering::Relaxed) {
+            let n = match monitor.recv(&mut buf) {
+                Ok(n) => n,
+                // The poll timeout, so `term` is checked
+                Err(e) if matches!(e.kind(), io::ErrorKind::WouldBlock | io::ErrorKind::Interrupted) => continue,
+                // The socket overran and events were lost: look everything up again
+                Err(e) if e.raw_os_error() == Some(libc::ENOBUFS) => {
+                    println!("[NEIGH] Missed neighbor or route changes, resyncing");
+                    self.resync();
+                    continue;
+                }
+                Err(e) => return println!("[NEIGH] Stopped watching for neighbor changes: {}", e),
+            };
+            let mut rerouted = false;
+            for (ty, _, payload) in messages(&buf[..n]) {
+                match ty {
+                    // Deleted entries are ignored: the last MAC is still the best guess
+                    libc::RTM_NEWNEIGH => {
+                        let Some(entry) = parse_neigh(payload) else { continue };
+                        let next_hop = self.next_hop();
+                        if let (true, Some(mac)) = (entry.ifindex == self.iface.index && entry.ip == next_hop, entry.mac) {
+                            self.update(next_hop, mac);
+                        }
+                    }
+                    libc::RTM_NEWROUTE | libc::RTM_DELROUTE => rerouted = true,
+                    _ => {}
+                }
+            }
+            if rerouted { self.reroute(); }
+        }
+    }
+
+    /// Re-read the route and the next hop's neighbor entry, as after missed events.
+    fn resync(&self) {
+        self.reroute();
+        let next_hop = self.next_hop();
+        if let Ok(Some(mac)) = neighbor(self.iface.index, next_hop) {
+            self.update(next_hop, mac);
+        }
+    }
+
+    fn reroute(&self) {
+        let Ok(route) = route_get(self.peer) else { return };
+        let next_hop = route.gateway.unwrap_or(self.peer);
+        if mem::replace(&mut *self.next_hop.lock().unwrap(), next_hop) == next_hop { return; }
+        println!("[NEIGH] Route to {} now via {}", self.peer, next_hop);
+
+        // Workers own the XSKs by now, so a missing entry is solicited over a packet socket
+        if let Ok(Some(mac)) = neighbor(self.iface.index, next_hop) {
+            return self.update(next_hop, mac);
+        }
+        let solicited = PacketSocket::open(self.iface.index)
+            .and_then(|raw| self.solicit(|frame| raw.send(frame), Duration::from_secs(1)));
+        if let Err(e) = solicited {
+            println!("[NEIGH] Could not resolve {}: {}", next_hop, e);
+        }
+    }
+
+    fn update(&self, next_hop: IpAddr, mac: [u8; 6]) {
+        self.resolved.store(true, Ordering::Relaxed);
+        if self.link.set_dst(mac) {
+            println!("[NEIGH] Next hop {} is at {}", next_hop, fmt_mac(&mac));
+        }
+    }
+}
+
+struct NeighEntry {
+    ifindex: u32,
+    ip: IpAddr,
+    mac: Option<[u8; 6]>,
+}
+
+fn parse_neigh(payload: &[u8]) -> Option<NeighEntry> {
+    let ndm = payload.get(..NDMSG_LEN)?;
+    let ifindex = i32::from_ne_bytes(ndm[4..8].try_into().unwrap()) as u32;
+    let state = u16::from_ne_bytes(ndm[8..10].try_into().unwrap());
+
+    let (mut ip, mut mac) = (None, None);
+    for (ty, data) in attrs(&payload[NDMSG_LEN..]) {
+        match ty {
+            libc::NDA_DST => ip = parse_ip(data),
+            libc::NDA_LLADDR => mac = data.try_into().ok(),
+            _ => {}
+        }
+    }
+    Some(NeighEntry { ifindex, ip: ip?, mac: mac.filter(|_| state & NUD_VALID != 0) })
+}
+
+fn family_and_octets(ip: IpAddr) -> (u8, Vec<u8>) {
+    match ip {
+        IpAddr::V4(ip) => (libc::AF_INET as u8, ip.octets().to_vec()),
+        IpAddr::V6(ip) => (libc::AF_INET6 as u8, ip.octets().to_vec()),
+    }
+}
+
+fn parse_ip(data: &[u8]) -> Option<IpAddr> {
+    match data.len() {
+        4 => Some(IpAddr::V4(<[u8; 4]>::try_from(data).ok()?.into())),
+        16 => Some(IpAddr::V6(<[u8; 16]>::try_from(data).ok()?.into())),
+        _ => None,
+    }
+}
+
+fn push_attr(buf: &mut Vec<u8>, ty: u16, data: &[u8]) {
+    buf.extend_from_slice(&((4 + data.len()) as u16).to_ne_bytes());
+    buf.extend_from_slice(&ty.to_ne_bytes());
+    buf.extend_from_slice(data);
+    buf.resize(align4(buf.len()), 0);
+}
+
+fn align4(len: usize) -> usize {
+    (len + 3) & !3
+}
+
+/// `(type, seq, payload)` for each message in a netlink datagram.
+fn messages(buf: &[u8]) -> impl Iterator<Item = (u16, u32, &[u8])> {
+    let mut rest = buf;
+    std::iter::from_fn(move || {
+        let len = u32::from_ne_bytes(rest.get(0..4)?.try_into().unwrap()) as usize;
+        if len < NLMSG_HDR_LEN || len > rest.len() { return None; }
+        let ty = u16::from_ne_bytes(rest[4..6].try_into().unwrap());
+        let seq = u32::from_ne_bytes(rest[8..12].try_into().unwrap());
+        let payload = &rest[NLMSG_HDR_LEN..len];
+        rest = &rest[align4(len).min(rest.len())..];
+        Some((ty, seq, payload))
+    })
+}
+
+/// Add the replies to request `seq` found in one datagram. `Some` once the request is
+/// answered: `NLMSG_DONE` or the single reply, an ack, or an error.
+fn take_replies(buf: &[u8], seq: u32, dump: bool, replies: &mut Vec<(u16, Vec<u8>)>) -> Option<io::Result<()>> {
+    for (ty, msg_seq, payload) in messages(buf) {
+        if msg_seq != seq { continue; }
+        match ty as i32 {
+            libc::NLMSG_DONE => return Some(Ok(())),
+            libc::NLMSG_ERROR => {
+                let errno = i32::from_ne_bytes(payload.get(..4).unwrap_or(&[0; 4]).try_into().unwrap());
+                if errno != 0 { return Some(Err(io::Error::from_raw_os_error(-errno))); }
+                return Some(Ok(()));
+            }
+            _ => {
+                replies.push((ty, payload.to_vec()));
+                if !dump { return Some(Ok(())); }
+            }
+        }
+    }
+    None
+}
+
+/// `(type, data)` for each route attribute in `buf`.
+fn attrs(buf: &[u8]) -> impl Iterator<Item = (u16, &[u8])> {
+    let mut rest = buf;
+    std::iter::from_fn(move || {
+        let len = u16::from_ne_bytes(rest.get(0..2)?.try_into().unwrap()) as usize;
+        if len < 4 || len > rest.len() { return None; }
+        let ty = u16::from_ne_bytes(rest[2..4].try_into().unwrap());
+        let data = &rest[4..len];
+        rest = &rest[align4(len).min(rest.len())..];
+        Some((ty, data))
+    })
+}
+
+fn set_recv_timeout(fd: RawFd, timeout: Duration) -> io::Result<()> {
+    let tv = libc::timeval { tv_sec: timeout.as_secs() as _, tv_usec: timeout.subsec_micros() as _ };
+    if unsafe { setsockopt(fd, SOL_SOCKET, SO_RCVTIMEO, &tv as *const _ as *const _, mem::size_of::<libc::timeval>() as u32) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+/// NETLINK_ROUTE socket, optionally subscribed to multicast groups.
+struct Netlink {
+    fd: RawFd,
+    seq: u32,
+}
+
+impl Netlink {
+    fn open(groups: u32) -> io::Result<Netlink> {
+        unsafe {
+            let fd = socket(libc::AF_NETLINK, libc::SOCK_RAW | libc::SOCK_CLOEXEC, libc::NETLINK_ROUTE);
+            if fd < 0 { return Err(io::Error::last_os_error()); }
+
+            let mut addr: libc::sockaddr_nl = mem::zeroed();
+            addr.nl_family = libc::AF_NETLINK as u16;
+            addr.nl_groups = groups;
+            if libc::bind(fd, &addr as *const _ as *const libc::sockaddr, mem::size_of::<libc::sockaddr_nl>() as u32) != 0 {
+                let err = io::Error::last_os_error();
+                close(fd);
+                return Err(err);
+            }
+            Ok(Netlink { fd, seq: 0 })
+        }
+    }
+
+    /// Send one request and collect the replies: everything up to `NLMSG_DONE` for a dump,
+    /// else the single answer. Gives up after `REQUEST_TIMEOUT`.
+    fn request(&mut self, ty: u16, flags: u16, body: &[u8]) -> io::Result<Vec<(u16, Vec<u8>)>> {
+        set_recv_timeout(self.fd, REQUEST_TIMEOUT)?;
+        self.seq += 1;
+        let mut msg = Vec::with_capacity(NLMSG_HDR_LEN + body.len());
+        msg.extend_from_slice(&((NLMSG_HDR_LEN + body.len()) as u32).to_ne_bytes());
+        msg.extend_from_slice(&ty.to_ne_bytes());
+        msg.extend_from_slice(&(flags | libc::NLM_F_REQUEST as u16).to_ne_bytes());
+        msg.extend_from_slice(&self.seq.to_ne_bytes());
+        msg.extend_from_slice(&0u32.to_ne_bytes());
+        msg.extend_from_slice(body);
+        if unsafe { send(self.fd, msg.as_ptr() as *const _, msg.len(), 0) } < 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        let dump = flags & libc::NLM_F_DUMP as u16 != 0;
+        let deadline = Instant::now() + REQUEST_TIMEOUT;
+        let mut replies = Vec::new();
+        let mut buf = vec![0; 32 * 1024];
+        while Instant::now() < deadline {
+            let n = match self.recv(&mut buf) {
+                Ok(n) => n,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => break,
+                Err(e) => return Err(e),
+            };
+            if let Some(done) = take_replies(&buf[..n], self.seq, dump, &mut replies) {
+                return done.map(|()| replies);
+            }
+        }
+        Err(io::Error::new(io::ErrorKind::TimedOut, "no netlink reply"))
+    }
+
+    fn recv(&self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = unsafe { recv(self.fd, buf.as_mut_ptr() as *mut _, buf.len(), 0) };
+        if n < 0 { return Err(io::Error::last_os_error()); }
+        Ok(n as usize)
+    }
+}
+
+impl Drop for Netlink {
+    fn drop(&mut self) {
+        unsafe { close(self.fd); }
+    }
+}
+
+/// AF_PACKET socket bound to one interface, seeing every frame the kernel receives there.
+struct PacketSocket(RawFd);
+
+impl PacketSocket {
+    fn open(ifindex: u32) -> io::Result<PacketSocket> {
+        let protocol = (libc::ETH_P_ALL as u16).to_be();
+        unsafe {
+            let fd = socket(libc::AF_PACKET, libc::SOCK_RAW | libc::SOCK_CLOEXEC, protocol as i32);
+            if fd < 0 { return Err(io::Error::last_os_error()); }
+
+            let mut addr: libc::sockaddr_ll = mem::zeroed();
+            addr.sll_family = libc::AF_PACKET as u16;
+            addr.sll_protocol = protocol;
+            addr.sll_ifindex = ifindex as i32;
+            if libc::bind(fd, &addr as *const _ as *const libc::sockaddr, mem::size_of::<libc::sockaddr_ll>() as u32) != 0 {
+                let err = io::Error::last_os_error();
+                close(fd);
+                return Err(err);
+            }
+            Ok(PacketSocket(fd))
+        }
+    }
+
+    fn send(&self, frame: &[u8]) -> io::Result<()> {
+        if unsafe { send(self.0, frame.as_ptr() as *const _, frame.len(), 0) } < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(())
+    }
+
+    fn recv(&self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = unsafe { recv(self.0, buf.as_mut_ptr() as *mut _, buf.len(), 0) };
+        if n < 0 { return Err(io::Error::last_os_error()); }
+        Ok(n as usize)
+    }
+}
+
+impl Drop for PacketSocket {
+    fn drop(&mut self) {
+        unsafe { close(self.0); }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// One netlink message, padded to 4 bytes.
+    fn nlmsg(ty: u16, seq: u32, payload: &[u8]) -> Vec<u8> {
+        let mut msg = ((NLMSG_HDR_LEN + payload.len()) as u32).to_ne_bytes().to_vec();
+        msg.extend_from_slice(&ty.to_ne_bytes());
+        msg.extend_from_slice(&0u16.to_ne_bytes());
+        msg.extend_from_slice(&seq.to_ne_bytes());
+        msg.extend_from_slice(&0u32.to_ne_bytes());
+        msg.extend_from_slice(payload);
+        msg.resize(align4(msg.len()), 0);
+        msg
+    }
+
+    /// RTM_NEWNEIGH payload: ndmsg, then NDA_DST and (if given) NDA_LLADDR.
+    fn neigh(ifindex: i32, state: u16, ip: IpAddr, mac: Option<[u8; 6]>) -> Vec<u8> {
+        let (family, octets) = family_and_octets(ip);
+        let mut body = vec![0; NDMSG_LEN];
+        body[0] = family;
+        body[4..8].copy_from_slice(&ifindex.to_ne_bytes());
+        body[8..10].copy_from_slice(&state.to_ne_bytes());
+        push_attr(&mut body, libc::NDA_DST, &octets);
+        if let Some(mac) = mac {
+            push_attr(&mut body, libc::NDA_LLADDR, &mac);
+        }
+        body
+    }
+
+    fn nlmsg_error(seq: u32, errno: i32) -> Vec<u8> {
+        // The error, then the header of the request it answers
+        let mut payload = errno.to_ne_bytes().to_vec();
+        payload.extend_from_slice(&[0; NLMSG_HDR_LEN]);
+        nlmsg(libc::NLMSG_ERROR as u16, seq, &payload)
+    }
+
+    const MAC: [u8; 6] = [0x02, 0, 0, 0, 0, 0x11];
+
+    #[test]
+    fn messages_stop_at_a_truncated_one() {
+        let mut buf = nlmsg(libc::RTM_NEWNEIGH, 1, b"abc");
+        buf.extend(nlmsg(libc::RTM_NEWROUTE, 2, b""));
+        let whole = buf.len();
+        buf.extend(nlmsg(libc::RTM_NEWNEIGH, 3, &[0; 20]));
+
+        let parsed: Vec<_> = messages(&buf).collect();
+        assert_eq!(parsed[..2], [(libc::RTM_NEWNEIGH, 1, &b"abc"[..]), (libc::RTM_NEWROUTE, 2, &b""[..])]);
+        assert_eq!(parsed[2].2.len(), 20);
+        assert_eq!(messages(&buf[..whole + 20]).count(), 2, "length past the end of the datagram");
+        assert_eq!(messages(&buf[..3]).count(), 0);
+
+        // A length shorter than the header itself ends the walk instead of looping on it
+        let mut short = nlmsg(libc::RTM_NEWNEIGH, 1, b"");
+        short[..4].copy_from_slice(&8u32.to_ne_bytes());
+        assert_eq!(messages(&short).count(), 0);
+    }
+
+    #[test]
+    fn attrs_are_padded_and_bounded() {
+        let mut buf = Vec::new();
+        push_attr(&mut buf, libc::RTA_OIF, &7u32.to_ne_bytes());
+        push_attr(&mut buf, libc::RTA_GATEWAY, &[10, 0, 0, 1]);
+        push_attr(&mut buf, libc::NDA_LLADDR, &MAC);
+        assert_eq!(buf.len(), 8 + 8 + 12);
+        let parsed: Vec<_> = attrs(&buf).collect();
+        assert_eq!(parsed[1], (libc::RTA_GATEWAY, &[10, 0, 0, 1][..]));
+        assert_eq!(parsed[2], (libc::NDA_LLADDR, &MAC[..]));
+
+        assert_eq!(attrs(&buf[..8 + 8 + 7]).count(), 2, "truncated last attribute");
+        assert_eq!(attrs(&[2, 0, 1, 0]).count(), 0, "length under the attribute header");
+    }
+
+    #[test]
+    fn neighbor_entries_carry_a_mac_only_in_usable_states() {
+        let ip: IpAddr = "10.0.0.1".parse().unwrap();
+        for state in [libc::NUD_REACHABLE, libc::NUD_STALE, libc::NUD_DELAY, libc::NUD_PROBE, libc::NUD_PERMANENT] {
+            let entry = parse_neigh(&neigh(3, state, ip, Some(MAC))).unwrap();
+            assert_eq!((entry.ifindex, entry.ip, entry.mac), (3, ip, Some(MAC)), "state {:#x}", state);
+        }
+        // The kernel keeps the old address around while (re)probing fails
+        for state in [libc::NUD_INCOMPLETE, libc::NUD_FAILED, libc::NUD_NONE] {
+            assert_eq!(parse_neigh(&neigh(3, state, ip, Some(MAC))).unwrap().mac, None, "state {:#x}", state);
+        }
+
+        let v6: IpAddr = "fe80::1".parse().unwrap();
+        assert_eq!(parse_neigh(&neigh(3, libc::NUD_REACHABLE, v6, None)).unwrap().ip, v6);
+
+        assert!(parse_neigh(&[0; NDMSG_LEN - 1]).is_none());
+        assert!(parse_neigh(&[0; NDMSG_LEN]).is_none(), "no NDA_DST");
+    }
+
+    #[test]
+    fn replies_end_at_done_ack_or_error() {
+        let entry = neigh(3, libc::NUD_REACHABLE, "10.0.0.1".parse().unwrap(), Some(MAC));
+
+        // A dump runs over several datagrams until NLMSG_DONE; other requests' messages are skipped
+        let mut replies = Vec::new();
+        let mut first = nlmsg(libc::RTM_NEWNEIGH, 5, &entry);
+        first.extend(nlmsg(libc::RTM_NEWNEIGH, 4, &entry));
+        assert!(take_replies(&first, 5, true, &mut replies).is_none());
+        let mut last = nlmsg(libc::RTM_NEWNEIGH, 5, &entry);
+        last.extend(nlmsg(libc::NLMSG_DONE as u16, 5, &[0; 4]));
+        assert!(matches!(take_replies(&last, 5, true, &mut replies), Some(Ok(()))));
+        assert_eq!(replies.len(), 2);
+
+        // Otherwise the first reply answers it
+        let mut replies = Vec::new();
+        assert!(matches!(take_replies(&nlmsg(libc::RTM_NEWROUTE, 1, b"rt"), 1, false, &mut replies), Some(Ok(()))));
+        assert_eq!(replies, [(libc::RTM_NEWROUTE, b"rt".to_vec())]);
+
+        let mut replies = Vec::new();
+        let err = take_replies(&nlmsg_error(2, -libc::ENETUNREACH), 2, false, &mut replies).unwrap().unwrap_err();
+        assert_eq!(err.raw_os_error(), Some(libc::ENETUNREACH));
+        assert!(matches!(take_replies(&nlmsg_error(2, 0), 2, false, &mut replies), Some(Ok(()))), "ack");
+        assert!(take_replies(&nlmsg_error(1, -libc::EINVAL), 2, false, &mut replies).is_none(), "someone else's error");
+        assert!(replies.is_empty());
+    }
+}
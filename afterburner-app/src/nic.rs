@@ -0,0 +1,144 @@
+use std::ffi::CString;
+use std::{fs, io, mem};
+use libc::{close, ioctl, socket, AF_INET, SIOCETHTOOL, SOCK_DGRAM};
+
+const ETHTOOL_GCHANNELS: u32 = 0x0000003c;
+
+#[repr(C)]
+#[derive(Default)]
+struct EthtoolChannels {
+    cmd: u32,
+    max_rx: u32,
+    max_tx: u32,
+    max_other: u32,
+    max_combined: u32,
+    rx_count: u32,
+    tx_count: u32,
+    other_count: u32,
+    combined_count: u32,
+}
+
+/// Number of RX queues on `iface`, taken from the ethtool channel config.
+/// Drivers without ETHTOOL_GCHANNELS (older veth) fall back to counting sysfs `rx-*` queues.
+pub fn rx_queue_count(iface: &str) -> io::Result<u32> {
+    match ethtool_channels(iface) {
+        Ok(ch) if ch.combined_count + ch.rx_count > 0 => Ok(ch.combined_count + ch.rx_count),
+        _ => sysfs_rx_queues(iface),
+    }
+}
+
+fn ethtool_channels(iface: &str) -> io::Result<EthtoolChannels> {
+    let if_name = CString::new(iface)?;
+    if if_name.as_bytes_with_nul().len() > libc::IFNAMSIZ {
+        return Err(io::Error::from_raw_os_error(libc::ENAMETOOLONG));
+    }
+
+    unsafe {
+        let fd = socket(AF_INET, SOCK_DGRAM, 0);
+        if fd < 0 { return Err(io::Error::last_os_error()); }
+
+        let mut ch = EthtoolChannels { cmd: ETHTOOL_GCHANNELS, ..Default::default() };
+        let mut ifr: libc::ifreq = mem::zeroed();
+        for (dst, src) in ifr.ifr_name.iter_mut().zip(if_name.as_bytes()) {
+            *dst = *src as libc::c_char;
+        }
+        ifr.ifr_ifru.ifru_data = &mut ch as *mut _ as *mut libc::c_char;
+
+        let ret = ioctl(fd, SIOCETHTOOL as _, &mut ifr);
+        let err = io::Error::last_os_error();
+        close(fd);
+        if ret < 0 { return Err(err); }
+        Ok(ch)
+    }
+}
+
+fn sysfs_rx_queues(iface: &str) -> io::Result<u32> {
+    let count = fs::read_dir(format!("/sys/class/net/{}/queues", iface))?
+        .filter_map(Result::ok)
+        .filter(|e| e.file_name().to_string_lossy().starts_with("rx-"))
+        .count();
+    Ok(count.max(1) as u32)
+}
+
+/// NUMA node the NIC hangs off, or `None` on single-node machines and virtual devices.
+pub fn numa_node(iface: &str) -> Option<u32> {
+    let node = fs::read_to_string(format!("/sys/class/net/{}/device/numa_node", iface)).ok()?;
+    // The kernel reports -1 when the device has no affinity
+    node.trim().parse::<i32>().ok().filter(|n| *n >= 0).map(|n| n as u32)
+}
+
+/// CPUs local to a NUMA node, from `/sys/devices/system/node/nodeN/cpulist`.
+pub fn node_cpus(node: u32) -> io::Result<Vec<usize>> {
+    let list = fs::read_to_string(format!("/sys/devices/system/node/node{}/cpulist", node))?;
+    parse_cpu_list(&list)
+}
+
+/// `--cores`: a CPU list whose CPUs must all be online.
+pub fn parse_cores(list: &str) -> io::Result<Vec<usize>> {
+    let cores = parse_cpu_list(list)?;
+    let online = parse_cpu_list(&fs::read_to_string("/sys/devices/system/cpu/online")?)?;
+    match cores.iter().find(|core| !online.contains(core)) {
+        Some(core) => Err(io::Error::new(io::ErrorKind::InvalidInput, format!("CPU {} is not online", core))),
+        None => Ok(cores),
+    }
+}
+
+/// Parse a kernel-style CPU list such as `2,4-7,10`. CPUs past `CPU_SETSIZE` are refused,
+/// since no affinity mask can name them.
+pub fn parse_cpu_list(list: &str) -> io::Result<Vec<usize>> {
+    let invalid = || io::Error::new(io::ErrorKind::InvalidInput, format!("invalid CPU list: {:?}", list));
+
+    let mut cpus = Vec::new();
+    for part in list.trim().split(',').filter(|p| !p.is_empty()) {
+        match part.split_once('-') {
+            Some((lo, hi)) => {
+                let lo: usize = lo.trim().parse().map_err(|_| invalid())?;
+                let hi: usize = hi.trim().parse().map_err(|_| invalid())?;
+                if lo > hi || hi >= libc::CPU_SETSIZE as usize { return Err(invalid()); }
+                cpus.extend(lo..=hi);
+            }
+            None => {
+                let cpu: usize = part.trim().parse().map_err(|_| invalid())?;
+                if cpu >= libc::CPU_SETSIZE as usize { return Err(invalid()); }
+                cpus.push(cpu);
+            }
+        }
+    }
+    if cpus.is_empty() { return Err(invalid()); }
+    Ok(cpus)
+}
+
+/// Pin the calling thread to a single CPU.
+pub fn pin_to_core(core: usize) -> io::Result<()> {
+    if core >= libc::CPU_SETSIZE as usize {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, format!("CPU {} is past CPU_SETSIZE", core)));
+    }
+    unsafe {
+        let mut set: libc::cpu_set_t = mem::zeroed();
+        libc::CPU_SET(core, &mut set);
+        if libc::sched_setaffinity(0, mem::size_of::<libc::cpu_set_t>(), &set) != 0 {
+            return Err(io::Error::last_os_error());
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cpu_lists_stay_within_an_affinity_mask() {
+        assert_eq!(parse_cpu_list("2,4-7,10\n").unwrap(), [2, 4, 5, 6, 7, 10]);
+        assert!(parse_cpu_list("3-1").is_err());
+        assert!(parse_cpu_list("1024").is_err());
+        assert!(parse_cpu_list("0-4096").is_err());
+        assert_eq!(pin_to_core(libc::CPU_SETSIZE as usize).unwrap_err().kind(), io::ErrorKind::InvalidInput);
+    }
+
+    #[test]
+    fn cores_must_be_online() {
+        assert_eq!(parse_cores("0").unwrap(), [0]);
+        assert!(parse_cores("1023").is_err());
+    }
+}
@@ -0,0 +1,175 @@
+use std::time::{Duration, Instant};
+
+// Unpaced, and the floor for the default burst when paced
+const MIN_BURST: usize = 4;
+
+/// Raise the rate by `step` TPS every `every`, starting at `step`, until the target.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Ramp {
+    pub step: f64,
+    pub every: Duration,
+}
+
+impl Ramp {
+    /// `<step>/<seconds>s`, e.g. `5000/10s`.
+    pub fn parse(s: &str) -> Result<Self, String> {
+        let err = || format!("{:?}: expected <tps step>/<seconds>s, e.g. 5000/10s", s);
+        let (step, every) = s.split_once('/').ok_or_else(err)?;
+        let step: f64 = step.parse().map_err(|_| err())?;
+        let every: f64 = every.strip_suffix('s').unwrap_or(every).parse().map_err(|_| err())?;
+        if !(step > 0.0 && every > 0.0) {
+            return Err(err());
+        }
+        Ok(Ramp { step, every: Duration::from_secs_f64(every) })
+    }
+}
+
+/// Flood rate settings. Without a `tps` the flooder sends `burst` per loop iteration.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct PaceConfig {
+    pub tps: Option<f64>,
+    /// Most transactions sent back to back; defaults to 1ms worth of `tps`
+    pub burst: Option<usize>,
+    pub ramp: Option<Ramp>,
+}
+
+impl PaceConfig {
+    /// One worker's share when `workers` flood side by side.
+    pub fn split(&self, workers: usize) -> Self {
+        let n = workers.max(1);
+        PaceConfig {
+            tps: self.tps.map(|tps| tps / n as f64),
+            burst: self.burst.map(|burst| burst.div_ceil(n)),
+            ramp: self.ramp.map(|r| Ramp { step: r.step / n as f64, ..r }),
+        }
+    }
+}
+
+/// Token bucket on the monotonic clock: tokens accrue at the (ramped) target rate and
+/// are capped at the burst size. Each token is one transaction.
+pub struct Pacer {
+    config: PaceConfig,
+    burst: usize,
+    tokens: f64,
+    started: Instant,
+    last: Instant,
+    sent: u64,
+    // Start of the current reporting window, and `sent` at that point
+    window: (Instant, u64),
+}
+
+impl Pacer {
+    pub fn new(config: PaceConfig, now: Instant) -> Self {
+        let burst = match (config.burst, config.tps) {
+            (Some(burst), _) => burst.max(1),
+            (None, Some(tps)) => ((tps / 1000.0) as usize).max(MIN_BURST),
+            (None, None) => MIN_BURST,
+        };
+        Pacer { config, burst, tokens: burst as f64, started: now, last: now, sent: 0, window: (now, 0) }
+    }
+
+    pub fn is_paced(&self) -> bool {
+        self.config.tps.is_some()
+    }
+
+    /// Target rate at `now`, `None` when unpaced.
+    pub fn rate(&self, now: Instant) -> Option<f64> {
+        let tps = self.config.tps?;
+        Some(match self.config.ramp {
+            Some(Ramp { step, every }) => {
+                let steps = (now - self.started).as_secs_f64() / every.as_secs_f64();
+                tps.min(step * (steps.floor() + 1.0))
+            }
+            None => tps,
+        })
+    }
+
+    /// Whole transactions that may go out now.
+    pub fn available(&mut self, now: Instant) -> usize {
+        match self.rate(now) {
+            Some(rate) => {
+                let elapsed = now.saturating_duration_since(self.last).as_secs_f64();
+                self.tokens = (self.tokens + elapsed * rate).min(self.burst as f64);
+                self.last = now;
+                self.tokens as usize
+            }
+            None => self.burst,
+        }
+    }
+
+    /// Take `n` tokens for transactions sent.
+    pub fn spend(&mut self, n: usize) {
+        self.tokens = (self.tokens - n as f64).max(0.0);
+        self.sent += n as u64;
+    }
+
+    /// Print the rate achieved since the last call against the target.
+    pub fn log(&mut self, label: &str, now: Instant) {
+        let (since, sent_before) = self.window;
+        let secs = (now - since).as_secs_f64();
+        if secs <= 0.0 {
+            return;
+        }
+        let achieved = (self.sent - sent_before) as f64 / secs;
+        match self.rate(now) {
+            Some(target) => println!("[PACE] {} target={:.0}/s achieved={:.0}/s ({:.1}%) burst={}",
+                label, target, achieved, achieved / target * 100.0, self.burst),
+            None => println!("[PACE] {} target=max achieved={:.0}/s burst={}", label, achieved, self.burst),
+        }
+        self.window = (now, self.sent);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MS: Duration = Duration::from_millis(1);
+
+    /// Send whatever is allowed every millisecond for `ms`, returning the total.
+    fn drive(pacer: &mut Pacer, start: Instant, ms: u32) -> usize {
+        (1..=ms).map(|i| {
+            let n = pacer.available(start + MS * i);
+            pacer.spend(n);
+            n
+        }).sum()
+    }
+
+    #[test]
+    fn holds_the_target_rate() {
+        let t0 = Instant::now();
+        let mut pacer = Pacer::new(PaceConfig { tps: Some(10_000.0), burst: Some(50), ramp: None }, t0);
+
+        // A full bucket the first millisecond, then 10 per millisecond (less a rounding fraction)
+        let sent = drive(&mut pacer, t0, 1000);
+        assert!((50 + 9_989..=50 + 9_990).contains(&sent), "{}", sent);
+
+        // Idle time only builds up to one burst
+        assert_eq!(pacer.available(t0 + Duration::from_secs(5)), 50);
+    }
+
+    #[test]
+    fn ramps_in_steps_up_to_the_target() {
+        let t0 = Instant::now();
+        let ramp = Ramp::parse("1000/2s").unwrap();
+        let pacer = Pacer::new(PaceConfig { tps: Some(2500.0), burst: None, ramp: Some(ramp) }, t0);
+        let rates: Vec<_> = [0, 1, 2, 5, 60].iter().map(|&s| pacer.rate(t0 + Duration::from_secs(s)).unwrap()).collect();
+        assert_eq!(rates, [1000.0, 1000.0, 2000.0, 2500.0, 2500.0]);
+
+        assert!(Ramp::parse("1000/0s").is_err());
+        assert!(Ramp::parse("fast").is_err());
+    }
+
+    #[test]
+    fn splits_across_workers() {
+        let config = PaceConfig { tps: Some(90_000.0), burst: Some(10), ramp: Some(Ramp::parse("30000/5").unwrap()) };
+        let share = config.split(4);
+        assert_eq!(share.tps, Some(22_500.0));
+        assert_eq!(share.burst, Some(3));
+        assert_eq!(share.ramp.unwrap().step, 7_500.0);
+
+        // Unpaced: a fixed batch per call
+        let mut pacer = Pacer::new(PaceConfig::default(), Instant::now());
+        assert_eq!(pacer.available(Instant::now()), MIN_BURST);
+    }
+}
@@ -0,0 +1,176 @@
+use std::net::SocketAddr;
+use std::ops::{Deref, DerefMut};
+use std::sync::Arc;
+use crate::headers::{self, HeaderTemplate, Reject, RejectCounts, UdpChecksum, MAX_HEADER_LEN};
+use crate::neigh::LinkAddrs;
+use crate::xsk::{RxFrame, TxFrame, XdpSocket, TX_METADATA_LEN};
+
+/// Datagram transport under the worker loop. Frames are UDP payloads; backends below
+/// the IP stack strip and write the headers themselves.
+pub trait PacketIo {
+    type Rx<'a>: DerefMut<Target = [u8]> where Self: 'a;
+    type Tx<'a>: TxPacket where Self: 'a;
+
+    /// Next received datagram and its source address, or `None` when nothing is pending.
+    fn poll_rx(&mut self) -> Option<(SocketAddr, Self::Rx<'_>)>;
+
+    /// Buffer for one outgoing datagram, or `None` when the backend is out of room.
+    fn get_tx_frame(&mut self) -> Option<Self::Tx<'_>>;
+
+    /// Put everything submitted so far on the wire. Returns how many datagrams went out.
+    fn tx_submit(&mut self) -> usize;
+
+    /// Backend counters for the `[STATS]` line, tagged with `label`; called on the stats
+    /// interval. `None` when the backend keeps none.
+    fn stats(&self, _label: &str) -> Option<String> {
+        None
+    }
+}
+
+/// Outgoing datagram buffer. Dropping it without `submit` cancels it.
+pub trait TxPacket: DerefMut<Target = [u8]> {
+    fn submit(self, len: usize, from: SocketAddr, to: SocketAddr);
+}
+
+// XDP ----------------------------------------------------------------------
+
+// Where the UDP payload starts in a TX frame: room for TX metadata and the largest header stack
+const TX_PAYLOAD_OFFSET: usize = TX_METADATA_LEN + MAX_HEADER_LEN;
+
+/// AF_XDP backend: an `XdpSocket` plus the header templates its frames are built from.
+pub struct XdpIo {
+    socket: XdpSocket,
+    headers: TxHeaders,
+    local: SocketAddr,
+    rejects: RejectCounts,
+}
+
+impl XdpIo {
+    /// Only frames to `local` are received. `UdpChecksum::Offload` needs a socket with TX
+    /// metadata; without it checksums are computed in software.
+    pub fn new(socket: XdpSocket, local: SocketAddr, link: Arc<LinkAddrs>, mut checksum: UdpChecksum) -> Self {
+        if checksum == UdpChecksum::Offload && !socket.has_tx_metadata() {
+            checksum = UdpChecksum::Software;
+        }
+        XdpIo { socket, headers: TxHeaders { link, checksum, templates: Vec::new() }, local, rejects: RejectCounts::default() }
+    }
+}
+
+/// One `HeaderTemplate` per (from, to) pair, kept on the current next-hop MAC.
+struct TxHeaders {
+    link: Arc<LinkAddrs>,
+    checksum: UdpChecksum,
+    // Usually a single entry, so a scan beats hashing
+    templates: Vec<HeaderTemplate>,
+}
+
+impl TxHeaders {
+    fn template(&mut self, from: SocketAddr, to: SocketAddr) -> &mut HeaderTemplate {
+        let eth = self.link.eth();
+        let i = match self.templates.iter().position(|t| t.is_for(from, to)) {
+            Some(i) => i,
+            None => {
+                self.templates.push(HeaderTemplate::new(&eth, from, to, self.checksum));
+                self.templates.len() - 1
+            }
+        };
+        let template = &mut self.templates[i];
+        if template.eth() != eth { template.set_eth(&eth); }
+        template
+    }
+}
+
+/// UDP payload of a received XDP frame, as located by `headers::parse_frame`.
+pub struct XdpRx<'a> {
+    frame: RxFrame<'a>,
+    offset: usize,
+    len: usize,
+}
+
+impl Deref for XdpRx<'_> {
+    type Target = [u8];
+    fn deref(&self) -> &[u8] { &self.frame[self.offset..self.offset + self.len] }
+}
+
+impl DerefMut for XdpRx<'_> {
+    fn deref_mut(&mut self) -> &mut [u8] { &mut self.frame[self.offset..self.offset + self.len] }
+}
+
+/// UDP payload area of a TX frame, after room for TX metadata and the largest header
+/// stack. The headers are written on submit, right in front of the payload.
+pub struct XdpTx<'a> {
+    frame: TxFrame<'a>,
+    headers: &'a mut TxHeaders,
+}
+
+impl Deref for XdpTx<'_> {
+    type Target = [u8];
+    fn deref(&self) -> &[u8] { &self.frame[TX_PAYLOAD_OFFSET..] }
+}
+
+impl DerefMut for XdpTx<'_> {
+    fn deref_mut(&mut self) -> &mut [u8] { &mut self.frame[TX_PAYLOAD_OFFSET..] }
+}
+
+impl TxPacket for XdpTx<'_> {
+    fn submit(self, len: usize, from: SocketAddr, to: SocketAddr) {
+        let XdpTx { mut frame, headers } = self;
+        let template = headers.template(from, to);
+        let start = TX_PAYLOAD_OFFSET - template.len();
+        let frame_len = template.len() + len;
+        match template.write(&mut frame[start..], len) {
+            Some((csum_start, csum_offset)) => frame.submit_with_checksum(start, frame_len, csum_start, csum_offset),
+            None => frame.submit(start, frame_len),
+        }
+    }
+}
+
+impl PacketIo for XdpIo {
+    type Rx<'a> = XdpRx<'a>;
+    type Tx<'a> = XdpTx<'a>;
+
+    fn poll_rx(&mut self) -> Option<(SocketAddr, XdpRx<'_>)> {
+        let (local, rejects) = (self.local, &mut self.rejects);
+        let accept = |frame: &[u8]| match headers::parse_frame(frame) {
+            Ok(udp) if udp.is_to(local) => Some((udp.src, udp.offset, udp.payload.len())),
+            Ok(_) => {
+                rejects.add(Reject::NotOurs);
+                None
+            }
+            Err(reason) => {
+                rejects.add(reason);
+                None
+            }
+        };
+        self.socket.recv(accept).map(|(frame, (from, offset, len))| (from, XdpRx { frame, offset, len }))
+    }
+
+    fn get_tx_frame(&mut self) -> Option<XdpTx<'_>> {
+        let headers = &mut self.headers;
+        self.socket.tx_frame().map(|frame| XdpTx { frame, headers })
+    }
+
+    fn tx_submit(&mut self) -> usize {
+        self.socket.tx_submit_batch()
+    }
+
+    fn stats(&self, label: &str) -> Option<String> {
+        let s = self.socket.stats().ok()?;
+        let mut line = format!(
+            "XSK {} RX drop={} ring_full={} fill_empty={} invalid={} TX invalid={} ring_empty={} ring_full={} exhausted={} comp_lag={} | UMEM inflight={}",
+            label, s.rx_dropped, s.rx_ring_full, s.rx_fill_ring_empty_descs, s.rx_invalid_descs,
+            s.tx_invalid_descs, s.tx_ring_empty_descs, s.tx_ring_full, s.tx_frames_exhausted,
+            s.completion_lag, s.umem_frames_in_flight,
+        );
+
+        if self.rejects.total() > 0 {
+            let counts: Vec<String> = Reject::ALL
+                .iter()
+                .filter(|r| self.rejects.get(**r) > 0)
+                .map(|r| format!("{}={}", r.name(), self.rejects.get(*r)))
+                .collect();
+            line += &format!(" | Rejected {}", counts.join(" "));
+        }
+        Some(line)
+    }
+}
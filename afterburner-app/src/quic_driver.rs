@@ -1,12 +1,164 @@
+use std::collections::{HashMap, VecDeque};
 use std::net::SocketAddr;
+use std::path::PathBuf;
 use std::pin::Pin;
+use std::time::{Duration, Instant};
+use ring::rand::{SecureRandom, SystemRandom};
+use crate::keypair::{self, Keypair};
+use crate::session::SessionCache;
 
-pub struct QuicDriver {
+pub const SCID_LEN: usize = 20;
+// Wait between a connection closing and dialing the peer again
+const RECONNECT_DELAY: Duration = Duration::from_secs(1);
+// Transactions held per connection while the peer grants no new streams
+const MAX_QUEUED_TXS: usize = 1024;
+// CRYPTO_ERROR carrying the TLS bad_certificate alert (RFC 9001, section 4.8)
+pub const BAD_CERTIFICATE: u64 = 0x0100 + 42;
+
+/// How the server's certificate is checked.
+#[derive(Clone, Default)]
+pub enum PeerVerify {
+    /// Accept anything; validators present self-signed certificates.
+    #[default]
+    None,
+    /// The chain must lead to a CA in this PEM bundle. No hostname is checked, we dial IPs.
+    CaBundle(PathBuf),
+    /// The certificate's ed25519 key must be the peer's validator identity.
+    Pin(HashMap<SocketAddr, [u8; 32]>),
+}
+
+/// Client-side TLS settings shared by every connection of a driver.
+#[derive(Clone, Default)]
+pub struct ClientTls {
+    /// Presented as our client certificate, so stake-weighted QoS can find our stake.
+    pub identity: Option<Keypair>,
+    pub verify: PeerVerify,
+    pub sessions: SessionCache,
+}
+
+/// Random source connection ID whose last byte is `tag`, so that packets for it can be
+/// steered back to the worker that owns the connection.
+pub fn new_scid(rng: &SystemRandom, tag: u8) -> quiche::ConnectionId<'static> {
+    let mut scid = [0; SCID_LEN];
+    rng.fill(&mut scid[..SCID_LEN - 1]).expect("SystemRandom::fill");
+    scid[SCID_LEN - 1] = tag;
+    quiche::ConnectionId::from_vec(scid.to_vec())
+}
+
+/// What happened to each transaction handed to a connection.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct StreamCounts {
+    /// Uni streams opened
+    pub opened: u64,
+    /// Streams fully written, FIN included
+    pub finished: u64,
+    /// Transactions that had to wait for stream credit
+    pub queued: u64,
+    /// Transactions dropped because the wait queue was full
+    pub dropped: u64,
+    /// Streams the peer stopped (STOP_SENDING) before we finished them
+    pub stopped: u64,
+    /// Streams abandoned on any other error
+    pub failed: u64,
+    /// Ingested transactions that went out while this connection wasn't ready (handshaking,
+    /// reconnecting) or was backlogged; never sent to this peer
+    pub skipped: u64,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TxOutcome {
+    /// On its own stream (possibly still waiting for flow control to take the rest)
+    Sent,
+    /// Waiting for the peer to allow more streams
+    Queued,
+    Dropped,
+}
+
+/// Client-initiated unidirectional streams, one per transaction as agave's TPU expects
+/// (IDs 2, 6, 10, ...), opened only while the peer's MAX_STREAMS credit lasts.
+struct UniStreams {
+    next_id: u64,
+    queue: VecDeque<Vec<u8>>,
+    // Streams whose data didn't all fit the flow-control window: (id, tx, bytes written)
+    partial: VecDeque<(u64, Vec<u8>, usize)>,
+    counts: StreamCounts,
+}
+
+impl UniStreams {
+    fn new() -> Self {
+        UniStreams { next_id: 2, queue: VecDeque::new(), partial: VecDeque::new(), counts: StreamCounts::default() }
+    }
+
+    /// Write as much of `tx` as fits; false if the stream limit was hit and nothing was opened.
+    fn open(&mut self, conn: &mut quiche::Connection, tx: &[u8]) -> bool {
+        let id = self.next_id;
+        match conn.stream_send(id, tx, true) {
+            // quiche checks the limit before creating the stream, so the ID is still free
+            Err(quiche::Error::StreamLimit) => return false,
+            Ok(n) if n == tx.len() => self.counts.finished += 1,
+            Ok(n) => self.partial.push_back((id, tx.to_vec(), n)),
+            // Opened, but no flow-control window for any of it yet
+            Err(quiche::Error::Done) => self.partial.push_back((id, tx.to_vec(), 0)),
+            Err(quiche::Error::StreamStopped(_)) => self.counts.stopped += 1,
+            Err(_) => self.counts.failed += 1,
+        }
+        self.next_id += 4;
+        self.counts.opened += 1;
+        true
+    }
+
+    fn send(&mut self, conn: &mut quiche::Connection, tx: &[u8]) -> TxOutcome {
+        if self.queue.is_empty() && conn.peer_streams_left_uni() > 0 && self.open(conn, tx) {
+            return TxOutcome::Sent;
+        }
+        if self.queue.len() >= MAX_QUEUED_TXS {
+            self.counts.dropped += 1;
+            return TxOutcome::Dropped;
+        }
+        self.queue.push_back(tx.to_vec());
+        self.counts.queued += 1;
+        TxOutcome::Queued
+    }
+
+    /// Finish partly written streams, then open streams for queued transactions as credit allows.
+    fn flush(&mut self, conn: &mut quiche::Connection) {
+        for _ in 0..self.partial.len() {
+            let Some((id, tx, written)) = self.partial.pop_front() else { break };
+            match conn.stream_send(id, &tx[written..], true) {
+                Ok(n) if written + n == tx.len() => self.counts.finished += 1,
+                Ok(n) => self.partial.push_back((id, tx, written + n)),
+                Err(quiche::Error::Done) => self.partial.push_back((id, tx, written)),
+                Err(quiche::Error::StreamStopped(_)) => self.counts.stopped += 1,
+                Err(_) => self.counts.failed += 1,
+            }
+        }
+        while conn.peer_streams_left_uni() > 0 {
+            let Some(tx) = self.queue.pop_front() else { break };
+            if !self.open(conn, &tx) {
+                self.queue.push_front(tx);
+                break;
+            }
+        }
+    }
+}
+
+/// One entry in the connection table, with its own latency/loss stats.
+pub struct Connection {
     pub conn: Pin<Box<quiche::Connection>>,
-    stream_buf: [u8; 65535], 
-    closed_seen: bool,
+    pub peer: SocketAddr,
+    scid: quiche::ConnectionId<'static>,
+    pin: Option<[u8; 32]>,
+    // Established and, if pinned, the identity matched; nothing is sent before that
+    verified: bool,
+    // Closed by us over an identity mismatch, never redialed
+    rejected: bool,
+    session_saved: bool,
+    started: Instant,
+    // Time from connect to the first transaction, and whether it went out as 0-RTT
+    first_tx: Option<(Duration, bool)>,
+    closed_at: Option<Instant>,
+    streams: UniStreams,
     established_seen: bool,
-    close_timer: Option<std::time::Instant>,
     msg_buf: Vec<u8>,
     stats_count: u64,
     stats_sum_ns: u64,
@@ -14,36 +166,24 @@ pub struct QuicDriver {
     max_lat_ns: u64,
     last_seq: Option<u64>,
     lost_packets: u64,
-    last_stats_time: std::time::Instant,
     total_rx_msgs: u64,
 }
 
-impl QuicDriver {
-    pub fn new(scid: &[u8], local: SocketAddr, peer: SocketAddr) -> Self {
-        let mut config = quiche::Config::new(quiche::PROTOCOL_VERSION).unwrap();
-        
-        config.verify_peer(false);
-        config.set_application_protos(&[b"solana-tpu"]).unwrap();
-        config.set_max_ack_delay(0);
-        config.set_ack_delay_exponent(0);
-        config.set_disable_active_migration(true);
-        config.enable_early_data();
-        config.set_initial_max_data(100_000_000);
-        config.set_initial_max_stream_data_bidi_local(10_000_000);
-        config.set_initial_max_stream_data_bidi_remote(10_000_000);
-        config.set_initial_max_stream_data_uni(10_000_000); 
-        config.set_initial_max_streams_bidi(1000);
-        config.set_initial_max_streams_uni(1000);
-
-        let scid_obj = quiche::ConnectionId::from_ref(scid);
-        let conn = quiche::connect(None, &scid_obj, local, peer, &mut config).unwrap();
-
-        QuicDriver {
+impl Connection {
+    fn new(conn: quiche::Connection, peer: SocketAddr, scid: quiche::ConnectionId<'static>, pin: Option<[u8; 32]>) -> Self {
+        Connection {
             conn: Box::pin(conn),
-            stream_buf: [0; 65535],
-            closed_seen: false,
+            peer,
+            scid,
+            pin,
+            verified: false,
+            rejected: false,
+            session_saved: false,
+            started: Instant::now(),
+            first_tx: None,
+            closed_at: None,
+            streams: UniStreams::new(),
             established_seen: false,
-            close_timer: None,
             msg_buf: Vec::with_capacity(1024),
             stats_count: 0,
             stats_sum_ns: 0,
@@ -51,39 +191,114 @@ impl QuicDriver {
             max_lat_ns: 0,
             last_seq: None,
             lost_packets: 0,
-            last_stats_time: std::time::Instant::now(),
             total_rx_msgs: 0,
         }
     }
 
-    pub fn process_input(&mut self, data: &mut [u8], local: SocketAddr, peer: SocketAddr) {
-        let recv_info = quiche::RecvInfo { from: peer, to: local };
-        if self.conn.recv(data, recv_info).is_ok()
-            && self.conn.is_established()
-            && !self.established_seen
-        {
-            println!("[QUIC] Connection established");
-            self.established_seen = true;
+    /// Safe to send transactions on: verified, or resuming a session that was (tickets are
+    /// only saved from verified connections) and still in early data.
+    pub fn is_ready(&self) -> bool {
+        (self.verified && self.conn.is_established()) || self.conn.is_in_early_data()
+    }
+
+    /// Send `tx` on a fresh uni stream, or queue it until the peer grants one.
+    pub fn send_tx(&mut self, tx: &[u8]) -> TxOutcome {
+        let outcome = self.streams.send(&mut self.conn, tx);
+        if outcome == TxOutcome::Sent {
+            self.on_tx();
         }
+        outcome
     }
 
-    pub fn write_transmit(&mut self, frame: &mut [u8]) -> Option<usize> {
-        match self.conn.send(frame) {
-            Ok((written, _)) => Some(written),
-            Err(_) => None, 
+    /// Count an ingested transaction this connection missed.
+    pub fn skip_tx(&mut self) {
+        self.streams.counts.skipped += 1;
+    }
+
+    /// Transactions are waiting for stream credit.
+    pub fn is_backlogged(&self) -> bool {
+        !self.streams.queue.is_empty()
+    }
+
+    #[cfg(test)]
+    pub fn stream_counts(&self) -> StreamCounts {
+        self.streams.counts
+    }
+
+    fn on_tx(&mut self) {
+        if self.first_tx.is_none() {
+            let (after, early) = (self.started.elapsed(), self.conn.is_in_early_data());
+            println!("[QUIC] First TX to {} after {:.1}ms ({})", self.peer, after.as_secs_f64() * 1e3, if early { "0-RTT" } else { "1-RTT" });
+            self.first_tx = Some((after, early));
         }
     }
 
-    pub fn drain_streams(&mut self) {
+    /// `[STATS]` line: reply latency since the last one (`-` without replies), then `io`.
+    fn log_stats(&mut self, io: &str) {
+        let latency = if self.stats_count > 0 {
+            let avg_lat_us = (self.stats_sum_ns as f64 / self.stats_count as f64) / 1000.0;
+            let min_lat_us = self.min_lat_ns as f64 / 1000.0;
+            let max_lat_us = self.max_lat_ns as f64 / 1000.0;
+            format!("Avg={:.1} Min={:.1} Max={:.1}", avg_lat_us, min_lat_us, max_lat_us)
+        } else {
+            "-".to_string()
+        };
+        let first_tx = match self.first_tx {
+            Some((after, early)) => format!("{:.1}ms {}", after.as_secs_f64() * 1e3, if early { "0-RTT" } else { "1-RTT" }),
+            None => "-".to_string(),
+        };
+        let sep = if io.is_empty() { "" } else { " | " };
+        println!("[STATS] {} Lat(us) {} | RX: {} | Lost: {} | First TX: {}{}{}",
+            self.peer,
+            latency,
+            self.total_rx_msgs,
+            self.lost_packets,
+            first_tx,
+            sep, io
+        );
+
+        self.stats_count = 0;
+        self.stats_sum_ns = 0;
+        self.min_lat_ns = u64::MAX;
+        self.max_lat_ns = 0;
+    }
+
+    fn reconnect_due(&self) -> bool {
+        !self.rejected && self.closed_at.is_some_and(|at| at.elapsed() >= RECONNECT_DELAY)
+    }
+
+    /// Runs once the handshake completes. A server whose key isn't the pinned identity gets
+    /// the connection closed before any stream data is sent to it.
+    fn check_identity(&mut self) {
+        let Some(want) = self.pin else {
+            println!("[QUIC] Connection to {} established", self.peer);
+            self.verified = true;
+            return;
+        };
+        match self.conn.peer_cert().and_then(keypair::cert_ed25519_pubkey) {
+            Some(got) if got == want => {
+                println!("[QUIC] Connection to {} established, identity {} verified", self.peer, bs58::encode(want).into_string());
+                self.verified = true;
+            }
+            got => {
+                let got = got.map_or_else(|| "no ed25519 certificate".to_string(), |k| bs58::encode(k).into_string());
+                println!("[TLS] {} presented {}, expected {}; closing", self.peer, got, bs58::encode(want).into_string());
+                self.rejected = true;
+                let _ = self.conn.close(false, BAD_CERTIFICATE, b"server identity mismatch");
+            }
+        }
+    }
+
+    fn drain_streams(&mut self, stream_buf: &mut [u8]) {
         if self.conn.is_established() {
             // Only process stream 1 (server-initiated unidirectional for timestamps)
-            while let Ok((read_len, _fin)) = self.conn.stream_recv(1, &mut self.stream_buf) {
+            while let Ok((read_len, _fin)) = self.conn.stream_recv(1, stream_buf) {
                 if read_len == 0 { break; }
-                
+
                 // Append new data to message buffer
-                self.msg_buf.extend_from_slice(&self.stream_buf[..read_len]);
+                self.msg_buf.extend_from_slice(&stream_buf[..read_len]);
             }
-            
+
             // Process complete 17-byte messages
             while self.msg_buf.len() >= 17 {
                 // Find magic byte
@@ -92,17 +307,17 @@ impl QuicDriver {
                     self.msg_buf.remove(0);
                     continue;
                 }
-                
+
                 // Extract complete message
                 let ts_bytes: [u8; 8] = self.msg_buf[1..9].try_into().unwrap();
                 let server_ts = u64::from_le_bytes(ts_bytes);
-                
+
                 let seq_bytes: [u8; 8] = self.msg_buf[9..17].try_into().unwrap();
                 let seq = u64::from_le_bytes(seq_bytes);
-                
+
                 // Remove processed message
                 self.msg_buf.drain(..17);
-                
+
                 // Loss Detection
                 if let Some(last) = self.last_seq {
                     if seq > last + 1 {
@@ -111,67 +326,488 @@ impl QuicDriver {
                     }
                 }
                 self.last_seq = Some(seq);
-                
+
                 // Latency Calculation
                 let now_ns = std::time::SystemTime::now()
                     .duration_since(std::time::UNIX_EPOCH)
                     .unwrap()
                     .as_nanos() as u64;
-                
+
                 let latency_ns = now_ns.saturating_sub(server_ts);
-                
+
                 if latency_ns < self.min_lat_ns { self.min_lat_ns = latency_ns; }
                 if latency_ns > self.max_lat_ns { self.max_lat_ns = latency_ns; }
-                
+
                 self.stats_count += 1;
                 self.stats_sum_ns += latency_ns;
                 self.total_rx_msgs += 1;
-                
-                if self.last_stats_time.elapsed() >= std::time::Duration::from_millis(500) && self.stats_count > 0 {
-                    let avg_lat_us = (self.stats_sum_ns as f64 / self.stats_count as f64) / 1000.0;
-                    let min_lat_us = self.min_lat_ns as f64 / 1000.0;
-                    let max_lat_us = self.max_lat_ns as f64 / 1000.0;
-                    
-                    println!("[STATS] Lat(us) Avg={:.1} Min={:.1} Max={:.1} | RX: {} | Lost: {}", 
-                        avg_lat_us, min_lat_us, max_lat_us,
-                        self.total_rx_msgs,
-                        self.lost_packets
-                    );
-                    
-                    self.stats_count = 0;
-                    self.stats_sum_ns = 0;
-                    self.min_lat_ns = u64::MAX;
-                    self.max_lat_ns = 0;
-                    self.last_stats_time = std::time::Instant::now();
-                }
             }
-            
+
             for stream_id in self.conn.readable() {
                 if stream_id == 1 { continue; }
-                while let Ok((read_len, _fin)) = self.conn.stream_recv(stream_id, &mut self.stream_buf) {
+                while let Ok((read_len, _fin)) = self.conn.stream_recv(stream_id, stream_buf) {
                     if read_len == 0 { break; }
                 }
             }
         }
-        
-        if self.conn.is_closed() && !self.closed_seen {
-            self.closed_seen = true;
+
+        if self.conn.is_closed() && self.closed_at.is_none() {
+            self.closed_at = Some(Instant::now());
             if let Some(err) = self.conn.peer_error() {
-                println!("[CLOSE] Peer: is_app={} code={} reason={:?}", 
-                    err.is_app, err.error_code, String::from_utf8_lossy(&err.reason));
+                println!("[CLOSE] {} Peer: is_app={} code={} reason={:?}",
+                    self.peer, err.is_app, err.error_code, String::from_utf8_lossy(&err.reason));
             } else if let Some(err) = self.conn.local_error() {
-                println!("[CLOSE] Local: is_app={} code={} reason={:?}", 
-                    err.is_app, err.error_code, String::from_utf8_lossy(&err.reason));
+                println!("[CLOSE] {} Local: is_app={} code={} reason={:?}",
+                    self.peer, err.is_app, err.error_code, String::from_utf8_lossy(&err.reason));
             } else if self.conn.is_timed_out() {
-                println!("[CLOSE] Timed out");
+                println!("[CLOSE] {} Timed out", self.peer);
             } else {
-                println!("[CLOSE] Unknown reason");
+                println!("[CLOSE] {} Unknown reason", self.peer);
             }
-            self.close_timer = Some(std::time::Instant::now());
         }
     }
+}
+
+/// Client connections to any number of peers, all sharing one local address (and socket).
+/// Inbound packets are matched by DCID, falling back to the source address; outbound
+/// packets are taken round-robin so a busy connection can't starve the others.
+pub struct QuicDriver {
+    config: quiche::Config,
+    pins: Option<HashMap<SocketAddr, [u8; 32]>>,
+    sessions: SessionCache,
+    local: SocketAddr,
+    tag: u8,
+    rng: SystemRandom,
+    conns: Vec<Connection>,
+    by_dcid: HashMap<quiche::ConnectionId<'static>, usize>,
+    by_peer: HashMap<SocketAddr, usize>,
+    next_tx: usize,
+    stream_buf: [u8; 65535],
+    unroutable: u64,
+    // Of connections replaced or removed: streams they finished, transactions they never sent
+    retired_finished: u64,
+    discarded: u64,
+}
+
+impl QuicDriver {
+    /// `tag` ends up in the last byte of every SCID (the worker index).
+    pub fn new(local: SocketAddr, tag: u8, tls: &ClientTls) -> Self {
+        let mut config = quiche::Config::new(quiche::PROTOCOL_VERSION).unwrap();
+        if let Some(keypair) = &tls.identity {
+            keypair.load_into(&mut config).unwrap_or_else(|e| panic!("client certificate: {}", e));
+        }
+
+        let mut pins = None;
+        match &tls.verify {
+            PeerVerify::None => config.verify_peer(false),
+            PeerVerify::CaBundle(path) => {
+                config.verify_peer(true);
+                config
+                    .load_verify_locations_from_file(&path.to_string_lossy())
+                    .unwrap_or_else(|e| panic!("CA bundle {}: {}", path.display(), e));
+            }
+            // Checked against the certificate ourselves once the handshake is done
+            PeerVerify::Pin(keys) => {
+                config.verify_peer(false);
+                pins = Some(keys.clone());
+            }
+        }
 
+        config.set_application_protos(&[b"solana-tpu"]).unwrap();
+        config.set_max_ack_delay(0);
+        config.set_ack_delay_exponent(0);
+        config.set_disable_active_migration(true);
+        config.enable_early_data();
+        config.set_initial_max_data(100_000_000);
+        config.set_initial_max_stream_data_bidi_local(10_000_000);
+        config.set_initial_max_stream_data_bidi_remote(10_000_000);
+        config.set_initial_max_stream_data_uni(10_000_000);
+        config.set_initial_max_streams_bidi(1000);
+        config.set_initial_max_streams_uni(1000);
+
+        QuicDriver {
+            config,
+            pins,
+            sessions: tls.sessions.clone(),
+            local,
+            tag,
+            rng: SystemRandom::new(),
+            conns: Vec::new(),
+            by_dcid: HashMap::new(),
+            by_peer: HashMap::new(),
+            next_tx: 0,
+            stream_buf: [0; 65535],
+            unroutable: 0,
+            retired_finished: 0,
+            discarded: 0,
+        }
+    }
+
+    /// Start a connection to `peer`, unless one is already open. A closed connection to the
+    /// same peer is replaced. A cached session is resumed, so data can go out as 0-RTT.
+    pub fn connect(&mut self, peer: SocketAddr) -> &mut Connection {
+        let existing = self.by_peer.get(&peer).copied();
+        if let Some(i) = existing.filter(|i| !self.conns[*i].conn.is_closed()) {
+            return &mut self.conns[i];
+        }
+
+        let pin = self.pins.as_ref().map(|pins| *pins.get(&peer).unwrap_or_else(|| panic!("no pinned identity for {}", peer)));
+        let scid = new_scid(&self.rng, self.tag);
+        let mut conn = quiche::connect(None, &scid, self.local, peer, &mut self.config).expect("quiche::connect");
+        if let Some(session) = self.sessions.get(peer) {
+            if let Err(e) = conn.set_session(&session) {
+                println!("[QUIC] Can't resume the session with {}: {}", peer, e);
+            }
+        }
+        let entry = Connection::new(conn, peer, scid.clone(), pin);
+        let i = match existing {
+            Some(i) => {
+                let old = std::mem::replace(&mut self.conns[i], entry);
+                self.by_dcid.remove(&old.scid);
+                self.retire(&old);
+                i
+            }
+            None => {
+                self.conns.push(entry);
+                self.conns.len() - 1
+            }
+        };
+        self.by_dcid.insert(scid, i);
+        self.by_peer.insert(peer, i);
+        &mut self.conns[i]
+    }
+
+    pub fn connections_mut(&mut self) -> &mut [Connection] {
+        &mut self.conns
+    }
+
+    /// Datagrams that matched no connection.
+    #[cfg(test)]
+    pub fn unroutable(&self) -> u64 {
+        self.unroutable
+    }
+
+    /// Transactions whose stream was written in full, FIN included, over every connection so
+    /// far. Queued ones only count once they get a stream.
+    pub fn tx_count(&self) -> u64 {
+        self.retired_finished + self.conns.iter().map(|c| c.streams.counts.finished).sum::<u64>()
+    }
+
+    /// Keep the totals of a connection leaving the table; whatever it still held is dropped.
+    fn retire(&mut self, old: &Connection) {
+        let unsent = (old.streams.queue.len() + old.streams.partial.len()) as u64;
+        self.retired_finished += old.streams.counts.finished;
+        self.discarded += unsent;
+        if unsent > 0 {
+            println!("[STREAMS] {} closed with {} transaction(s) unsent, dropped", old.peer, unsent);
+        }
+    }
+
+    /// Take connection `i` out of the table; the last one moves into its slot.
+    fn remove(&mut self, i: usize) {
+        let gone = self.conns.swap_remove(i);
+        self.retire(&gone);
+        self.by_dcid.remove(&gone.scid);
+        self.by_peer.remove(&gone.peer);
+        if let Some(moved) = self.conns.get(i) {
+            self.by_dcid.insert(moved.scid.clone(), i);
+            self.by_peer.insert(moved.peer, i);
+        }
+        if self.next_tx >= self.conns.len() {
+            self.next_tx = 0;
+        }
+    }
+
+    pub fn process_input(&mut self, data: &mut [u8], from: SocketAddr) {
+        let by_dcid = match quiche::Header::from_slice(data, SCID_LEN) {
+            Ok(hdr) => self.by_dcid.get(&hdr.dcid).copied(),
+            Err(_) => None,
+        };
+        let Some(i) = by_dcid.or_else(|| self.by_peer.get(&from).copied()) else {
+            self.unroutable += 1;
+            return;
+        };
+
+        let c = &mut self.conns[i];
+        let recv_info = quiche::RecvInfo { from, to: self.local };
+        if c.conn.recv(data, recv_info).is_ok()
+            && c.conn.is_established()
+            && !c.established_seen
+        {
+            c.established_seen = true;
+            c.check_identity();
+        }
+        // The ticket arrives after the handshake, in NewSessionTicket
+        if c.verified && !c.session_saved {
+            if let Some(session) = c.conn.session() {
+                self.sessions.put(c.peer, session);
+                c.session_saved = true;
+            }
+        }
+    }
+
+    /// Next packet to send, from the connections in turn. Returns its length and destination.
+    pub fn write_transmit(&mut self, frame: &mut [u8]) -> Option<(usize, SocketAddr)> {
+        let n = self.conns.len();
+        for k in 0..n {
+            let i = (self.next_tx + k) % n;
+            if let Ok((written, info)) = self.conns[i].conn.send(frame) {
+                self.next_tx = (i + 1) % n;
+                return Some((written, info.to));
+            }
+        }
+        None
+    }
+
+    /// Push out what's waiting on flow control or stream credit.
+    pub fn flush_streams(&mut self) {
+        for c in self.conns.iter_mut().filter(|c| c.is_ready()) {
+            c.streams.flush(&mut c.conn);
+        }
+    }
+
+    /// A `[STATS]` line per connection, with `io` (the backend counters) appended.
+    pub fn log_stats(&mut self, io: &str) {
+        for c in self.conns.iter_mut() {
+            c.log_stats(io);
+        }
+    }
+
+    pub fn log_streams(&self) {
+        for c in &self.conns {
+            let s = c.streams.counts;
+            println!("[STREAMS] {} opened={} finished={} queued={} waiting={} dropped={} stopped={} failed={} skipped={}",
+                c.peer, s.opened, s.finished, s.queued, c.streams.queue.len(), s.dropped, s.stopped, s.failed, s.skipped);
+        }
+        // Across reconnects: discarded were queued or partly written when their connection closed
+        println!("[STREAMS] total finished={} discarded={} unroutable={}", self.tx_count(), self.discarded, self.unroutable);
+    }
+
+    pub fn drain_streams(&mut self) {
+        for c in self.conns.iter_mut() {
+            c.drain_streams(&mut self.stream_buf);
+        }
+    }
+
+    /// Fire the timers that are due.
     pub fn on_timeout(&mut self) {
-        self.conn.on_timeout();
+        let now = Instant::now();
+        for c in self.conns.iter_mut() {
+            if c.conn.timeout_instant().is_some_and(|at| at <= now) {
+                c.conn.on_timeout();
+            }
+        }
     }
-}
\ No newline at end of file
+
+    /// Forget connections closed for good (a rejected identity) once their close is logged,
+    /// and dial the others again a little later (idle timeout, server close).
+    pub fn reconnect_closed(&mut self) {
+        let mut i = 0;
+        while i < self.conns.len() {
+            if self.conns[i].rejected && self.conns[i].closed_at.is_some() {
+                self.remove(i);
+            } else {
+                i += 1;
+            }
+        }
+
+        let due: Vec<SocketAddr> = self.conns.iter().filter(|c| c.reconnect_due()).map(|c| c.peer).collect();
+        for peer in due {
+            println!("[QUIC] Reconnecting to {}", peer);
+            self.connect(peer);
+        }
+    }
+
+    pub fn close_all(&mut self, err: u64, reason: &[u8]) {
+        for c in self.conns.iter_mut() {
+            let _ = c.conn.close(true, err, reason);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::{HashMap, HashSet};
+    use std::sync::Arc;
+    use std::time::{Duration, Instant};
+    use super::*;
+    use crate::fixture::{client_conn, establish, local, peer, pump, setup, TestServer, LINK_1MS, TX_LEN};
+    use crate::loopback;
+    use crate::worker::Worker;
+
+    fn ed25519() -> rcgen::KeyPair {
+        rcgen::KeyPair::generate_for(&rcgen::PKCS_ED25519).unwrap()
+    }
+
+    /// A client pinning `pin` for `peer(0)`.
+    fn pinned(pin: [u8; 32]) -> ClientTls {
+        ClientTls { verify: PeerVerify::Pin(HashMap::from([(peer(0), pin)])), ..Default::default() }
+    }
+
+    #[test]
+    fn routes_by_dcid_then_peer_address() {
+        let (mut client, mut servers, clock) = setup(LINK_1MS, &ClientTls::default(), &[&TestServer::new, &TestServer::new]);
+        // Each handshake only completes if the server's packets reach the connection to it
+        establish(&mut client, &mut servers, &clock);
+        pump(&mut client, &mut servers, &clock, 500);
+        for server in &servers {
+            assert!(server.finished.len() > 50, "{} got {} streams", server.local, server.finished.len());
+            assert!(server.finished.iter().all(|(_, len)| *len == TX_LEN));
+        }
+        let driver = client.driver();
+        assert_eq!(driver.unroutable(), 0);
+
+        // A known DCID wins over an unknown source; an unknown DCID falls back to the source
+        let stranger: SocketAddr = "10.0.0.99:9999".parse().unwrap();
+        let dcid = driver.connections_mut()[1].conn.source_id().into_owned();
+        let mut known = vec![0x40];
+        known.extend_from_slice(&dcid);
+        known.resize(64, 0);
+        driver.process_input(&mut known, stranger);
+        let mut junk = vec![0x40; 64];
+        driver.process_input(&mut junk.clone(), peer(1));
+        assert_eq!(driver.unroutable(), 0);
+        driver.process_input(&mut junk, stranger);
+        assert_eq!(driver.unroutable(), 1);
+    }
+
+    #[test]
+    fn write_transmit_takes_connections_in_turn() {
+        let (client, mut servers, clock) = setup(LINK_1MS, &ClientTls::default(), &[&TestServer::new, &TestServer::new]);
+        // Nothing to flood, so the only data is what is queued below
+        let mut client = client.with_transactions(Arc::from(Vec::new()), 0..0);
+        establish(&mut client, &mut servers, &clock);
+        pump(&mut client, &mut servers, &clock, 100);
+
+        for c in client.driver().connections_mut() {
+            for _ in 0..20 {
+                assert_eq!(c.send_tx(&[0x5A; 1000]), TxOutcome::Sent);
+            }
+        }
+        let mut frame = [0; 1500];
+        let order: Vec<_> = (0..8).map(|_| client.driver().write_transmit(&mut frame).expect("nothing to send").1).collect();
+        assert!(order.windows(2).all(|w| w[0] != w[1]), "one connection went twice: {:?}", order);
+    }
+
+    #[test]
+    fn rejected_peer_is_pruned_and_the_rest_keep_flooding() {
+        let (imposter, validator) = (ed25519(), ed25519());
+        let pin: [u8; 32] = validator.public_key_raw().try_into().unwrap();
+        let tls = ClientTls { verify: PeerVerify::Pin(HashMap::from([(peer(0), pin), (peer(1), pin)])), ..Default::default() };
+        // The imposter comes first, so the remaining connection moves to its slot
+        let (mut client, mut servers, clock) = setup(LINK_1MS, &tls, &[
+            &|io| TestServer::with_identity(io, &imposter),
+            &|io| TestServer::with_identity(io, &validator),
+        ]);
+
+        pump(&mut client, &mut servers, &clock, 500);
+        let rejected = &client.driver().connections_mut()[0];
+        assert!(rejected.rejected, "the imposter was not rejected");
+        assert_eq!(rejected.conn.local_error().map(|e| e.error_code), Some(BAD_CERTIFICATE));
+        assert!(rejected.conn.is_draining() || rejected.conn.is_closed());
+
+        // quiche ends the draining period on real time; stand in for it, then it is pruned
+        client.driver().connections_mut()[0].closed_at = Some(Instant::now());
+        client.driver().reconnect_closed();
+        let left: Vec<_> = client.driver().connections_mut().iter().map(|c| c.peer).collect();
+        assert_eq!(left, [peer(1)]);
+        assert_eq!(servers[0].rx_bytes, 0);
+
+        // Still routed by DCID and peer address from its new slot
+        let before = servers[1].finished.len();
+        pump(&mut client, &mut servers, &clock, 500);
+        assert!(servers[1].finished.len() > before + 50, "{} -> {} streams", before, servers[1].finished.len());
+        assert!(client_conn(&mut client).is_established());
+    }
+
+    #[test]
+    fn pinned_identity_is_flooded() {
+        let validator = ed25519();
+        let tls = pinned(validator.public_key_raw().try_into().unwrap());
+        let (mut client, mut servers, clock) = setup(LINK_1MS, &tls, &[&|io| TestServer::with_identity(io, &validator)]);
+        establish(&mut client, &mut servers, &clock);
+
+        pump(&mut client, &mut servers, &clock, 500);
+        assert!(client.driver().tx_count() > 0);
+        assert!(servers[0].rx_bytes > 0);
+    }
+
+    #[test]
+    fn pinned_identity_mismatch_closes_before_any_data() {
+        let imposter = ed25519();
+        let (mut client, mut servers, clock) = setup(LINK_1MS, &pinned([0x07; 32]), &[&|io| TestServer::with_identity(io, &imposter)]);
+
+        // Caught as it happens, before the connection is done closing
+        let mut closed = None;
+        for _ in 0..1000 {
+            pump(&mut client, &mut servers, &clock, 1);
+            let local = client.driver().connections_mut().first().and_then(|c| c.conn.local_error().map(|e| (e.is_app, e.error_code)));
+            closed = closed.or(local);
+            if closed.is_some() && servers[0].conn.as_ref().is_some_and(|c| c.peer_error().is_some()) { break; }
+        }
+
+        assert_eq!(closed, Some((false, BAD_CERTIFICATE)), "client did not close over the identity");
+        let err = servers[0].conn().peer_error().expect("server saw no CONNECTION_CLOSE");
+        assert!(!err.is_app);
+        assert_eq!(err.error_code, BAD_CERTIFICATE);
+        assert_eq!((client.driver().tx_count(), servers[0].rx_bytes), (0, 0), "data reached the imposter");
+    }
+
+    #[test]
+    fn resumed_session_floods_as_0rtt() {
+        let tls = ClientTls::default();
+
+        // A full handshake leaves a ticket in the (shared) cache
+        let (mut client, mut servers, clock) = setup(LINK_1MS, &tls, &[&TestServer::new]);
+        establish(&mut client, &mut servers, &clock);
+        for _ in 0..1000 {
+            if tls.sessions.get(peer(0)).is_some() { break; }
+            pump(&mut client, &mut servers, &clock, 1);
+        }
+        assert!(tls.sessions.get(peer(0)).is_some(), "no session ticket saved");
+        assert!(!client_conn(&mut client).is_resumed());
+
+        // The next client resumes it and floods before its handshake completes
+        let (hub, spokes) = loopback::star(LINK_1MS, &clock, &[peer(0)]);
+        servers[0].restart(spokes.into_iter().next().unwrap());
+        let mut client = Worker::new(0, vec![hub], local(), &[peer(0)], &tls);
+        let mut sent_early = 0;
+        while !client_conn(&mut client).is_established() {
+            assert!(clock.now() < Duration::from_secs(1), "resumed handshake did not complete");
+            pump(&mut client, &mut servers, &clock, 1);
+            if client_conn(&mut client).is_in_early_data() {
+                sent_early = client.driver().tx_count();
+            }
+        }
+        assert!(sent_early > 0, "nothing was sent as 0-RTT");
+        assert!(client_conn(&mut client).is_resumed());
+        assert!(servers[0].conn().is_resumed());
+
+        // The early streams arrive whole
+        pump(&mut client, &mut servers, &clock, 100);
+        let finished = &servers[0].finished;
+        assert!(finished.len() as u64 >= sent_early, "{} of {} early streams finished", finished.len(), sent_early);
+        assert!(finished.iter().all(|(_, len)| *len == TX_LEN));
+    }
+
+    #[test]
+    fn one_uni_stream_per_transaction() {
+        let (mut client, mut servers, clock) = setup(LINK_1MS, &ClientTls::default(), &[&|io| TestServer::new(io).limit_streams(8)]);
+        establish(&mut client, &mut servers, &clock);
+
+        pump(&mut client, &mut servers, &clock, 2000);
+        let counts = client.driver().connections_mut()[0].stream_counts();
+        let finished = &servers[0].finished;
+        assert!(finished.len() > 100, "only {} streams finished", finished.len());
+        let mut ids = HashSet::new();
+        for (id, len) in finished {
+            assert_eq!(id % 4, 2, "stream {} is not client-initiated uni", id);
+            assert_eq!(*len, TX_LEN, "stream {} carried {} bytes", id, len);
+            assert!(ids.insert(*id), "stream {} reused", id);
+        }
+        // Eight streams at a time, so the flooder must have waited on MAX_STREAMS
+        assert!(counts.queued > 0);
+        assert_eq!(counts.failed + counts.stopped + counts.dropped, 0);
+        assert!(counts.finished >= finished.len() as u64);
+    }
+}
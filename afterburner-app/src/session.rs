@@ -0,0 +1,127 @@
+use std::collections::HashMap;
+use std::fs::{self, OpenOptions};
+use std::io::{self, Write};
+use std::net::SocketAddr;
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use crate::keypair::Keypair;
+
+/// TLS session tickets per peer, shared by every worker. With a directory they also survive a
+/// restart, so the first flight after startup can already carry 0-RTT data.
+#[derive(Clone, Default)]
+pub struct SessionCache {
+    mem: Arc<Mutex<HashMap<SocketAddr, Vec<u8>>>>,
+    dir: Option<PathBuf>,
+    // A resumed session keeps the client certificate it was made with, so tickets are per identity
+    owner: String,
+}
+
+impl SessionCache {
+    pub fn new(dir: Option<PathBuf>, identity: Option<&Keypair>) -> io::Result<Self> {
+        if let Some(dir) = &dir {
+            fs::create_dir_all(dir)?;
+        }
+        Ok(SessionCache {
+            mem: Arc::default(),
+            dir,
+            owner: identity.map_or_else(|| "unstaked".to_string(), Keypair::pubkey_base58),
+        })
+    }
+
+    fn path(&self, peer: SocketAddr) -> Option<PathBuf> {
+        Some(self.dir.as_ref()?.join(format!("{}-{}.ticket", peer, self.owner)))
+    }
+
+    pub fn get(&self, peer: SocketAddr) -> Option<Vec<u8>> {
+        let mut mem = self.mem.lock().unwrap();
+        if let Some(session) = mem.get(&peer) {
+            return Some(session.clone());
+        }
+        let session = fs::read(self.path(peer)?).ok()?;
+        mem.insert(peer, session.clone());
+        Some(session)
+    }
+
+    pub fn put(&self, peer: SocketAddr, session: &[u8]) {
+        self.mem.lock().unwrap().insert(peer, session.to_vec());
+        if let Some(path) = self.path(peer) {
+            if let Err(e) = write_private(&path, session) {
+                println!("[QUIC] Can't save the session ticket for {} to {}: {}", peer, path.display(), e);
+            }
+        }
+    }
+}
+
+/// Tickets are resumption secrets: owner-only, like keypair files. Each write goes to its
+/// own temp file and is renamed into place, so concurrent workers never see half a ticket.
+fn write_private(path: &Path, contents: &[u8]) -> io::Result<()> {
+    static NEXT: AtomicUsize = AtomicUsize::new(0);
+    let tmp = path.with_extension(format!("tmp{}-{}", std::process::id(), NEXT.fetch_add(1, Ordering::Relaxed)));
+    let written = OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .mode(0o600)
+        .open(&tmp)
+        .and_then(|mut f| f.write_all(contents))
+        .and_then(|_| fs::rename(&tmp, path));
+    if written.is_err() {
+        let _ = fs::remove_file(&tmp);
+    }
+    written
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixture::TempDir;
+
+    use std::os::unix::fs::PermissionsExt;
+
+    #[test]
+    fn tickets_persist_per_peer() {
+        let tmp = TempDir::new("sessions");
+        let dir = tmp.path().to_path_buf();
+        let (a, b): (SocketAddr, SocketAddr) = ("10.0.0.11:8004".parse().unwrap(), "[fd00::11]:8004".parse().unwrap());
+
+        let cache = SessionCache::new(Some(dir.clone()), None).unwrap();
+        assert_eq!(cache.get(a), None);
+        cache.put(a, b"ticket-a");
+        cache.put(b, b"ticket-b");
+
+        // Readable by us only, and no temp files left behind
+        let path = cache.path(a).unwrap();
+        assert_eq!(fs::metadata(&path).unwrap().permissions().mode() & 0o777, 0o600);
+        assert_eq!(fs::read_dir(&dir).unwrap().count(), 2);
+
+        // A fresh process finds them on disk
+        let reloaded = SessionCache::new(Some(dir.clone()), None).unwrap();
+        assert_eq!(reloaded.get(a).as_deref(), Some(&b"ticket-a"[..]));
+        assert_eq!(reloaded.get(b).as_deref(), Some(&b"ticket-b"[..]));
+
+        // Memory-only caches are shared between clones (workers), not between instances
+        let mem = SessionCache::default();
+        mem.clone().put(a, b"ticket-a");
+        assert!(mem.get(a).is_some());
+        assert!(SessionCache::default().get(a).is_none());
+    }
+
+    #[test]
+    fn concurrent_puts_leave_one_whole_ticket() {
+        let tmp = TempDir::new("sessions");
+        let dir = tmp.path().to_path_buf();
+        let peer: SocketAddr = "10.0.0.11:8004".parse().unwrap();
+        let cache = SessionCache::new(Some(dir.clone()), None).unwrap();
+
+        let workers: Vec<_> = (0..8u8).map(|i| {
+            let cache = cache.clone();
+            std::thread::spawn(move || (0..50).for_each(|_| cache.put(peer, &[i; 512])))
+        }).collect();
+        workers.into_iter().for_each(|w| w.join().unwrap());
+
+        let ticket = fs::read(cache.path(peer).unwrap()).unwrap();
+        assert!(ticket.len() == 512 && ticket.iter().all(|b| *b == ticket[0]), "torn ticket");
+        assert_eq!(fs::read_dir(&dir).unwrap().count(), 1);
+    }
+}
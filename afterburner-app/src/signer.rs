@@ -0,0 +1,149 @@
+use std::fmt;
+use ring::signature::Ed25519KeyPair;
+use crate::emit::{Message, Pubkey, Transaction, WireError, PACKET_DATA_SIZE};
+use crate::keypair::Keypair;
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum SignError {
+    /// A required signer we hold no keypair for
+    MissingSigner(Pubkey),
+    /// The header wants more signers than the message has account keys
+    TooFewAccounts,
+    Wire(WireError),
+}
+
+impl From<WireError> for SignError {
+    fn from(e: WireError) -> Self {
+        SignError::Wire(e)
+    }
+}
+
+impl fmt::Display for SignError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SignError::MissingSigner(key) => write!(f, "no keypair for signer {}", bs58::encode(key).into_string()),
+            SignError::TooFewAccounts => write!(f, "fewer account keys than required signatures"),
+            SignError::Wire(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+/// Ed25519 signer over a set of keypairs. A message's first `num_required_signatures`
+/// account keys name its signers; each one must be among ours.
+pub struct Signer {
+    keys: Vec<(Pubkey, Ed25519KeyPair)>,
+}
+
+impl Signer {
+    pub fn new(keypairs: &[Keypair]) -> Self {
+        Signer { keys: keypairs.iter().map(|k| (*k.pubkey(), k.signing_key())).collect() }
+    }
+
+    /// Fee payers, in the order they were given.
+    pub fn pubkeys(&self) -> impl Iterator<Item = &Pubkey> {
+        self.keys.iter().map(|(pubkey, _)| pubkey)
+    }
+
+    fn key(&self, pubkey: &Pubkey) -> Result<&Ed25519KeyPair, SignError> {
+        self.keys.iter().find(|(k, _)| k == pubkey).map(|(_, key)| key).ok_or(SignError::MissingSigner(*pubkey))
+    }
+
+    /// Sign the serialized message and fill in every signature slot.
+    pub fn sign(&self, tx: &mut Transaction) -> Result<(), SignError> {
+        let mut buf = [0; PACKET_DATA_SIZE];
+        let len = tx.message.serialize(&mut buf)?;
+        let required = tx.message.header.num_required_signatures as usize;
+        let signers = tx.message.account_keys.get(..required).ok_or(SignError::TooFewAccounts)?;
+
+        tx.signatures = signers
+            .iter()
+            .map(|pubkey| Ok(self.key(pubkey)?.sign(&buf[..len]).as_ref().try_into().unwrap()))
+            .collect::<Result<_, SignError>>()?;
+        Ok(())
+    }
+
+    /// Sign and serialize every message up front, so the hot loop only copies bytes.
+    pub fn presign(&self, messages: impl Iterator<Item = Message>) -> Result<Vec<Vec<u8>>, SignError> {
+        let mut buf = [0; PACKET_DATA_SIZE];
+        messages
+            .map(|message| {
+                let mut tx = Transaction::new(message);
+                self.sign(&mut tx)?;
+                let len = tx.serialize(&mut buf)?;
+                Ok(buf[..len].to_vec())
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ring::signature::{UnparsedPublicKey, ED25519};
+    use super::*;
+    use crate::emit::MessageVersion;
+
+    fn keypair(seed: u8) -> Keypair {
+        let pair = Ed25519KeyPair::from_seed_unchecked(&[seed; 32]).unwrap();
+        let bytes = [&[seed; 32][..], ring::signature::KeyPair::public_key(&pair).as_ref()].concat();
+        Keypair::parse(&bs58::encode(bytes).into_string()).unwrap()
+    }
+
+    /// Check every signature against its signer's key, over the message as it went on the wire.
+    fn verify(wire: &[u8]) -> Transaction {
+        let tx = Transaction::deserialize(wire).unwrap();
+        let message = &wire[Transaction::message_offset(tx.signatures.len())..];
+        for (sig, key) in tx.signatures.iter().zip(&tx.message.account_keys) {
+            UnparsedPublicKey::new(&ED25519, key).verify(message, sig).expect("bad signature");
+        }
+        tx
+    }
+
+    #[test]
+    fn signs_every_required_signer() {
+        let (a, b) = (keypair(1), keypair(2));
+        let signer = Signer::new(&[a.clone(), b.clone()]);
+
+        // Two signers, the second not the fee payer; keys given in the other order
+        let mut message = Message::transfer(*b.pubkey(), [9; 32], 10, [3; 32]);
+        message.version = MessageVersion::V0;
+        message.account_keys.insert(1, *a.pubkey());
+        message.header.num_required_signatures = 2;
+        message.instructions[0] = crate::emit::CompiledInstruction { program_id_index: 3, accounts: vec![0, 2], data: vec![1] };
+
+        let mut tx = Transaction::new(message);
+        signer.sign(&mut tx).unwrap();
+        let mut wire = [0; PACKET_DATA_SIZE];
+        let len = tx.serialize(&mut wire).unwrap();
+        assert_eq!(verify(&wire[..len]), tx);
+
+        // Tampering breaks it
+        wire[len - 1] ^= 1;
+        let message = &wire[Transaction::message_offset(2)..len];
+        assert!(UnparsedPublicKey::new(&ED25519, b.pubkey()).verify(message, &tx.signatures[0]).is_err());
+    }
+
+    #[test]
+    fn refuses_unknown_signers() {
+        let signer = Signer::new(&[keypair(1)]);
+        let mut tx = Transaction::new(Message::transfer([5; 32], [9; 32], 1, [0; 32]));
+        assert_eq!(signer.sign(&mut tx), Err(SignError::MissingSigner([5; 32])));
+
+        tx.message.header.num_required_signatures = 4;
+        assert_eq!(signer.sign(&mut tx), Err(SignError::TooFewAccounts));
+    }
+
+    #[test]
+    fn presigns_distinct_transactions() {
+        let payers = [keypair(1), keypair(2)];
+        let signer = Signer::new(&payers);
+        let keys: Vec<Pubkey> = signer.pubkeys().copied().collect();
+        let batch = signer
+            .presign((0..8).map(|i| Message::transfer(keys[i % keys.len()], [9; 32], i as u64 + 1, [3; 32])))
+            .unwrap();
+
+        assert_eq!(batch.len(), 8);
+        let sigs: std::collections::HashSet<_> = batch.iter().map(|wire| verify(wire).signatures[0]).collect();
+        assert_eq!(sigs.len(), 8, "signatures repeat across the batch");
+        assert_eq!(verify(&batch[1]).message.account_keys[0], *payers[1].pubkey());
+    }
+}
@@ -0,0 +1,134 @@
+use std::cell::UnsafeCell;
+use std::mem::MaybeUninit;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+
+/// Bounded lock-free ring shared by exactly one producer and one consumer thread.
+/// `head` is only written by the consumer and `tail` only by the producer.
+struct Ring<T> {
+    slots: Box<[UnsafeCell<MaybeUninit<T>>]>,
+    mask: usize,
+    head: AtomicUsize,
+    tail: AtomicUsize,
+}
+
+// Each slot is owned by one side at a time, as handed over by head/tail
+unsafe impl<T: Send> Sync for Ring<T> {}
+
+impl<T> Drop for Ring<T> {
+    fn drop(&mut self) {
+        let (head, tail) = (*self.head.get_mut(), *self.tail.get_mut());
+        for i in head..tail {
+            unsafe { self.slots[i & self.mask].get_mut().assume_init_drop() };
+        }
+    }
+}
+
+pub struct Producer<T> {
+    ring: Arc<Ring<T>>,
+    // Cached consumer position, refreshed only when the ring looks full
+    head: usize,
+}
+
+pub struct Consumer<T> {
+    ring: Arc<Ring<T>>,
+    // Cached producer position, refreshed only when the ring looks empty
+    tail: usize,
+}
+
+/// Queue of at least `capacity` entries (rounded up to a power of two).
+pub fn channel<T: Send>(capacity: usize) -> (Producer<T>, Consumer<T>) {
+    let size = capacity.max(1).next_power_of_two();
+    let ring = Arc::new(Ring {
+        slots: (0..size).map(|_| UnsafeCell::new(MaybeUninit::uninit())).collect(),
+        mask: size - 1,
+        head: AtomicUsize::new(0),
+        tail: AtomicUsize::new(0),
+    });
+    (Producer { ring: Arc::clone(&ring), head: 0 }, Consumer { ring, tail: 0 })
+}
+
+impl<T> Producer<T> {
+    /// Hands `value` back if the queue is full.
+    pub fn push(&mut self, value: T) -> Result<(), T> {
+        let tail = self.ring.tail.load(Ordering::Relaxed);
+        if tail - self.head == self.ring.slots.len() {
+            self.head = self.ring.head.load(Ordering::Acquire);
+            if tail - self.head == self.ring.slots.len() {
+                return Err(value);
+            }
+        }
+        unsafe { (*self.ring.slots[tail & self.ring.mask].get()).write(value) };
+        self.ring.tail.store(tail + 1, Ordering::Release);
+        Ok(())
+    }
+}
+
+impl<T> Consumer<T> {
+    pub fn pop(&mut self) -> Option<T> {
+        let head = self.ring.head.load(Ordering::Relaxed);
+        if head == self.tail {
+            self.tail = self.ring.tail.load(Ordering::Acquire);
+            if head == self.tail {
+                return None;
+            }
+        }
+        let value = unsafe { (*self.ring.slots[head & self.ring.mask].get()).assume_init_read() };
+        self.ring.head.store(head + 1, Ordering::Release);
+        Some(value)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fifo_until_full() {
+        let (mut tx, mut rx) = channel(3);
+        for i in 0..4 {
+            tx.push(i).unwrap();
+        }
+        assert_eq!(tx.push(4), Err(4));
+        assert_eq!(rx.pop(), Some(0));
+        tx.push(4).unwrap();
+        assert_eq!((1..5).map(|_| rx.pop().unwrap()).collect::<Vec<_>>(), [1, 2, 3, 4]);
+        assert_eq!(rx.pop(), None);
+    }
+
+    #[test]
+    fn hands_over_between_threads() {
+        const N: u64 = 50_000;
+        let (mut tx, mut rx) = channel(64);
+        let producer = std::thread::spawn(move || {
+            for i in 0..N {
+                let mut v = vec![i];
+                while let Err(back) = tx.push(v) {
+                    v = back;
+                    std::thread::yield_now();
+                }
+            }
+        });
+        let mut expect = 0;
+        while expect < N {
+            match rx.pop() {
+                Some(v) => {
+                    assert_eq!(v, [expect]);
+                    expect += 1;
+                }
+                None => std::thread::yield_now(),
+            }
+        }
+        producer.join().unwrap();
+    }
+
+    #[test]
+    fn drops_what_is_left() {
+        let value = Arc::new(());
+        let (mut tx, rx) = channel(4);
+        tx.push(Arc::clone(&value)).unwrap();
+        tx.push(Arc::clone(&value)).unwrap();
+        drop((tx, rx));
+        assert_eq!(Arc::strong_count(&value), 1);
+    }
+}
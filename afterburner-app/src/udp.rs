@@ -0,0 +1,261 @@
+use std::net::{Ipv4Addr, Ipv6Addr, SocketAddr, SocketAddrV4, SocketAddrV6, UdpSocket};
+use std::ops::{Deref, DerefMut};
+use std::os::fd::AsRawFd;
+use std::{io, mem, ptr};
+use crate::packet_io::{PacketIo, TxPacket};
+
+const BATCH: usize = 64;
+// Ethernet MTU; QUIC never sends datagrams larger than the path allows
+const MAX_DATAGRAM: usize = 1500;
+
+/// Kernel UDP socket backend for hosts where XDP can't attach (containers, laptops,
+/// no CAP_NET_ADMIN/CAP_BPF). RX and TX are batched with `recvmmsg`/`sendmmsg`.
+pub struct UdpIo {
+    socket: UdpSocket,
+    rx_bufs: Vec<[u8; MAX_DATAGRAM]>,
+    rx_lens: [Option<usize>; BATCH],
+    rx_addrs: Vec<libc::sockaddr_storage>,
+    rx_count: usize,
+    rx_pos: usize,
+    // Datagrams larger than MAX_DATAGRAM, cut short by the kernel and dropped
+    rx_truncated: u64,
+    tx_bufs: Vec<[u8; MAX_DATAGRAM]>,
+    tx_lens: [usize; BATCH],
+    tx_addrs: Vec<(libc::sockaddr_storage, libc::socklen_t)>,
+    tx_count: usize,
+    // Datagrams dropped on a hard sendmmsg error
+    tx_errors: u64,
+}
+
+impl UdpIo {
+    pub fn bind(addr: SocketAddr) -> io::Result<Self> {
+        let socket = UdpSocket::bind(addr)?;
+        socket.set_nonblocking(true)?;
+
+        Ok(UdpIo {
+            socket,
+            rx_bufs: vec![[0; MAX_DATAGRAM]; BATCH],
+            rx_lens: [None; BATCH],
+            rx_addrs: vec![unsafe { mem::zeroed() }; BATCH],
+            rx_count: 0,
+            rx_pos: 0,
+            rx_truncated: 0,
+            tx_bufs: vec![[0; MAX_DATAGRAM]; BATCH],
+            tx_lens: [0; BATCH],
+            tx_addrs: vec![(unsafe { mem::zeroed() }, 0); BATCH],
+            tx_count: 0,
+            tx_errors: 0,
+        })
+    }
+
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.socket.local_addr()
+    }
+
+    /// Receive into the RX slots; a truncated datagram gets no length (`None`).
+    fn recv_batch(&mut self) -> usize {
+        unsafe {
+            let mut iovs: [libc::iovec; BATCH] = mem::zeroed();
+            let mut msgs: [libc::mmsghdr; BATCH] = mem::zeroed();
+            for (((iov, msg), buf), addr) in iovs.iter_mut().zip(msgs.iter_mut()).zip(self.rx_bufs.iter_mut()).zip(self.rx_addrs.iter_mut()) {
+                iov.iov_base = buf.as_mut_ptr() as *mut _;
+                iov.iov_len = buf.len();
+                msg.msg_hdr.msg_iov = iov;
+                msg.msg_hdr.msg_iovlen = 1;
+                msg.msg_hdr.msg_name = addr as *mut _ as *mut _;
+                msg.msg_hdr.msg_namelen = mem::size_of::<libc::sockaddr_storage>() as _;
+            }
+
+            let n = libc::recvmmsg(self.socket.as_raw_fd(), msgs.as_mut_ptr(), BATCH as _, libc::MSG_DONTWAIT, ptr::null_mut());
+            if n <= 0 { return 0; }
+
+            for (len, msg) in self.rx_lens.iter_mut().zip(&msgs[..n as usize]) {
+                *len = (msg.msg_hdr.msg_flags & libc::MSG_TRUNC == 0).then_some(msg.msg_len as usize);
+            }
+            n as usize
+        }
+    }
+}
+
+impl PacketIo for UdpIo {
+    type Rx<'a> = &'a mut [u8];
+    type Tx<'a> = UdpTx<'a>;
+
+    fn poll_rx(&mut self) -> Option<(SocketAddr, &mut [u8])> {
+        loop {
+            if self.rx_pos == self.rx_count {
+                self.rx_pos = 0;
+                self.rx_count = self.recv_batch();
+                if self.rx_count == 0 { return None; }
+            }
+
+            let i = self.rx_pos;
+            self.rx_pos += 1;
+            let Some(len) = self.rx_lens[i] else {
+                self.rx_truncated += 1;
+                continue;
+            };
+            if let Some(from) = sockaddr_to(&self.rx_addrs[i]) {
+                return Some((from, &mut self.rx_bufs[i][..len]));
+            }
+        }
+    }
+
+    fn get_tx_frame(&mut self) -> Option<UdpTx<'_>> {
+        if self.tx_count == BATCH {
+            self.tx_submit();
+            if self.tx_count == BATCH { return None; }
+        }
+        Some(UdpTx { io: self })
+    }
+
+    fn tx_submit(&mut self) -> usize {
+        if self.tx_count == 0 { return 0; }
+
+        // Taken off the batch: sent, or dropped on a hard error
+        let (taken, sent) = unsafe {
+            let mut iovs: [libc::iovec; BATCH] = mem::zeroed();
+            let mut msgs: [libc::mmsghdr; BATCH] = mem::zeroed();
+            for i in 0..self.tx_count {
+                iovs[i].iov_base = self.tx_bufs[i].as_mut_ptr() as *mut _;
+                iovs[i].iov_len = self.tx_lens[i];
+                msgs[i].msg_hdr.msg_iov = &mut iovs[i];
+                msgs[i].msg_hdr.msg_iovlen = 1;
+                msgs[i].msg_hdr.msg_name = &mut self.tx_addrs[i].0 as *mut _ as *mut _;
+                msgs[i].msg_hdr.msg_namelen = self.tx_addrs[i].1;
+            }
+
+            let n = libc::sendmmsg(self.socket.as_raw_fd(), msgs.as_mut_ptr(), self.tx_count as _, libc::MSG_DONTWAIT);
+            if n >= 0 {
+                (n as usize, n as usize)
+            } else if io::Error::last_os_error().kind() == io::ErrorKind::WouldBlock {
+                (0, 0)
+            } else {
+                // Hard error on the head datagram (e.g. unreachable): drop it, QUIC retransmits
+                self.tx_errors += 1;
+                (1, 0)
+            }
+        };
+
+        // Keep whatever the kernel didn't take for the next call
+        if taken < self.tx_count {
+            self.tx_bufs[..self.tx_count].rotate_left(taken);
+            self.tx_lens[..self.tx_count].rotate_left(taken);
+            self.tx_addrs[..self.tx_count].rotate_left(taken);
+        }
+        self.tx_count -= taken;
+        sent
+    }
+
+    fn stats(&self, label: &str) -> Option<String> {
+        Some(format!("UDP {} RX truncated={} TX errors={}", label, self.rx_truncated, self.tx_errors))
+    }
+}
+
+/// Next free slot in the `sendmmsg` batch.
+pub struct UdpTx<'a> {
+    io: &'a mut UdpIo,
+}
+
+impl Deref for UdpTx<'_> {
+    type Target = [u8];
+    fn deref(&self) -> &[u8] { &self.io.tx_bufs[self.io.tx_count] }
+}
+
+impl DerefMut for UdpTx<'_> {
+    fn deref_mut(&mut self) -> &mut [u8] { &mut self.io.tx_bufs[self.io.tx_count] }
+}
+
+impl TxPacket for UdpTx<'_> {
+    fn submit(self, len: usize, _from: SocketAddr, to: SocketAddr) {
+        let i = self.io.tx_count;
+        self.io.tx_lens[i] = len;
+        self.io.tx_addrs[i] = sockaddr_from(to);
+        self.io.tx_count += 1;
+    }
+}
+
+fn sockaddr_from(addr: SocketAddr) -> (libc::sockaddr_storage, libc::socklen_t) {
+    unsafe {
+        let mut storage: libc::sockaddr_storage = mem::zeroed();
+        let len = match addr {
+            SocketAddr::V4(a) => {
+                let sin = &mut *(&mut storage as *mut _ as *mut libc::sockaddr_in);
+                sin.sin_family = libc::AF_INET as _;
+                sin.sin_port = a.port().to_be();
+                sin.sin_addr.s_addr = u32::from_ne_bytes(a.ip().octets());
+                mem::size_of::<libc::sockaddr_in>()
+            }
+            SocketAddr::V6(a) => {
+                let sin6 = &mut *(&mut storage as *mut _ as *mut libc::sockaddr_in6);
+                sin6.sin6_family = libc::AF_INET6 as _;
+                sin6.sin6_port = a.port().to_be();
+                sin6.sin6_flowinfo = a.flowinfo();
+                sin6.sin6_addr.s6_addr = a.ip().octets();
+                sin6.sin6_scope_id = a.scope_id();
+                mem::size_of::<libc::sockaddr_in6>()
+            }
+        };
+        (storage, len as libc::socklen_t)
+    }
+}
+
+fn sockaddr_to(storage: &libc::sockaddr_storage) -> Option<SocketAddr> {
+    unsafe {
+        match storage.ss_family as i32 {
+            libc::AF_INET => {
+                let sin = &*(storage as *const _ as *const libc::sockaddr_in);
+                let ip = Ipv4Addr::from(u32::from_be(sin.sin_addr.s_addr));
+                Some(SocketAddrV4::new(ip, u16::from_be(sin.sin_port)).into())
+            }
+            libc::AF_INET6 => {
+                let sin6 = &*(storage as *const _ as *const libc::sockaddr_in6);
+                let ip = Ipv6Addr::from(sin6.sin6_addr.s6_addr);
+                Some(SocketAddrV6::new(ip, u16::from_be(sin6.sin6_port), sin6.sin6_flowinfo, sin6.sin6_scope_id).into())
+            }
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn local() -> SocketAddr {
+        "127.0.0.1:0".parse().unwrap()
+    }
+
+    /// Poll until something arrives or the loopback has clearly delivered everything.
+    fn poll(io: &mut UdpIo) -> Option<Vec<u8>> {
+        for _ in 0..1000 {
+            if let Some((_, payload)) = io.poll_rx() {
+                return Some(payload.to_vec());
+            }
+            std::thread::yield_now();
+        }
+        None
+    }
+
+    #[test]
+    fn oversized_datagrams_are_dropped_and_counted() {
+        let mut io = UdpIo::bind(local()).unwrap();
+        let to = io.local_addr().unwrap();
+        let sender = UdpSocket::bind(local()).unwrap();
+        sender.send_to(&[7; MAX_DATAGRAM + 1], to).unwrap();
+        sender.send_to(&[8; 100], to).unwrap();
+
+        assert_eq!(poll(&mut io), Some(vec![8; 100]));
+        assert_eq!(io.rx_truncated, 1);
+    }
+
+    #[test]
+    fn hard_send_errors_are_not_counted_as_sent() {
+        let mut io = UdpIo::bind(local()).unwrap();
+        // An IPv6 destination on an IPv4 socket fails outright
+        let frame = io.get_tx_frame().unwrap();
+        frame.submit(10, local(), "[::1]:9".parse().unwrap());
+        assert_eq!(io.tx_submit(), 0);
+        assert_eq!((io.tx_count, io.tx_errors), (0, 1));
+    }
+}
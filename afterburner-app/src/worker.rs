@@ -0,0 +1,336 @@
+use std::net::SocketAddr;
+use std::ops::Range;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use crate::flood::Flooder;
+use crate::packet_io::{PacketIo, TxPacket};
+use crate::quic_driver::{ClientTls, QuicDriver, SCID_LEN};
+use crate::pacer::PaceConfig;
+use crate::spsc::{self, Consumer, Producer};
+
+const RX_BATCH: usize = 64;
+// Largest datagram handed between workers; anything bigger is processed where it landed
+const HANDOFF_MTU: usize = 2048;
+// Per pair of workers
+const HANDOFF_DEPTH: usize = 64;
+// How often the [STATS], [ROUTE], [STREAMS] and [PACE] lines are printed
+const IO_STATS_INTERVAL: Duration = Duration::from_millis(500);
+
+/// Worker index encoded in the last byte of an inbound packet's DCID (see `quic_driver::new_scid`),
+/// so a packet that RSS lands on another queue can be routed back to its owner.
+fn owner_of(payload: &[u8]) -> Option<usize> {
+    let dcid = if payload.first()? & 0x80 == 0 {
+        // Short header: DCID follows the first byte, length known from our SCID
+        payload.get(1..1 + SCID_LEN)?
+    } else {
+        // Long header: first byte, version (4), DCID len (1), DCID
+        let len = *payload.get(5)? as usize;
+        payload.get(6..6 + len)?
+    };
+    if dcid.len() != SCID_LEN { return None; }
+    Some(dcid[SCID_LEN - 1] as usize)
+}
+
+/// A datagram for another worker's connection, copied into a queue slot so the hand-off
+/// neither allocates nor locks.
+struct Handoff {
+    from: SocketAddr,
+    len: usize,
+    buf: [u8; HANDOFF_MTU],
+}
+
+/// One worker's ends of the queues between every pair of workers.
+pub struct Routes {
+    // Indexed by owner, `None` at our own index
+    to: Vec<Option<Producer<Handoff>>>,
+    from: Vec<Consumer<Handoff>>,
+    handed_off: u64,
+    dropped: u64,
+}
+
+/// Hand-off queues for `workers` workers, in worker order.
+pub fn routes(workers: usize) -> Vec<Routes> {
+    let mut all: Vec<_> = (0..workers).map(|_| Routes { to: Vec::new(), from: Vec::new(), handed_off: 0, dropped: 0 }).collect();
+    for src in 0..workers {
+        for dst in 0..workers {
+            if src == dst {
+                all[src].to.push(None);
+                continue;
+            }
+            let (tx, rx) = spsc::channel(HANDOFF_DEPTH);
+            all[src].to.push(Some(tx));
+            all[dst].from.push(rx);
+        }
+    }
+    all
+}
+
+impl Routes {
+    /// Queue `payload` for worker `owner`, dropping it if that queue is full. False when it
+    /// should be processed here instead: ours, no such worker, or too big.
+    fn hand_off(&mut self, owner: usize, from: SocketAddr, payload: &[u8]) -> bool {
+        let Some(Some(queue)) = self.to.get_mut(owner) else { return false };
+        if payload.len() > HANDOFF_MTU {
+            return false;
+        }
+        let mut handoff = Handoff { from, len: payload.len(), buf: [0; HANDOFF_MTU] };
+        handoff.buf[..payload.len()].copy_from_slice(payload);
+        match queue.push(handoff) {
+            Ok(()) => self.handed_off += 1,
+            Err(_) => self.dropped += 1,
+        }
+        true
+    }
+}
+
+/// One busy-poll loop: RX on its sockets, QUIC, flooding, TX.
+pub struct Worker<P: PacketIo> {
+    id: usize,
+    sockets: Vec<P>,
+    driver: QuicDriver,
+    flooder: Flooder,
+    local: SocketAddr,
+    // Cross-queue hand-off between workers; none in single-thread mode
+    routes: Option<Routes>,
+    last_io_stats: Instant,
+}
+
+impl<P: PacketIo> Worker<P> {
+    /// Connects to every address in `peers`.
+    pub fn new(id: usize, sockets: Vec<P>, local: SocketAddr, peers: &[SocketAddr], tls: &ClientTls) -> Self {
+        let mut driver = QuicDriver::new(local, id as u8, tls);
+        for peer in peers {
+            driver.connect(*peer);
+        }
+        Worker {
+            id,
+            sockets,
+            driver,
+            flooder: Flooder::new(),
+            local,
+            routes: None,
+            last_io_stats: Instant::now(),
+        }
+    }
+
+    /// Flood pre-signed `txs[range]`, each once, instead of the default unsigned transfer.
+    pub fn with_transactions(mut self, txs: Arc<[Vec<u8>]>, range: Range<usize>) -> Self {
+        self.flooder = self.flooder.with_transactions(txs, range);
+        self
+    }
+
+    /// Flood what the ingestion layer queues instead (see `ingest.rs`).
+    pub fn with_ingest(mut self, queue: Consumer<Vec<u8>>) -> Self {
+        self.flooder = self.flooder.with_ingest(queue);
+        self
+    }
+
+    /// Pace the flood instead of sending a fixed batch per iteration.
+    pub fn with_pace(mut self, config: PaceConfig) -> Self {
+        self.flooder = self.flooder.with_pace(config);
+        self
+    }
+
+    /// Pass packets for other workers' connections to them, and take theirs (see `routes`).
+    pub fn with_routing(mut self, routes: Routes) -> Self {
+        self.routes = Some(routes);
+        self
+    }
+
+    /// Run until `term` is set, then close the connection. Returns how many transactions went
+    /// out on a finished stream.
+    pub fn run(mut self, term: &AtomicBool) -> u64 {
+        while !term.load(Ordering::Relaxed) {
+            self.poll_once();
+            std::hint::spin_loop();
+        }
+
+        self.driver.close_all(0, b"done");
+        self.flush(16);
+        self.driver.tx_count()
+    }
+
+    /// One pass of the loop.
+    pub fn poll_once(&mut self) {
+        for socket in self.sockets.iter_mut() {
+            for _ in 0..RX_BATCH {
+                let Some((from, mut payload)) = socket.poll_rx() else { break };
+                let handed_off = match (&mut self.routes, owner_of(&payload)) {
+                    (Some(routes), Some(owner)) => routes.hand_off(owner, from, &payload),
+                    _ => false,
+                };
+                if !handed_off {
+                    self.driver.process_input(&mut payload, from);
+                }
+            }
+        }
+
+        if let Some(routes) = &mut self.routes {
+            for queue in routes.from.iter_mut() {
+                while let Some(mut handoff) = queue.pop() {
+                    self.driver.process_input(&mut handoff.buf[..handoff.len], handoff.from);
+                }
+            }
+        }
+
+        self.driver.on_timeout();
+        self.driver.reconnect_closed();
+        self.driver.drain_streams();
+        self.driver.flush_streams();
+        self.flooder.shoot(&mut self.driver);
+        self.flush(usize::MAX);
+
+        if self.last_io_stats.elapsed() >= IO_STATS_INTERVAL {
+            self.print_io_stats();
+            self.last_io_stats = Instant::now();
+        }
+    }
+
+    fn print_io_stats(&mut self) {
+        let io: Vec<String> = self.sockets.iter().enumerate().filter_map(|(i, s)| s.stats(&format!("w{}/s{}", self.id, i))).collect();
+        self.driver.log_stats(&io.join(" | "));
+        if let Some(routes) = &self.routes {
+            println!("[ROUTE] w{} handed_off={} dropped={}", self.id, routes.handed_off, routes.dropped);
+        }
+        self.driver.log_streams();
+        self.flooder.log_pace(&format!("w{}", self.id));
+    }
+
+    /// Drain up to `max` QUIC packets into one TX batch. TX can leave through any queue;
+    /// the first socket carries it.
+    fn flush(&mut self, max: usize) {
+        let socket = &mut self.sockets[0];
+        for _ in 0..max {
+            let Some(mut frame) = socket.get_tx_frame() else { break };
+            match self.driver.write_transmit(&mut frame) {
+                Some((quic_len, to)) if quic_len > 0 => frame.submit(quic_len, self.local, to),
+                // Dropping the unsubmitted frame returns it to the pool
+                _ => break,
+            }
+        }
+        socket.tx_submit();
+    }
+}
+
+#[cfg(test)]
+impl<P: PacketIo> Worker<P> {
+    pub fn driver(&mut self) -> &mut QuicDriver {
+        &mut self.driver
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixture::{client_conn, establish, pump, setup, TestServer, LINK_1MS};
+    use crate::loopback::LinkConfig;
+
+    #[test]
+    fn owner_follows_scid_tag() {
+        let rng = ring::rand::SystemRandom::new();
+        let scid = crate::quic_driver::new_scid(&rng, 3);
+        assert_ne!(scid, crate::quic_driver::new_scid(&rng, 3), "SCIDs should be random");
+
+        // Short header: flags, DCID
+        let mut short = vec![0x40];
+        short.extend_from_slice(&scid);
+        assert_eq!(owner_of(&short), Some(3));
+
+        // Long header: flags, version, DCID length, DCID
+        let mut long = vec![0xC0, 0, 0, 0, 1, SCID_LEN as u8];
+        long.extend_from_slice(&scid);
+        assert_eq!(owner_of(&long), Some(3));
+        long[5] = 8;
+        assert_eq!(owner_of(&long), None);
+    }
+
+    #[test]
+    fn handshake_then_flood() {
+        let (mut client, mut servers, clock) = setup(LINK_1MS, &ClientTls::default(), &[&TestServer::new]);
+
+        establish(&mut client, &mut servers, &clock);
+        assert_eq!(client.driver.tx_count(), 0);
+
+        pump(&mut client, &mut servers, &clock, 2000);
+        let sent = client.driver.tx_count() as usize;
+        assert!(sent > 100, "only {} transactions flooded", sent);
+        assert!(servers[0].rx_bytes > 0);
+        assert!(servers[0].finished.len() <= sent, "server finished {} of {} streams", servers[0].finished.len(), sent);
+        assert_eq!(client.sockets[0].outbound_stats().oversized, 0);
+    }
+
+    #[test]
+    fn hands_off_to_the_owner_without_allocating() {
+        let mut routes = routes(3);
+        let from: SocketAddr = "10.0.0.11:8004".parse().unwrap();
+        assert!(routes[0].hand_off(2, from, b"for w2"));
+        assert!(!routes[0].hand_off(0, from, b"ours"));
+        assert!(!routes[0].hand_off(3, from, b"no such worker"));
+        assert!(!routes[0].hand_off(1, from, &[0; HANDOFF_MTU + 1]));
+
+        let handoff = routes[2].from.iter_mut().find_map(|q| q.pop()).unwrap();
+        assert_eq!((handoff.from, &handoff.buf[..handoff.len]), (from, &b"for w2"[..]));
+        assert!(routes[1].from.iter_mut().all(|q| q.pop().is_none()));
+
+        // A full queue drops rather than blocking the RX loop
+        for _ in 0..=HANDOFF_DEPTH {
+            routes[1].hand_off(0, from, b"burst");
+        }
+        assert_eq!((routes[1].handed_off, routes[1].dropped), (HANDOFF_DEPTH as u64, 1));
+    }
+
+    #[test]
+    fn detects_loss_and_keeps_flooding() {
+        let (mut client, mut servers, clock) = setup(LINK_1MS, &ClientTls::default(), &[&TestServer::new]);
+        establish(&mut client, &mut servers, &clock);
+
+        // quiche's timers (PTO, loss time threshold, idle) run on the real clock, not the
+        // virtual one. So the link turns lossy only once the handshake is done, the flood keeps
+        // later packets coming, and loss is found by packet threshold from their ACKs. No
+        // reordering, which quiche could call lost or not depending on real elapsed time.
+        let lossy = LinkConfig { loss: 0.05, ..LINK_1MS };
+        client.sockets[0].set_link(lossy.clone());
+        servers[0].io.set_link(lossy);
+
+        pump(&mut client, &mut servers, &clock, 3000);
+        let before = client.driver.tx_count();
+        pump(&mut client, &mut servers, &clock, 1000);
+
+        assert!(client.sockets[0].outbound_stats().lost > 0);
+        assert!(client_conn(&mut client).stats().lost > 0, "quiche detected no loss");
+        assert!(client_conn(&mut client).is_established());
+        assert!(client.driver.tx_count() > before);
+    }
+
+    #[test]
+    fn client_close_reaches_server() {
+        let (mut client, mut servers, clock) = setup(LinkConfig::default(), &ClientTls::default(), &[&TestServer::new]);
+        establish(&mut client, &mut servers, &clock);
+        pump(&mut client, &mut servers, &clock, 10);
+
+        // `run` skips the loop when already terminated and goes straight to close + flush
+        let sent = client.run(&AtomicBool::new(true));
+        servers[0].step();
+
+        assert!(sent > 0);
+        let err = servers[0].conn().peer_error().expect("server saw no CONNECTION_CLOSE");
+        assert!(err.is_app);
+        assert_eq!(err.reason, b"done");
+    }
+
+    #[test]
+    fn server_close_reaches_client() {
+        let (mut client, mut servers, clock) = setup(LinkConfig::default(), &ClientTls::default(), &[&TestServer::new]);
+        establish(&mut client, &mut servers, &clock);
+
+        servers[0].conn().close(true, 7, b"bye").unwrap();
+        pump(&mut client, &mut servers, &clock, 5);
+
+        let conn = client_conn(&mut client);
+        let err = conn.peer_error().expect("client saw no CONNECTION_CLOSE");
+        assert!(err.is_app);
+        assert_eq!(err.error_code, 7);
+        assert!(conn.is_draining() || conn.is_closed());
+    }
+}
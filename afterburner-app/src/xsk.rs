@@ -1,59 +1,199 @@
 use std::ffi::CString;
-use std::{io, mem};
+use std::{fmt, io, mem};
+use std::ops::{Deref, DerefMut};
 use std::os::fd::RawFd;
 use std::ptr;
 use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::{Arc, Mutex};
 use libc::{
     close, mmap, munmap, setsockopt, socket, AF_XDP, MAP_ANONYMOUS, MAP_FAILED,
     MAP_HUGETLB, MAP_POPULATE, MAP_PRIVATE, MAP_SHARED, PROT_READ, PROT_WRITE,
-    SOCK_RAW, SOL_XDP, XDP_COPY, XDP_MMAP_OFFSETS, XDP_PGOFF_RX_RING, XDP_RX_RING,
-    XDP_TX_RING, XDP_UMEM_COMPLETION_RING, XDP_UMEM_FILL_RING,
-    XDP_UMEM_PGOFF_COMPLETION_RING, XDP_UMEM_PGOFF_FILL_RING, XDP_UMEM_REG,
+    SOCK_RAW, SOL_XDP, XDP_COPY, XDP_MMAP_OFFSETS, XDP_OPTIONS, XDP_OPTIONS_ZEROCOPY,
+    XDP_STATISTICS,
+    XDP_PGOFF_RX_RING, XDP_RING_NEED_WAKEUP, XDP_RX_RING, XDP_TX_RING,
+    XDP_UMEM_COMPLETION_RING, XDP_UMEM_FILL_RING, XDP_UMEM_PGOFF_COMPLETION_RING,
+    XDP_SHARED_UMEM, XDP_UMEM_PGOFF_FILL_RING, XDP_UMEM_REG, XDP_USE_NEED_WAKEUP,
+    XDP_ZEROCOPY,
 };
 
-// Constants
+// Defaults
 const UMEM_SIZE: usize = 8 * 1024 * 1024;
 const FRAME_SIZE: usize = 4096;
-const NUM_FRAMES: usize = UMEM_SIZE / FRAME_SIZE;
 const RING_SIZE: u32 = 2048;
 
+// Space the kernel reserves in front of every RX frame (XDP_PACKET_HEADROOM)
+const XDP_PACKET_HEADROOM: u32 = 256;
+
+/// Bytes of `struct xsk_tx_metadata` in front of a TX packet (Linux 6.11+).
+pub const TX_METADATA_LEN: usize = 16;
+// Not in libc yet
+const XDP_UMEM_TX_SW_CSUM: u32 = 1 << 1;
+const XDP_UMEM_TX_METADATA_LEN: u32 = 1 << 2;
+const XDP_TX_METADATA: u32 = 1 << 1;
+const XDP_TXMD_FLAGS_CHECKSUM: u64 = 1 << 1;
+
+/// UMEM and ring geometry for an `XdpSocket`.
+#[derive(Clone, Debug)]
+pub struct XskConfig {
+    umem_size: usize,
+    frame_size: usize,
+    fill_ring_size: u32,
+    comp_ring_size: u32,
+    rx_ring_size: u32,
+    tx_ring_size: u32,
+    rx_frames: Option<usize>,
+    headroom: u32,
+    tx_checksum_offload: bool,
+    bind_mode: BindMode,
+}
+
+impl Default for XskConfig {
+    fn default() -> Self {
+        XskConfig {
+            umem_size: UMEM_SIZE,
+            frame_size: FRAME_SIZE,
+            fill_ring_size: RING_SIZE,
+            comp_ring_size: RING_SIZE,
+            rx_ring_size: RING_SIZE,
+            tx_ring_size: RING_SIZE,
+            rx_frames: None,
+            headroom: 0,
+            tx_checksum_offload: false,
+            bind_mode: BindMode::Auto,
+        }
+    }
+}
+
+impl XskConfig {
+    /// Total UMEM bytes, a multiple of the frame size.
+    pub fn umem_size(mut self, bytes: usize) -> Self { self.umem_size = bytes; self }
+
+    /// UMEM chunk size: 2048 or 4096.
+    pub fn frame_size(mut self, bytes: usize) -> Self { self.frame_size = bytes; self }
+
+    pub fn fill_ring_size(mut self, entries: u32) -> Self { self.fill_ring_size = entries; self }
+
+    pub fn comp_ring_size(mut self, entries: u32) -> Self { self.comp_ring_size = entries; self }
+
+    pub fn rx_ring_size(mut self, entries: u32) -> Self { self.rx_ring_size = entries; self }
+
+    pub fn tx_ring_size(mut self, entries: u32) -> Self { self.tx_ring_size = entries; self }
+
+    /// Frames each socket hands to its fill ring for RX; the rest stay in the UMEM's
+    /// free pool for TX. Defaults to half the UMEM.
+    pub fn rx_frames(mut self, frames: usize) -> Self { self.rx_frames = Some(frames); self }
+
+    /// Bytes the kernel leaves free in front of each RX packet.
+    pub fn headroom(mut self, bytes: u32) -> Self { self.headroom = bytes; self }
+
+    /// Register the UMEM with room for TX metadata so checksums can be offloaded per packet.
+    /// In copy mode the kernel computes them instead.
+    pub fn tx_checksum_offload(mut self, enable: bool) -> Self { self.tx_checksum_offload = enable; self }
+
+    pub fn bind_mode(mut self, mode: BindMode) -> Self { self.bind_mode = mode; self }
+
+    pub fn num_frames(&self) -> usize {
+        self.umem_size / self.frame_size
+    }
+
+    fn rx_frame_count(&self) -> usize {
+        self.rx_frames.unwrap_or(self.num_frames() / 2)
+    }
+
+    pub fn validate(&self) -> Result<(), XskError> {
+        let invalid = |msg: String| Err(XskError::Config(msg));
+
+        if self.frame_size != 2048 && self.frame_size != 4096 {
+            return invalid(format!("frame size {} must be 2048 or 4096", self.frame_size));
+        }
+        if self.umem_size == 0 || !self.umem_size.is_multiple_of(self.frame_size) {
+            return invalid(format!("UMEM size {} must be a non-zero multiple of the frame size {}", self.umem_size, self.frame_size));
+        }
+        for (name, size) in [
+            ("fill", self.fill_ring_size),
+            ("completion", self.comp_ring_size),
+            ("rx", self.rx_ring_size),
+            ("tx", self.tx_ring_size),
+        ] {
+            if !size.is_power_of_two() {
+                return invalid(format!("{} ring size {} must be a power of two", name, size));
+            }
+        }
+        if self.rx_frame_count() > self.num_frames() {
+            return invalid(format!("{} RX frames exceed the {} frames in the UMEM", self.rx_frame_count(), self.num_frames()));
+        }
+        if self.rx_frame_count() > self.fill_ring_size as usize {
+            return invalid(format!("{} RX frames do not fit in a fill ring of {}", self.rx_frame_count(), self.fill_ring_size));
+        }
+        if self.headroom + XDP_PACKET_HEADROOM >= self.frame_size as u32 {
+            return invalid(format!("headroom {} leaves no room for packet data in a {} byte frame", self.headroom, self.frame_size));
+        }
+        Ok(())
+    }
+}
+
+const MPOL_PREFERRED: libc::c_int = 1;
+
 /// Allocate UMEM buffer using mmap, attempting HUGETLB for better TLB performance.
 /// Falls back to regular pages if huge pages are unavailable.
-unsafe fn allocate_umem(size: usize) -> Result<*mut u8, io::Error> {
+/// With a NUMA node the pages are placed on that node (the NIC's) before being faulted in.
+unsafe fn allocate_umem(size: usize, numa_node: Option<u32>) -> Result<*mut u8, io::Error> {
+    // MAP_POPULATE would fault pages in before the memory policy is set
+    let populate = if numa_node.is_some() { 0 } else { MAP_POPULATE };
+
     // Try with HUGETLB first (2MB pages reduce TLB entries from 2048 to 4 for 8MB)
-    let ptr = mmap(
+    let mut ptr = mmap(
         ptr::null_mut(),
         size,
         PROT_READ | PROT_WRITE,
-        MAP_PRIVATE | MAP_ANONYMOUS | MAP_HUGETLB | MAP_POPULATE,
+        MAP_PRIVATE | MAP_ANONYMOUS | MAP_HUGETLB | populate,
         -1,
         0,
     );
 
-    if ptr != MAP_FAILED {
-        return Ok(ptr as *mut u8);
-    }
+    if ptr == MAP_FAILED {
+        // Fallback to regular pages if HUGETLB fails
+        eprintln!("[afterburner] HUGETLB allocation failed, falling back to regular pages. \
+                   For optimal performance, configure huge pages: echo 64 | sudo tee /proc/sys/vm/nr_hugepages");
 
-    // Fallback to regular pages if HUGETLB fails
-    eprintln!("[afterburner] HUGETLB allocation failed, falling back to regular pages. \
-               For optimal performance, configure huge pages: echo 64 | sudo tee /proc/sys/vm/nr_hugepages");
+        ptr = mmap(
+            ptr::null_mut(),
+            size,
+            PROT_READ | PROT_WRITE,
+            MAP_PRIVATE | MAP_ANONYMOUS | populate,
+            -1,
+            0,
+        );
 
-    let ptr = mmap(
-        ptr::null_mut(),
-        size,
-        PROT_READ | PROT_WRITE,
-        MAP_PRIVATE | MAP_ANONYMOUS | MAP_POPULATE,
-        -1,
-        0,
-    );
+        if ptr == MAP_FAILED {
+            return Err(std::io::Error::last_os_error());
+        }
+    }
 
-    if ptr == MAP_FAILED {
-        return Err(std::io::Error::last_os_error());
+    if let Some(node) = numa_node {
+        bind_to_node(ptr as *mut u8, size, node);
     }
 
     Ok(ptr as *mut u8)
 }
 
+/// Prefer `node` for the pages backing `ptr`, then touch every page so they are
+/// allocated now rather than on the hot path.
+unsafe fn bind_to_node(ptr: *mut u8, size: usize, node: u32) {
+    // One bit per node, as many 64-bit words as `node` needs
+    let mut mask = vec![0u64; node as usize / 64 + 1];
+    mask[node as usize / 64] |= 1 << (node % 64);
+    let maxnode = mask.len() * 64 + 1;
+    let ret = libc::syscall(libc::SYS_mbind, ptr, size, MPOL_PREFERRED, mask.as_ptr(), maxnode, 0u32);
+    if ret != 0 {
+        eprintln!("[afterburner] mbind to NUMA node {} failed: {}", node, io::Error::last_os_error());
+    }
+
+    for off in (0..size).step_by(4096) {
+        ptr::write_volatile(ptr.add(off), 0);
+    }
+}
+
 #[repr(C)]
 struct XdpDesc {
     addr: u64,
@@ -68,6 +208,32 @@ struct XdpUmemReg {
     chunk_size: u32,
     headroom: u32,
     flags: u32,
+    tx_metadata_len: u32,
+}
+
+#[repr(C)]
+struct XskTxMetadata {
+    flags: u64,
+    csum_start: u16,
+    csum_offset: u16,
+    _pad: u32,
+}
+
+#[repr(C)]
+#[derive(Default)]
+struct XdpOptions {
+    flags: u32,
+}
+
+#[repr(C)]
+#[derive(Default)]
+struct XdpStatistics {
+    rx_dropped: u64,
+    rx_invalid_descs: u64,
+    tx_invalid_descs: u64,
+    rx_ring_full: u64,
+    rx_fill_ring_empty_descs: u64,
+    tx_ring_empty_descs: u64,
 }
 
 #[repr(C)]
@@ -88,219 +254,799 @@ struct XdpRingOffsets {
     flags: u64,
 }
 
-#[allow(dead_code)]
 struct XdpRing {
     producer: *mut AtomicU32,
     consumer: *mut AtomicU32,
+    flags: *mut AtomicU32,
     desc: *mut u8,
     size: u32,
     ptr: *mut libc::c_void,
     len: usize,
 }
 
-pub struct XdpSocket {
-    pub umem_ptr: *mut u8,
-    pub fd: RawFd,
-    umem_size: usize,
-    rx_ring: XdpRing,
-    tx_ring: XdpRing,
-    fill_ring: XdpRing,
-    comp_ring: XdpRing,
-    tx_free_frames: Vec<u64>,
-    pending_tx_addr: Option<u64>,
+// The mapping belongs to the ring; only its holder touches it
+unsafe impl Send for XdpRing {}
+
+/// How the AF_XDP socket should bind to the queue (`--bind`).
+#[derive(Clone, Copy, Debug, PartialEq, Eq, clap::ValueEnum)]
+pub enum BindMode {
+    /// Require zero-copy (XDP_ZEROCOPY); fails on drivers without support
+    #[value(name = "zerocopy")]
+    ZeroCopy,
+    /// Force copy mode (XDP_COPY)
+    Copy,
+    /// Let the kernel pick, preferring zero-copy
+    Auto,
 }
 
-impl XdpSocket {
-    pub fn new(iface: &str, queue_id: u32) -> Result<Self, io::Error> {
+#[derive(Debug)]
+pub enum XskError {
+    Io(io::Error),
+    /// Rejected `XskConfig`
+    Config(String),
+    /// bind(2) on the AF_XDP socket failed for the requested mode
+    Bind { iface: String, queue_id: u32, mode: BindMode, source: io::Error },
+}
+
+impl fmt::Display for XskError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            XskError::Io(err) => write!(f, "{}", err),
+            XskError::Config(msg) => write!(f, "invalid XSK config: {}", msg),
+            XskError::Bind { iface, queue_id, mode, source } => {
+                write!(f, "bind {} queue {} ({:?}) failed: {}", iface, queue_id, mode, source)?;
+                if *mode == BindMode::ZeroCopy && source.raw_os_error() == Some(libc::EOPNOTSUPP) {
+                    write!(f, " (driver has no zero-copy support, try --bind copy)")?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+impl std::error::Error for XskError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            XskError::Io(err) | XskError::Bind { source: err, .. } => Some(err),
+            XskError::Config(_) => None,
+        }
+    }
+}
+
+impl From<io::Error> for XskError {
+    fn from(err: io::Error) -> Self {
+        XskError::Io(err)
+    }
+}
+
+impl XdpRing {
+    /// mmap one ring of `fd` at page offset `pgoff`.
+    unsafe fn map(fd: RawFd, off: &XdpRingOffsets, size: u32, desc_size: usize, pgoff: i64) -> Result<XdpRing, io::Error> {
+        let len = off.desc as usize + (size as usize * desc_size);
+        let map = mmap(ptr::null_mut(), len, PROT_READ | PROT_WRITE, MAP_SHARED | MAP_POPULATE, fd, pgoff);
+        if map == MAP_FAILED { return Err(io::Error::last_os_error()); }
+
+        Ok(XdpRing {
+            producer: map.offset(off.producer as isize) as *mut AtomicU32,
+            consumer: map.offset(off.consumer as isize) as *mut AtomicU32,
+            flags: map.offset(off.flags as isize) as *mut AtomicU32,
+            desc: map.offset(off.desc as isize) as *mut u8,
+            size, ptr: map, len,
+        })
+    }
+
+    fn needs_wakeup(&self) -> bool {
+        unsafe { (*self.flags).load(Ordering::Relaxed) & XDP_RING_NEED_WAKEUP != 0 }
+    }
+}
+
+impl Drop for XdpRing {
+    fn drop(&mut self) {
+        unsafe { munmap(self.ptr, self.len); }
+    }
+}
+
+unsafe fn set_ring_size(fd: RawFd, ring: libc::c_int, size: u32) -> Result<(), io::Error> {
+    if setsockopt(fd, SOL_XDP, ring, &size as *const _ as *const _, 4) != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+unsafe fn mmap_offsets(fd: RawFd) -> Result<XdpMmapOffsets, io::Error> {
+    let mut off = XdpMmapOffsets::default();
+    let mut optlen = mem::size_of::<XdpMmapOffsets>() as u32;
+    if libc::getsockopt(fd, SOL_XDP, XDP_MMAP_OFFSETS, &mut off as *mut _ as *mut _, &mut optlen) != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(off)
+}
+
+/// Create the fill and completion rings on `fd` (needed once per device/queue pair).
+unsafe fn map_umem_rings(fd: RawFd, config: &XskConfig) -> Result<(XdpRing, XdpRing), io::Error> {
+    set_ring_size(fd, XDP_UMEM_FILL_RING, config.fill_ring_size)?;
+    set_ring_size(fd, XDP_UMEM_COMPLETION_RING, config.comp_ring_size)?;
+    let off = mmap_offsets(fd)?;
+
+    // FILL / COMP (u64 = 8 bytes)
+    let fill = XdpRing::map(fd, &off.fr, config.fill_ring_size, 8, XDP_UMEM_PGOFF_FILL_RING as i64)?;
+    let comp = XdpRing::map(fd, &off.cr, config.comp_ring_size, 8, XDP_UMEM_PGOFF_COMPLETION_RING as i64)?;
+    Ok((fill, comp))
+}
+
+/// Registered packet memory, shareable between sockets with `XDP_SHARED_UMEM`.
+///
+/// The socket that registers the UMEM owns it in the kernel; the first `XdpSocket`
+/// built on a `Umem` binds through that socket and takes its fill/completion rings.
+/// Later sockets (other queues or other devices) bind with `XDP_SHARED_UMEM` and get
+/// rings of their own. Free frames are pooled here, behind a lock, so sockets on any
+/// thread draw from the same memory and a frame received on one socket can be
+/// transmitted by another without copying.
+pub struct Umem {
+    ptr: *mut u8,
+    size: usize,
+    frame_size: usize,
+    num_frames: usize,
+    tx_metadata: bool,
+    fd: RawFd,
+    owner_rings: Mutex<Option<(XdpRing, XdpRing)>>,
+    free_frames: Mutex<Vec<u64>>,
+}
+
+// A frame is only accessed by the socket holding it, and the pool is locked
+unsafe impl Send for Umem {}
+unsafe impl Sync for Umem {}
+
+impl Umem {
+    pub fn new(config: &XskConfig, numa_node: Option<u32>) -> Result<Arc<Umem>, XskError> {
+        config.validate()?;
+
         unsafe {
             // 1. Socket
             let fd = socket(AF_XDP, SOCK_RAW, 0);
-            if fd < 0 { return Err(io::Error::last_os_error()); }
+            if fd < 0 { return Err(io::Error::last_os_error().into()); }
 
             // 2. UMEM (using mmap with HUGETLB for better TLB performance)
-            let umem_ptr = allocate_umem(UMEM_SIZE)?;
+            let ptr = match allocate_umem(config.umem_size, numa_node) {
+                Ok(ptr) => ptr,
+                Err(err) => {
+                    close(fd);
+                    return Err(err.into());
+                }
+            };
 
+            // From here on Drop releases the memory and fd on error
+            let mut umem = Umem {
+                ptr, size: config.umem_size, frame_size: config.frame_size, num_frames: config.num_frames(),
+                tx_metadata: config.tx_checksum_offload, fd,
+                owner_rings: Mutex::new(None),
+                free_frames: Mutex::new((0..config.num_frames()).rev().map(|i| (i * config.frame_size) as u64).collect()),
+            };
+
+            let (flags, tx_metadata_len) = match config.tx_checksum_offload {
+                true => (XDP_UMEM_TX_METADATA_LEN | XDP_UMEM_TX_SW_CSUM, TX_METADATA_LEN as u32),
+                false => (0, 0),
+            };
             let mr = XdpUmemReg {
-                addr: umem_ptr as u64, len: UMEM_SIZE as u64, chunk_size: FRAME_SIZE as u32, headroom: 0, flags: 0,
+                addr: ptr as u64, len: config.umem_size as u64, chunk_size: config.frame_size as u32,
+                headroom: config.headroom, flags, tx_metadata_len,
             };
             if setsockopt(fd, SOL_XDP, XDP_UMEM_REG, &mr as *const _ as *const _, mem::size_of::<XdpUmemReg>() as u32) != 0 {
-                return Err(io::Error::last_os_error());
+                return Err(io::Error::last_os_error().into());
             }
 
-            // 3. Ring Sizes
-            setsockopt(fd, SOL_XDP, XDP_UMEM_FILL_RING, &RING_SIZE as *const _ as *const _, 4);
-            setsockopt(fd, SOL_XDP, XDP_UMEM_COMPLETION_RING, &RING_SIZE as *const _ as *const _, 4);
-            setsockopt(fd, SOL_XDP, XDP_RX_RING, &RING_SIZE as *const _ as *const _, 4);
-            setsockopt(fd, SOL_XDP, XDP_TX_RING, &RING_SIZE as *const _ as *const _, 4);
-
-            // 4. Offsets
-            let mut off = XdpMmapOffsets::default();
-            let mut optlen = mem::size_of::<XdpMmapOffsets>() as u32;
-            libc::getsockopt(fd, SOL_XDP, XDP_MMAP_OFFSETS, &mut off as *mut _ as *mut _, &mut optlen);
-
-            // 5. Map Rings
-            
-            // FILL (u64 = 8 bytes)
-            let fill_len = off.fr.desc as usize + (RING_SIZE as usize * 8);
-            let fill_map = mmap(ptr::null_mut(), fill_len, PROT_READ | PROT_WRITE, MAP_SHARED | MAP_POPULATE, fd, XDP_UMEM_PGOFF_FILL_RING as i64);
-            if fill_map == MAP_FAILED { return Err(std::io::Error::last_os_error()); }
-            
-            let fill_ring = XdpRing {
-                producer: fill_map.offset(off.fr.producer as isize) as *mut AtomicU32,
-                consumer: fill_map.offset(off.fr.consumer as isize) as *mut AtomicU32,
-                desc: fill_map.offset(off.fr.desc as isize) as *mut u8,
-                size: RING_SIZE, ptr: fill_map, len: fill_len,
-            };
+            // 3. Fill / completion rings for the owning socket
+            umem.owner_rings = Mutex::new(Some(map_umem_rings(fd, config)?));
 
-            // COMP (u64 = 8 bytes)
-            let comp_len = off.cr.desc as usize + (RING_SIZE as usize * 8);
-            let comp_map = mmap(ptr::null_mut(), comp_len, PROT_READ | PROT_WRITE, MAP_SHARED | MAP_POPULATE, fd, XDP_UMEM_PGOFF_COMPLETION_RING as i64);
-            if comp_map == MAP_FAILED { return Err(std::io::Error::last_os_error()); }
-            
-            let comp_ring = XdpRing {
-                producer: comp_map.offset(off.cr.producer as isize) as *mut AtomicU32,
-                consumer: comp_map.offset(off.cr.consumer as isize) as *mut AtomicU32,
-                desc: comp_map.offset(off.cr.desc as isize) as *mut u8,
-                size: RING_SIZE, ptr: comp_map, len: comp_len,
-            };
+            Ok(Arc::new(umem))
+        }
+    }
 
-            // RX (xdp_desc = 16 bytes)
-            let rx_len = off.rx.desc as usize + (RING_SIZE as usize * 16); 
-            let rx_map = mmap(ptr::null_mut(), rx_len, PROT_READ | PROT_WRITE, MAP_SHARED | MAP_POPULATE, fd, XDP_PGOFF_RX_RING);
-            if rx_map == MAP_FAILED { 
-                return Err(std::io::Error::last_os_error()); 
-            }
-            
-            let rx_ring = XdpRing {
-                producer: rx_map.offset(off.rx.producer as isize) as *mut AtomicU32,
-                consumer: rx_map.offset(off.rx.consumer as isize) as *mut AtomicU32,
-                desc: rx_map.offset(off.rx.desc as isize) as *mut u8,
-                size: RING_SIZE, ptr: rx_map, len: rx_len,
-            };
+    /// Undo a `with_umem` that failed before binding `fd`: the owner's fill/completion rings
+    /// go back for the next socket to bind through, a sharing socket's rings and fd are freed.
+    unsafe fn abandon(&self, fd: RawFd, rings: (XdpRing, XdpRing)) {
+        if fd == self.fd {
+            *self.owner_rings.lock().unwrap() = Some(rings);
+        } else {
+            drop(rings);
+            close(fd);
+        }
+    }
 
-            // TX (xdp_desc = 16 bytes)
-            let tx_len = off.tx.desc as usize + (RING_SIZE as usize * 16);
-            let tx_map = mmap(ptr::null_mut(), tx_len, PROT_READ | PROT_WRITE, MAP_SHARED | MAP_POPULATE, fd, libc::XDP_PGOFF_TX_RING);
-            if tx_map == MAP_FAILED { return Err(std::io::Error::last_os_error()); }
-            
-            let tx_ring = XdpRing {
-                producer: tx_map.offset(off.tx.producer as isize) as *mut AtomicU32,
-                consumer: tx_map.offset(off.tx.consumer as isize) as *mut AtomicU32,
-                desc: tx_map.offset(off.tx.desc as isize) as *mut u8,
-                size: RING_SIZE, ptr: tx_map, len: tx_len,
-            };
+    /// Frames not currently on any ring or in use by a caller.
+    pub fn free_frames(&self) -> usize {
+        self.free_frames.lock().unwrap().len()
+    }
 
-            // 6. Init Fill
-            let mut prod = (*fill_ring.producer).load(Ordering::Acquire);
-            let desc_ptr = fill_ring.desc as *mut u64;
-            for i in 0..(NUM_FRAMES / 2) {
-                 *desc_ptr.add((prod as usize) & (RING_SIZE as usize - 1)) = (i * FRAME_SIZE) as u64;
-                 prod += 1;
-            }
-            (*fill_ring.producer).store(prod, Ordering::Release);
+    fn alloc_frame(&self) -> Option<u64> {
+        self.free_frames.lock().unwrap().pop()
+    }
 
-            // 7. Init TX
-            let mut tx_free_frames = Vec::new();
-            for i in (NUM_FRAMES/2)..NUM_FRAMES { tx_free_frames.push((i * FRAME_SIZE) as u64); }
+    fn free_frame(&self, addr: u64) {
+        self.free_frames.lock().unwrap().push(self.frame_start(addr));
+    }
 
-            // 8. Bind
-            let if_name = CString::new(iface)?;
+    /// Completions echo the submitted address, which may point inside the frame.
+    fn frame_start(&self, addr: u64) -> u64 {
+        addr - addr % self.frame_size as u64
+    }
+}
+
+impl Drop for Umem {
+    fn drop(&mut self) {
+        unsafe {
+            // Unmap the owner rings if no socket ever claimed them
+            self.owner_rings.get_mut().unwrap_or_else(|e| e.into_inner()).take();
+
+            // Unmap UMEM buffer
+            munmap(self.ptr as *mut libc::c_void, self.size);
+
+            // Close the registering socket
+            close(self.fd);
+        }
+    }
+}
+
+/// Kernel `XDP_STATISTICS` counters plus what userspace tracks for one socket.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct XskStats {
+    /// Dropped for reasons other than invalid descriptors (e.g. no fill entry)
+    pub rx_dropped: u64,
+    pub rx_invalid_descs: u64,
+    pub tx_invalid_descs: u64,
+    /// Dropped because the RX ring was full
+    pub rx_ring_full: u64,
+    /// Times the driver found the fill ring empty
+    pub rx_fill_ring_empty_descs: u64,
+    /// Times the kernel found the TX ring empty when kicked
+    pub tx_ring_empty_descs: u64,
+    /// Frames not in the free pool (on a ring, held by a guard or staged), counted over the
+    /// whole UMEM and so over every socket sharing it
+    pub umem_frames_in_flight: u64,
+    /// `tx_frame` calls that found no free frame
+    pub tx_frames_exhausted: u64,
+    /// `tx_frame` calls that found the TX ring full
+    pub tx_ring_full: u64,
+    /// Submitted TX frames the kernel has not completed yet
+    pub completion_lag: u64,
+}
+
+/// A received frame: UMEM offset and length.
+#[derive(Clone, Copy, Default)]
+pub struct RxDesc {
+    pub addr: u64,
+    pub len: usize,
+}
+
+/// Descriptors pulled from the RX ring per consumer update.
+const RX_BATCH: usize = 64;
+
+pub struct XdpSocket {
+    umem_ptr: *mut u8,
+    pub fd: RawFd,
+    umem: Arc<Umem>,
+    rx_ring: XdpRing,
+    tx_ring: XdpRing,
+    fill_ring: XdpRing,
+    comp_ring: XdpRing,
+    // Descriptors taken off the RX ring but not yet handed out by `recv`
+    rx_cache: Vec<RxDesc>,
+    rx_cache_pos: usize,
+    // Frames whose RxFrame was dropped, waiting to go back to the fill ring in one update
+    rx_recycle: Vec<RxDesc>,
+    // Filled frames waiting for the next tx_submit_batch
+    // (addr, len, options)
+    tx_batch: Vec<(u64, u32, u32)>,
+    zero_copy: bool,
+    tx_submitted: u64,
+    tx_completed: u64,
+    tx_frames_exhausted: u64,
+    tx_ring_full: u64,
+}
+
+// Owns its rings; the UMEM it shares is Sync
+unsafe impl Send for XdpSocket {}
+
+impl XdpSocket {
+    /// Socket on `umem`. The first socket on a UMEM binds through the registering fd;
+    /// the rest use `XDP_SHARED_UMEM` and must each be on a different device/queue.
+    pub fn with_umem(iface: &str, queue_id: u32, umem: Arc<Umem>, config: &XskConfig) -> Result<Self, XskError> {
+        config.validate()?;
+        if config.frame_size != umem.frame_size {
+            return Err(XskError::Config(format!(
+                "frame size {} does not match the shared UMEM's {}", config.frame_size, umem.frame_size,
+            )));
+        }
+        let mode = config.bind_mode;
+        let rx_frames = config.rx_frame_count();
+        if rx_frames > umem.free_frames() {
+            return Err(XskError::Config(format!(
+                "{} RX frames requested but the UMEM only has {} free", rx_frames, umem.free_frames(),
+            )));
+        }
+
+        unsafe {
+            // 1. Socket: the UMEM's own fd if unclaimed, else a new one sharing it
+            let owner_rings = umem.owner_rings.lock().unwrap().take();
+            let shared = owner_rings.is_none();
+            let fd = if shared { socket(AF_XDP, SOCK_RAW, 0) } else { umem.fd };
+            if fd < 0 { return Err(io::Error::last_os_error().into()); }
+
+            // 2. Fill / completion rings (a shared socket needs its own per device/queue)
+            let (fill_ring, comp_ring) = match owner_rings {
+                Some(rings) => rings,
+                None => match map_umem_rings(fd, config) {
+                    Ok(rings) => rings,
+                    Err(err) => {
+                        close(fd);
+                        return Err(err.into());
+                    }
+                },
+            };
+
+            // 3. RX / TX rings (xdp_desc = 16 bytes)
+            let rings = (|| {
+                set_ring_size(fd, XDP_RX_RING, config.rx_ring_size)?;
+                set_ring_size(fd, XDP_TX_RING, config.tx_ring_size)?;
+                let off = mmap_offsets(fd)?;
+                let rx = XdpRing::map(fd, &off.rx, config.rx_ring_size, 16, XDP_PGOFF_RX_RING)?;
+                let tx = XdpRing::map(fd, &off.tx, config.tx_ring_size, 16, libc::XDP_PGOFF_TX_RING)?;
+                Ok::<_, io::Error>((rx, tx))
+            })();
+            let (rx_ring, tx_ring) = match rings {
+                Ok(rings) => rings,
+                Err(err) => {
+                    umem.abandon(fd, (fill_ring, comp_ring));
+                    return Err(err.into());
+                }
+            };
+
+            // 4. Bind
+            let Ok(if_name) = CString::new(iface) else {
+                umem.abandon(fd, (fill_ring, comp_ring));
+                return Err(io::Error::from(io::ErrorKind::InvalidInput).into());
+            };
             let mut sa: libc::sockaddr_xdp = mem::zeroed();
             sa.sxdp_family = AF_XDP as u16;
             sa.sxdp_ifindex = libc::if_nametoindex(if_name.as_ptr());
             sa.sxdp_queue_id = queue_id;
-            
+            // Auto leaves the choice to the kernel: zero-copy if the driver supports it, else copy
+            sa.sxdp_flags = XDP_USE_NEED_WAKEUP | match mode {
+                BindMode::ZeroCopy => XDP_ZEROCOPY,
+                BindMode::Copy => XDP_COPY,
+                BindMode::Auto => 0,
+            };
+            if shared {
+                // The kernel takes the mode from the UMEM owner; only the share flag is allowed
+                sa.sxdp_flags = XDP_SHARED_UMEM;
+                sa.sxdp_shared_umem_fd = umem.fd as u32;
+            }
+
             if libc::bind(fd, &sa as *const _ as *const _, mem::size_of::<libc::sockaddr_xdp>() as u32) != 0 {
-                sa.sxdp_flags = XDP_COPY;
-                libc::bind(fd, &sa as *const _ as *const _, mem::size_of::<libc::sockaddr_xdp>() as u32);
+                let source = io::Error::last_os_error();
+                umem.abandon(fd, (fill_ring, comp_ring));
+                return Err(XskError::Bind { iface: iface.to_string(), queue_id, mode, source });
+            }
+
+            // From here on Drop releases the rings and fd (and returns frames) on error
+            let mut socket = XdpSocket {
+                umem_ptr: umem.ptr, fd, umem, rx_ring, tx_ring, fill_ring, comp_ring,
+                rx_cache: vec![RxDesc::default(); RX_BATCH], rx_cache_pos: RX_BATCH,
+                rx_recycle: Vec::with_capacity(RX_BATCH),
+                tx_batch: Vec::with_capacity(config.tx_ring_size as usize),
+                zero_copy: false, tx_submitted: 0, tx_completed: 0, tx_frames_exhausted: 0, tx_ring_full: 0,
+            };
+
+            // 5. Init Fill; the driver waits for it (need_wakeup) if it started on an empty ring
+            let mut prod = (*socket.fill_ring.producer).load(Ordering::Acquire);
+            let desc_ptr = socket.fill_ring.desc as *mut u64;
+            for _ in 0..rx_frames {
+                let Some(addr) = socket.umem.alloc_frame() else { break };
+                *desc_ptr.add((prod & (socket.fill_ring.size - 1)) as usize) = addr;
+                prod = prod.wrapping_add(1);
             }
+            (*socket.fill_ring.producer).store(prod, Ordering::Release);
 
-            Ok(XdpSocket {
-                fd, umem_ptr, umem_size: UMEM_SIZE, rx_ring, tx_ring, fill_ring, comp_ring,
-                tx_free_frames, pending_tx_addr: None,
-            })
+            // 6. Read back what the kernel negotiated
+            let mut opts = XdpOptions::default();
+            let mut optlen = mem::size_of::<XdpOptions>() as u32;
+            if libc::getsockopt(fd, SOL_XDP, XDP_OPTIONS, &mut opts as *mut _ as *mut _, &mut optlen) != 0 {
+                return Err(io::Error::last_os_error().into());
+            }
+            socket.zero_copy = opts.flags & XDP_OPTIONS_ZEROCOPY != 0;
+
+            Ok(socket)
+        }
+    }
+
+    /// Whether the kernel bound this socket in zero-copy mode (from `XDP_OPTIONS`).
+    pub fn is_zero_copy(&self) -> bool {
+        self.zero_copy
+    }
+
+    /// Whether `TxFrame::submit_with_checksum` can be used (UMEM built with `tx_checksum_offload`).
+    pub fn has_tx_metadata(&self) -> bool {
+        self.umem.tx_metadata
+    }
+
+    /// Kernel drop counters (`XDP_STATISTICS`) and userspace ring/frame accounting.
+    pub fn stats(&self) -> Result<XskStats, io::Error> {
+        let mut ks = XdpStatistics::default();
+        let mut optlen = mem::size_of::<XdpStatistics>() as u32;
+        // Older kernels fill only the first three counters; the rest stay zero
+        if unsafe { libc::getsockopt(self.fd, SOL_XDP, XDP_STATISTICS, &mut ks as *mut _ as *mut _, &mut optlen) } != 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        Ok(XskStats {
+            rx_dropped: ks.rx_dropped,
+            rx_invalid_descs: ks.rx_invalid_descs,
+            tx_invalid_descs: ks.tx_invalid_descs,
+            rx_ring_full: ks.rx_ring_full,
+            rx_fill_ring_empty_descs: ks.rx_fill_ring_empty_descs,
+            tx_ring_empty_descs: ks.tx_ring_empty_descs,
+            umem_frames_in_flight: (self.umem.num_frames - self.umem.free_frames()) as u64,
+            tx_frames_exhausted: self.tx_frames_exhausted,
+            tx_ring_full: self.tx_ring_full,
+            completion_lag: self.tx_submitted - self.tx_completed,
+        })
+    }
+
+    /// Next received frame that `accept` maps to `Some`, along with that value; frames it
+    /// rejects are recycled unseen. The frame stays out of the kernel's reach until the
+    /// guard drops, then goes back to the fill ring (batched with the other released frames).
+    pub fn recv<T>(&mut self, mut accept: impl FnMut(&[u8]) -> Option<T>) -> Option<(RxFrame<'_>, T)> {
+        loop {
+            let desc = self.next_rx_desc()?;
+            let bytes = unsafe { std::slice::from_raw_parts(self.umem_ptr.add(desc.addr as usize), desc.len) };
+            if let Some(value) = accept(bytes) {
+                return Some((RxFrame { socket: self, desc }, value));
+            }
+            self.rx_recycle.push(desc);
+        }
+    }
+
+    fn next_rx_desc(&mut self) -> Option<RxDesc> {
+        if self.rx_cache_pos == self.rx_cache.len() {
+            let recycled = mem::take(&mut self.rx_recycle);
+            self.rx_release(&recycled);
+            self.rx_recycle = recycled;
+            self.rx_recycle.clear();
+
+            let mut cache = mem::take(&mut self.rx_cache);
+            let n = self.poll_rx_batch(&mut cache);
+            self.rx_cache = cache;
+            if n == 0 { return None; }
+            // Keep the cache full length; only the first n entries are fresh
+            self.rx_cache_pos = self.rx_cache.len() - n;
+            self.rx_cache.copy_within(..n, self.rx_cache_pos);
         }
+
+        let desc = self.rx_cache[self.rx_cache_pos];
+        self.rx_cache_pos += 1;
+        Some(desc)
     }
 
-    pub fn poll_rx(&mut self) -> Option<(u64, usize)> {
+    /// Consume up to `descs.len()` RX descriptors with a single consumer update.
+    /// Low-level: the frames belong to the caller until they are given back with
+    /// `rx_release`. Prefer `recv`.
+    pub fn poll_rx_batch(&mut self, descs: &mut [RxDesc]) -> usize {
+        // The driver went idle waiting for fill entries; poke it so RX resumes
+        if self.fill_ring.needs_wakeup() {
+            unsafe { libc::recvfrom(self.fd, ptr::null_mut(), 0, libc::MSG_DONTWAIT, ptr::null_mut(), ptr::null_mut()); }
+        }
+
         unsafe {
             let cons = (*self.rx_ring.consumer).load(Ordering::Relaxed);
             let prod = (*self.rx_ring.producer).load(Ordering::Acquire);
-            if cons == prod { return None; }
-            let idx = cons & (self.rx_ring.size - 1);
-            let desc = &*(self.rx_ring.desc as *const XdpDesc).add(idx as usize);
-            let addr = desc.addr;
-            let len = desc.len as usize;
-            (*self.rx_ring.consumer).store(cons + 1, Ordering::Release);
-            
-            // Return frame to fill ring for reuse
+            let n = prod.wrapping_sub(cons).min(descs.len() as u32);
+            if n == 0 { return 0; }
+
+            let ring = self.rx_ring.desc as *const XdpDesc;
+            for (i, out) in descs.iter_mut().take(n as usize).enumerate() {
+                let desc = &*ring.add((cons.wrapping_add(i as u32) & (self.rx_ring.size - 1)) as usize);
+                out.addr = desc.addr;
+                out.len = desc.len as usize;
+            }
+            (*self.rx_ring.consumer).store(cons.wrapping_add(n), Ordering::Release);
+
+            n as usize
+        }
+    }
+
+    /// Return received frames to the fill ring with a single producer update.
+    pub fn rx_release(&mut self, descs: &[RxDesc]) {
+        unsafe {
             let fill_prod = (*self.fill_ring.producer).load(Ordering::Relaxed);
-            let fill_idx = fill_prod & (self.fill_ring.size - 1);
             let fill_desc = self.fill_ring.desc as *mut u64;
-            *fill_desc.add(fill_idx as usize) = addr;
-            (*self.fill_ring.producer).store(fill_prod + 1, Ordering::Release);
-            
-            Some((addr, len))
+            for (i, d) in descs.iter().enumerate() {
+                let fill_idx = fill_prod.wrapping_add(i as u32) & (self.fill_ring.size - 1);
+                *fill_desc.add(fill_idx as usize) = d.addr;
+            }
+            (*self.fill_ring.producer).store(fill_prod.wrapping_add(descs.len() as u32), Ordering::Release);
         }
     }
 
-    pub fn get_tx_frame(&mut self) -> Option<&mut [u8]> {
+    /// Move completed TX frames back to the UMEM's free pool.
+    fn reclaim_completions(&mut self) {
         unsafe {
             let cons = (*self.comp_ring.consumer).load(Ordering::Relaxed);
             let prod = (*self.comp_ring.producer).load(Ordering::Acquire);
+            if prod == cons { return; }
+            let mut pool = self.umem.free_frames.lock().unwrap();
             let mut c = cons;
             while c != prod {
-                self.tx_free_frames.push(*(self.comp_ring.desc as *const u64).add((c & (self.comp_ring.size - 1)) as usize));
-                c += 1;
+                pool.push(self.umem.frame_start(*(self.comp_ring.desc as *const u64).add((c & (self.comp_ring.size - 1)) as usize)));
+                c = c.wrapping_add(1);
             }
-            if c != cons { (*self.comp_ring.consumer).store(c, Ordering::Release); }
+            drop(pool);
+            (*self.comp_ring.consumer).store(c, Ordering::Release);
+            self.tx_completed += c.wrapping_sub(cons) as u64;
+        }
+    }
 
+    /// Staged frames will take TX ring slots too.
+    fn tx_ring_is_full(&self) -> bool {
+        unsafe {
             let t_prod = (*self.tx_ring.producer).load(Ordering::Relaxed);
             let t_cons = (*self.tx_ring.consumer).load(Ordering::Acquire);
-            if t_prod - t_cons >= self.tx_ring.size { return None; }
+            t_prod.wrapping_sub(t_cons) + self.tx_batch.len() as u32 >= self.tx_ring.size
+        }
+    }
+
+    /// A free frame to build a packet in. Dropping the guard without `submit` puts
+    /// the frame back in the pool.
+    pub fn tx_frame(&mut self) -> Option<TxFrame<'_>> {
+        self.reclaim_completions();
+        if self.tx_ring_is_full() {
+            self.tx_ring_full += 1;
+            return None;
         }
 
-        if let Some(addr) = self.tx_free_frames.pop() {
-            self.pending_tx_addr = Some(addr);
-            let ptr = unsafe { self.umem_ptr.add(addr as usize) };
-            return Some(unsafe { std::slice::from_raw_parts_mut(ptr, FRAME_SIZE) });
+        let Some(addr) = self.umem.alloc_frame() else {
+            self.tx_frames_exhausted += 1;
+            return None;
+        };
+        Some(TxFrame { socket: self, addr })
+    }
+
+    /// Transmit a received frame without copying. Both sockets must share this UMEM.
+    /// The receiving socket's fill ring gets a replacement frame from the free pool.
+    /// Hands the frame back if the TX ring is full.
+    // For forwarding between queues or devices; the QUIC client terminates every datagram
+    #[allow(dead_code)]
+    pub fn tx_forward<'a>(&mut self, frame: RxFrame<'a>) -> Result<(), RxFrame<'a>> {
+        debug_assert!(Arc::ptr_eq(&self.umem, &frame.socket.umem), "tx_forward across different UMEMs");
+        self.reclaim_completions();
+        if self.tx_ring_is_full() { return Err(frame); }
+
+        let mut frame = mem::ManuallyDrop::new(frame);
+        self.tx_batch.push((frame.desc.addr, frame.desc.len as u32, 0));
+        if let Some(addr) = self.umem.alloc_frame() {
+            frame.socket.rx_recycle.push(RxDesc { addr, len: 0 });
         }
-        None
+        Ok(())
     }
 
-    pub fn tx_submit(&mut self, len: usize) {
-        if let Some(addr) = self.pending_tx_addr.take() {
-            unsafe {
-                let prod = (*self.tx_ring.producer).load(Ordering::Relaxed);
-                let d = (self.tx_ring.desc as *mut XdpDesc).add((prod & (self.tx_ring.size - 1)) as usize);
-                (*d).addr = addr; (*d).len = len as u32; (*d).options = 0;
-                (*self.tx_ring.producer).store(prod + 1, Ordering::Release);
-                libc::sendto(self.fd, ptr::null(), 0, libc::MSG_DONTWAIT, ptr::null(), 0);
+    /// Publish every staged frame with one producer update and one kick.
+    pub fn tx_submit_batch(&mut self) -> usize {
+        let n = self.tx_batch.len();
+
+        unsafe {
+            let prod = (*self.tx_ring.producer).load(Ordering::Relaxed);
+            // Nothing new, but descriptors from an earlier batch may still need a kick
+            if n == 0 && prod == (*self.tx_ring.consumer).load(Ordering::Acquire) { return 0; }
+
+            let ring = self.tx_ring.desc as *mut XdpDesc;
+            for (i, (addr, len, options)) in self.tx_batch.drain(..).enumerate() {
+                let d = ring.add((prod.wrapping_add(i as u32) & (self.tx_ring.size - 1)) as usize);
+                (*d).addr = addr; (*d).len = len; (*d).options = options;
             }
+            (*self.tx_ring.producer).store(prod.wrapping_add(n as u32), Ordering::Release);
+        }
+        self.tx_submitted += n as u64;
+
+        // With need_wakeup the kernel only wants a kick when it stopped draining TX
+        if self.tx_ring.needs_wakeup() {
+            unsafe { libc::sendto(self.fd, ptr::null(), 0, libc::MSG_DONTWAIT, ptr::null(), 0); }
         }
+        n
     }
 
-    pub fn cancel_tx(&mut self) {
-        if let Some(addr) = self.pending_tx_addr.take() {
-            self.tx_free_frames.push(addr);
+    /// Copy a complete frame (e.g. an ARP request) in and transmit it right away,
+    /// together with anything already staged.
+    pub fn send_raw(&mut self, bytes: &[u8]) -> io::Result<()> {
+        let mut frame = self.tx_frame().ok_or_else(|| io::Error::from(io::ErrorKind::WouldBlock))?;
+        if bytes.len() > frame.len() {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, format!("{} bytes don't fit a {}-byte frame", bytes.len(), frame.len())));
         }
+        frame[..bytes.len()].copy_from_slice(bytes);
+        frame.submit(0, bytes.len());
+        self.tx_submit_batch();
+        Ok(())
     }
 }
 
 impl Drop for XdpSocket {
     fn drop(&mut self) {
+        // Staged but never submitted frames go back to the pool
+        for (addr, _, _) in self.tx_batch.drain(..) {
+            self.umem.free_frame(addr);
+        }
+
+        // Ring buffers unmap themselves; the UMEM closes its own fd
+        if self.fd != self.umem.fd {
+            unsafe { close(self.fd); }
+        }
+    }
+}
+
+/// A received frame on loan from its socket; derefs to the packet bytes.
+pub struct RxFrame<'a> {
+    socket: &'a mut XdpSocket,
+    desc: RxDesc,
+}
+
+impl Deref for RxFrame<'_> {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        unsafe { std::slice::from_raw_parts(self.socket.umem_ptr.add(self.desc.addr as usize), self.desc.len) }
+    }
+}
+
+impl DerefMut for RxFrame<'_> {
+    fn deref_mut(&mut self) -> &mut [u8] {
+        unsafe { std::slice::from_raw_parts_mut(self.socket.umem_ptr.add(self.desc.addr as usize), self.desc.len) }
+    }
+}
+
+impl Drop for RxFrame<'_> {
+    fn drop(&mut self) {
+        self.socket.rx_recycle.push(self.desc);
+    }
+}
+
+/// A free UMEM frame being filled for TX; derefs to the whole frame buffer.
+pub struct TxFrame<'a> {
+    socket: &'a mut XdpSocket,
+    addr: u64,
+}
+
+impl TxFrame<'_> {
+    /// Queue `len` bytes starting `offset` into the frame for the next `tx_submit_batch`.
+    pub fn submit(self, offset: usize, len: usize) {
+        let mut frame = mem::ManuallyDrop::new(self);
+        let addr = frame.addr + offset as u64;
+        frame.socket.tx_batch.push((addr, len as u32, 0));
+    }
+
+    /// Like `submit`, asking the NIC to checksum from `csum_start` (relative to `offset`) to
+    /// the end and store the result `csum_offset` further on. The metadata goes in the
+    /// `TX_METADATA_LEN` bytes before `offset`. Needs a UMEM set up with `tx_checksum_offload`.
+    pub fn submit_with_checksum(self, offset: usize, len: usize, csum_start: u16, csum_offset: u16) {
+        debug_assert!(self.socket.umem.tx_metadata, "UMEM registered without TX metadata");
+        debug_assert!(offset >= TX_METADATA_LEN);
+        let mut frame = mem::ManuallyDrop::new(self);
+        let addr = frame.addr + offset as u64;
         unsafe {
-            // Unmap ring buffers
-            munmap(self.fill_ring.ptr, self.fill_ring.len);
-            munmap(self.comp_ring.ptr, self.comp_ring.len);
-            munmap(self.rx_ring.ptr, self.rx_ring.len);
-            munmap(self.tx_ring.ptr, self.tx_ring.len);
+            let meta = frame.socket.umem_ptr.add(addr as usize - TX_METADATA_LEN) as *mut XskTxMetadata;
+            ptr::write_unaligned(meta, XskTxMetadata { flags: XDP_TXMD_FLAGS_CHECKSUM, csum_start, csum_offset, _pad: 0 });
+        }
+        frame.socket.tx_batch.push((addr, len as u32, XDP_TX_METADATA));
+    }
+}
 
-            // Unmap UMEM buffer
-            munmap(self.umem_ptr as *mut libc::c_void, self.umem_size);
+impl Deref for TxFrame<'_> {
+    type Target = [u8];
 
-            // Close socket
-            close(self.fd);
+    fn deref(&self) -> &[u8] {
+        unsafe { std::slice::from_raw_parts(self.socket.umem_ptr.add(self.addr as usize), self.socket.umem.frame_size) }
+    }
+}
+
+impl DerefMut for TxFrame<'_> {
+    fn deref_mut(&mut self) -> &mut [u8] {
+        unsafe { std::slice::from_raw_parts_mut(self.socket.umem_ptr.add(self.addr as usize), self.socket.umem.frame_size) }
+    }
+}
+
+impl Drop for TxFrame<'_> {
+    fn drop(&mut self) {
+        self.socket.umem.free_frame(self.addr);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Anonymous memory laid out like a kernel ring: producer, consumer, flags, descriptors.
+    fn ring(size: u32, desc_size: usize) -> XdpRing {
+        let len = 64 + size as usize * desc_size;
+        unsafe {
+            let ptr = mmap(ptr::null_mut(), len, PROT_READ | PROT_WRITE, MAP_PRIVATE | MAP_ANONYMOUS, -1, 0);
+            assert_ne!(ptr, MAP_FAILED);
+            XdpRing {
+                producer: ptr as *mut AtomicU32,
+                consumer: ptr.add(4) as *mut AtomicU32,
+                flags: ptr.add(8) as *mut AtomicU32,
+                desc: ptr.add(64) as *mut u8,
+                size, ptr, len,
+            }
+        }
+    }
+
+    /// Registered nowhere; its fd is /dev/null, which takes no XDP socket options.
+    fn umem(frames: usize) -> Arc<Umem> {
+        let fd = std::os::fd::IntoRawFd::into_raw_fd(std::fs::File::open("/dev/null").unwrap());
+        let size = frames * FRAME_SIZE;
+        let ptr = unsafe { mmap(ptr::null_mut(), size, PROT_READ | PROT_WRITE, MAP_PRIVATE | MAP_ANONYMOUS, -1, 0) };
+        assert_ne!(ptr, MAP_FAILED);
+        Arc::new(Umem {
+            ptr: ptr as *mut u8, size, frame_size: FRAME_SIZE, num_frames: frames, tx_metadata: false, fd,
+            owner_rings: Mutex::new(None),
+            free_frames: Mutex::new((0..frames).rev().map(|i| (i * FRAME_SIZE) as u64).collect()),
+        })
+    }
+
+    /// A socket on `umem` whose rings no kernel ever sees; the test plays the kernel.
+    fn socket(umem: &Arc<Umem>) -> XdpSocket {
+        XdpSocket {
+            umem_ptr: umem.ptr, fd: -1, umem: Arc::clone(umem),
+            rx_ring: ring(8, 16), tx_ring: ring(8, 16), fill_ring: ring(8, 8), comp_ring: ring(8, 8),
+            rx_cache: vec![RxDesc::default(); RX_BATCH], rx_cache_pos: RX_BATCH,
+            rx_recycle: Vec::with_capacity(RX_BATCH), tx_batch: Vec::new(),
+            zero_copy: false, tx_submitted: 0, tx_completed: 0, tx_frames_exhausted: 0, tx_ring_full: 0,
         }
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn forwards_a_received_frame_without_copying() {
+        let umem = umem(4);
+        let (mut rx, mut tx) = (socket(&umem), socket(&umem));
+        let addr = umem.alloc_frame().unwrap();
+        unsafe {
+            ptr::write_bytes(umem.ptr.add(addr as usize), 0xAB, 60);
+            *(rx.rx_ring.desc as *mut XdpDesc) = XdpDesc { addr, len: 60, options: 0 };
+            (*rx.rx_ring.producer).store(1, Ordering::Release);
+        }
+        let free = umem.free_frames();
+
+        let (frame, ()) = rx.recv(|bytes| (bytes == [0xAB; 60]).then_some(())).unwrap();
+        assert!(tx.tx_forward(frame).is_ok());
+        assert_eq!(tx.tx_submit_batch(), 1);
+        let sent = unsafe { &*(tx.tx_ring.desc as *const XdpDesc) };
+        assert_eq!((sent.addr, sent.len), (addr, 60), "not the received frame");
+
+        // The receiving side refills from the pool instead of getting the frame back
+        assert_eq!(umem.free_frames(), free - 1);
+        assert_eq!(rx.rx_recycle.len(), 1);
+        assert_ne!(rx.rx_recycle[0].addr, addr);
+    }
+
+    #[test]
+    fn raw_frames_larger_than_a_frame_are_refused() {
+        let umem = umem(2);
+        let mut socket = socket(&umem);
+        let err = socket.send_raw(&vec![0; FRAME_SIZE + 1]).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+        assert_eq!(umem.free_frames(), 2, "frame not returned");
+
+        socket.send_raw(&[0xFF; 42]).unwrap();
+        let sent = unsafe { &*(socket.tx_ring.desc as *const XdpDesc) };
+        assert_eq!(sent.len, 42);
+    }
+
+    #[test]
+    fn failed_owner_setup_leaves_the_rings_for_the_next_socket() {
+        let umem = umem(4);
+        *umem.owner_rings.lock().unwrap() = Some((ring(8, 8), ring(8, 8)));
+
+        // Ring setup on the registering fd fails right after the rings are taken
+        let config = XskConfig::default().rx_frames(2);
+        assert!(XdpSocket::with_umem("lo", 0, Arc::clone(&umem), &config).is_err());
+        assert!(umem.owner_rings.lock().unwrap().is_some(), "owner rings lost");
+        assert_eq!(umem.free_frames(), 4);
+    }
+}
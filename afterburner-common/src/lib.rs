@@ -1 +1,112 @@
 #![no_std]
+
+use core::net::{IpAddr, Ipv6Addr, SocketAddr};
+
+/// Capacity of the `XSK` map. The XDP program indexes it by `rx_queue_index`,
+/// so this bounds the number of RX queues userspace can attach sockets to.
+pub const XSK_MAX_QUEUES: u32 = 64;
+
+/// Capacity of the `REDIRECT_RULES` map.
+pub const REDIRECT_MAX_RULES: u32 = 64;
+
+// First three words of an IPv4-mapped IPv6 address (::ffff:0:0/96), network byte order
+const V4_MAPPED_PREFIX: [u32; 3] = [0, 0, u32::from_ne_bytes([0, 0, 0xff, 0xff])];
+
+/// An IPv4 address as read from the IPv4 header, widened to the IPv4-mapped IPv6 words
+/// the map keys and rules use.
+#[inline(always)]
+pub const fn v4_mapped(addr: u32) -> [u32; 4] {
+    let [a, b, c] = V4_MAPPED_PREFIX;
+    [a, b, c, addr]
+}
+
+/// Key of `REDIRECT_RULES`: a UDP destination whose packets the XDP program steers to
+/// AF_XDP. Addresses are IPv6, with IPv4 stored IPv4-mapped (`::ffff:a.b.c.d`); all fields
+/// are in network byte order, exactly as they sit in the packet.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct RedirectKey {
+    pub addr: [u32; 4],
+    pub port: u16,
+    _pad: u16,
+}
+
+impl RedirectKey {
+    pub const fn new(addr: [u32; 4], port: u16) -> Self {
+        RedirectKey { addr, port, _pad: 0 }
+    }
+
+    /// Key for an IPv4 destination as read from the IPv4 header.
+    pub const fn v4(addr: u32, port: u16) -> Self {
+        RedirectKey::new(v4_mapped(addr), port)
+    }
+
+    /// The same port on any local address of the same family; the unspecified
+    /// address (`0.0.0.0` or `::`) in the map is the wildcard.
+    pub const fn any_addr(self) -> Self {
+        let a = self.addr;
+        let is_v4 = a[0] == V4_MAPPED_PREFIX[0] && a[1] == V4_MAPPED_PREFIX[1] && a[2] == V4_MAPPED_PREFIX[2];
+        if is_v4 { RedirectKey::v4(0, self.port) } else { RedirectKey::new([0; 4], self.port) }
+    }
+}
+
+impl From<SocketAddr> for RedirectKey {
+    fn from(addr: SocketAddr) -> Self {
+        RedirectKey::new(words(&to_v6(addr.ip())), addr.port().to_be())
+    }
+}
+
+/// Value of `REDIRECT_RULES`: which sources may use the redirect. Same address layout and
+/// byte order as the key; a zero mask accepts any source.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct RedirectRule {
+    pub src_addr: [u32; 4],
+    pub src_mask: [u32; 4],
+}
+
+impl RedirectRule {
+    pub const ANY_SOURCE: RedirectRule = RedirectRule { src_addr: [0; 4], src_mask: [0; 4] };
+
+    /// Only sources inside `addr/prefix_len`; an IPv4 prefix length counts IPv4 bits.
+    pub fn from_prefix(addr: IpAddr, prefix_len: u8) -> Self {
+        let bits = match addr {
+            IpAddr::V4(_) => 96 + prefix_len.min(32) as u32,
+            IpAddr::V6(_) => prefix_len.min(128) as u32,
+        };
+        let mask = (u128::MAX.checked_shl(128 - bits).unwrap_or(0)).to_be_bytes();
+        let mask = words(&Ipv6Addr::from(mask));
+        let addr = words(&to_v6(addr));
+        RedirectRule {
+            src_addr: [addr[0] & mask[0], addr[1] & mask[1], addr[2] & mask[2], addr[3] & mask[3]],
+            src_mask: mask,
+        }
+    }
+
+    #[inline(always)]
+    pub fn matches(&self, src_addr: &[u32; 4]) -> bool {
+        (src_addr[0] & self.src_mask[0]) == self.src_addr[0]
+            && (src_addr[1] & self.src_mask[1]) == self.src_addr[1]
+            && (src_addr[2] & self.src_mask[2]) == self.src_addr[2]
+            && (src_addr[3] & self.src_mask[3]) == self.src_addr[3]
+    }
+}
+
+fn to_v6(addr: IpAddr) -> Ipv6Addr {
+    match addr {
+        IpAddr::V4(addr) => addr.to_ipv6_mapped(),
+        IpAddr::V6(addr) => addr,
+    }
+}
+
+// Network-order words, as the XDP program loads them from the header
+fn words(addr: &Ipv6Addr) -> [u32; 4] {
+    let o = addr.octets();
+    core::array::from_fn(|i| u32::from_ne_bytes([o[4 * i], o[4 * i + 1], o[4 * i + 2], o[4 * i + 3]]))
+}
+
+#[cfg(feature = "user")]
+unsafe impl aya::Pod for RedirectKey {}
+
+#[cfg(feature = "user")]
+unsafe impl aya::Pod for RedirectRule {}
@@ -4,18 +4,23 @@
 use aya_ebpf::{
     bindings::xdp_action,
     macros::{map, xdp},
-    maps::XskMap,
+    maps::{HashMap, XskMap},
     programs::XdpContext,
 };
+use afterburner_common::{v4_mapped, RedirectKey, RedirectRule, REDIRECT_MAX_RULES, XSK_MAX_QUEUES};
 use core::mem;
 use network_types::{
     eth::{EthHdr, EtherType},
-    ip::{IpProto, Ipv4Hdr},
+    ip::{IpProto, Ipv4Hdr, Ipv6Hdr},
     udp::UdpHdr,
 };
 
 #[map]
-static XSK: XskMap = XskMap::with_max_entries(4, 0);
+static XSK: XskMap = XskMap::with_max_entries(XSK_MAX_QUEUES, 0);
+
+// Destinations to bypass, filled by userspace from `--listen`
+#[map]
+static REDIRECT_RULES: HashMap<RedirectKey, RedirectRule> = HashMap::with_max_entries(REDIRECT_MAX_RULES, 0);
 
 #[xdp]
 pub fn afterburner(ctx: XdpContext) -> u32 {
@@ -29,24 +34,50 @@ pub fn afterburner(ctx: XdpContext) -> u32 {
 fn try_afterburner(ctx: XdpContext) -> Result<u32, ()> {
     let eth = ptr_at::<EthHdr>(&ctx, 0).ok_or(())?;
 
-    match eth.ether_type {
-        EtherType::Ipv4 => {}
+    // Destination key and source address; IPv4 is looked up IPv4-mapped
+    let (key, src) = match eth.ether_type {
+        EtherType::Ipv4 => {
+            let ip = ptr_at::<Ipv4Hdr>(&ctx, EthHdr::LEN).ok_or(())?;
+            if ip.proto != IpProto::Udp {
+                return Ok(xdp_action::XDP_PASS);
+            }
+            // More-fragments flag or a non-zero fragment offset: only the first fragment carries
+            // the UDP header, and userspace drops fragments anyway, so the kernel reassembles them
+            if u16::from_be(ip.frag_off) & 0x3FFF != 0 {
+                return Ok(xdp_action::XDP_PASS);
+            }
+            // IHL (low nibble of the first byte) counts 32-bit words, options included; masked
+            // so the verifier sees a bounded offset, which `ptr_at` checks against `data_end`
+            let ihl = (unsafe { *(ip as *const Ipv4Hdr as *const u8) } & 0x0F) as usize * 4;
+            if ihl < Ipv4Hdr::LEN {
+                return Ok(xdp_action::XDP_PASS);
+            }
+            let udp = ptr_at::<UdpHdr>(&ctx, EthHdr::LEN + ihl).ok_or(())?;
+            (RedirectKey::v4(ip.dst_addr, udp.dest), v4_mapped(ip.src_addr))
+        }
+        EtherType::Ipv6 => {
+            let ip = ptr_at::<Ipv6Hdr>(&ctx, EthHdr::LEN).ok_or(())?;
+            // Extension headers are not walked; QUIC traffic doesn't carry them
+            if ip.next_hdr != IpProto::Udp {
+                return Ok(xdp_action::XDP_PASS);
+            }
+            let udp = ptr_at::<UdpHdr>(&ctx, EthHdr::LEN + Ipv6Hdr::LEN).ok_or(())?;
+            let (dst, src) = unsafe { (ip.dst_addr.in6_u.u6_addr32, ip.src_addr.in6_u.u6_addr32) };
+            (RedirectKey::new(dst, udp.dest), src)
+        }
         _ => return Ok(xdp_action::XDP_PASS),
-    }
+    };
 
-    let ip = ptr_at::<Ipv4Hdr>(&ctx, EthHdr::LEN).ok_or(())?;
-    if ip.proto != IpProto::Udp {
-        return Ok(xdp_action::XDP_PASS);
+    // Exact destination first, then the same port on any address
+    let rule = unsafe { REDIRECT_RULES.get(&key).or_else(|| REDIRECT_RULES.get(&key.any_addr())) };
+    match rule {
+        Some(rule) if rule.matches(&src) => {
+            // One XSK per RX queue: queues without a socket fall back to the kernel
+            let queue = unsafe { (*ctx.ctx).rx_queue_index };
+            Ok(XSK.redirect(queue, 0).unwrap_or(xdp_action::XDP_PASS))
+        }
+        _ => Ok(xdp_action::XDP_PASS),
     }
-
-    let udp = ptr_at::<UdpHdr>(&ctx, EthHdr::LEN + Ipv4Hdr::LEN).ok_or(())?;
-
-    if u16::from_be(udp.dest) == 8000 {
-        return Ok(XSK.redirect(0, 0).unwrap_or(xdp_action::XDP_PASS));
-        // return Ok(xdp_action::XDP_PASS);
-    }
-
-    Ok(xdp_action::XDP_PASS)
 }
 
 #[inline(always)]